@@ -14,19 +14,23 @@ use tokio::{
 
 use super::Freq;
 use crate::{
-    utils::{print_err, qy_robot::QyRobot},
+    utils::{
+        chart::{print_chart, ChartKind, Series},
+        print_err,
+        qy_robot::QyRobot,
+    },
     Client, NOT_FOUND_MSG,
 };
-use colored::Colorize;
+use colored::{Color, Colorize};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ReqCountResponse {
     pub code: i32,
     pub error: String,
     pub data: Option<ReqCountData>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ReqCountData {
     pub points: Vec<String>,
     #[serde(rename = "reqCount")]
@@ -54,6 +58,11 @@ impl Client {
         end_date: &str,
         domains: Vec<String>,
     ) -> Result<ReqCountResponse, anyhow::Error> {
+        if self.offline() {
+            return self
+                .store()?
+                .req_count(freq, region, start_date, end_date, &domains);
+        }
         let url = format!("https://{}{}", self.host, "/v2/tune/loganalyze/reqcount");
         let freq = freq.to_string();
         let data = ReqCountParam {
@@ -75,6 +84,24 @@ impl Client {
         Ok(response)
     }
 
+    /// 请求次数图表
+    pub fn chart_count(&self, response: &ReqCountResponse, kind: ChartKind) {
+        let data = match response.data.as_ref() {
+            Some(data) if response.code == 200 => data,
+            _ => return,
+        };
+        print_chart(
+            &data.points,
+            &[Series::new(
+                "Count",
+                data.req_count.iter().map(|x| *x as f64).collect(),
+                Color::Green,
+            )],
+            kind,
+            "",
+        );
+    }
+
     pub async fn print_count(
         &self,
         response: ReqCountResponse,
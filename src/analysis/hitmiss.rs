@@ -1,24 +1,35 @@
 //! 查询命中率
 
-use std::ops::Div;
+use std::{ops::Div, path::PathBuf};
 
-use anyhow::Ok;
-use chrono::Local;
-use colored::Colorize;
+use anyhow::{anyhow, Ok};
+use chrono::{Duration, Local, NaiveDate};
+use colored::{Color, Colorize};
 use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{self, File},
+    io::AsyncWriteExt,
+};
 
-use crate::{utils::print_err, Client, NOT_FOUND_MSG};
+use crate::{
+    utils::{
+        chart::{print_chart, ChartKind, Series},
+        print_err,
+        qy_robot::QyRobot,
+    },
+    Client, NOT_FOUND_MSG,
+};
 
 use super::Freq;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct HitMissResponse {
     pub code: i32,
     pub error: String,
     pub data: Option<HitMissData>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct HitMissData {
     pub points: Vec<String>,
     pub hit: Vec<i64>,
@@ -29,6 +40,118 @@ pub struct HitMissData {
     pub traffic_miss: Vec<i64>,
 }
 
+/// 命中次数及流量汇总
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HitRatio {
+    pub hit: i64,
+    pub miss: i64,
+    pub traffic_hit: i64,
+    pub traffic_miss: i64,
+}
+
+impl HitRatio {
+    /// 请求命中率(%)，没有请求时返回None
+    pub fn request_ratio(&self) -> Option<f64> {
+        if self.hit + self.miss <= 0 {
+            return None;
+        }
+        Some(self.hit as f64 / (self.hit + self.miss) as f64 * 100f64)
+    }
+
+    /// 流量命中率(%)，没有流量时返回None
+    pub fn traffic_ratio(&self) -> Option<f64> {
+        if self.traffic_hit + self.traffic_miss <= 0 {
+            return None;
+        }
+        Some(self.traffic_hit as f64 / (self.traffic_hit + self.traffic_miss) as f64 * 100f64)
+    }
+
+    pub fn requests(&self) -> i64 {
+        self.hit + self.miss
+    }
+
+    fn add(&mut self, other: &HitRatio) {
+        self.hit += other.hit;
+        self.miss += other.miss;
+        self.traffic_hit += other.traffic_hit;
+        self.traffic_miss += other.traffic_miss;
+    }
+}
+
+impl HitMissData {
+    /// 第i个时间点的命中数据
+    pub fn ratio_at(&self, i: usize) -> HitRatio {
+        HitRatio {
+            hit: *self.hit.get(i).unwrap_or(&0),
+            miss: *self.miss.get(i).unwrap_or(&0),
+            traffic_hit: *self.traffic_hit.get(i).unwrap_or(&0),
+            traffic_miss: *self.traffic_miss.get(i).unwrap_or(&0),
+        }
+    }
+
+    /// 区间内所有时间点的汇总
+    pub fn total(&self, start: usize, end: usize) -> HitRatio {
+        let mut total = HitRatio::default();
+        for i in start..end.min(self.points.len()) {
+            total.add(&self.ratio_at(i));
+        }
+        total
+    }
+}
+
+/// 检查命中率是否触发告警，返回告警原因
+pub fn hitmiss_alert_reasons(
+    current: &HitRatio,
+    baseline: Option<&HitRatio>,
+    request_floor: Option<f64>,
+    traffic_floor: Option<f64>,
+    baseline_drop: Option<f64>,
+) -> Vec<String> {
+    let mut reasons = vec![];
+    let request_ratio = current.request_ratio();
+    let traffic_ratio = current.traffic_ratio();
+    if let (Some(ratio), Some(floor)) = (request_ratio, request_floor) {
+        if ratio < floor {
+            reasons.push(format!("请求命中率`{:.2}%`低于下限`{:.2}%`", ratio, floor));
+        }
+    }
+    if let (Some(ratio), Some(floor)) = (traffic_ratio, traffic_floor) {
+        if ratio < floor {
+            reasons.push(format!("流量命中率`{:.2}%`低于下限`{:.2}%`", ratio, floor));
+        }
+    }
+    if let (Some(baseline), Some(drop)) = (baseline, baseline_drop) {
+        if let (Some(ratio), Some(base)) = (request_ratio, baseline.request_ratio()) {
+            if base - ratio > drop {
+                reasons.push(format!(
+                    "请求命中率`{:.2}%`比近7天基线`{:.2}%`下降`{:.2}`个百分点",
+                    ratio,
+                    base,
+                    base - ratio
+                ));
+            }
+        }
+        if let (Some(ratio), Some(base)) = (traffic_ratio, baseline.traffic_ratio()) {
+            if base - ratio > drop {
+                reasons.push(format!(
+                    "流量命中率`{:.2}%`比近7天基线`{:.2}%`下降`{:.2}`个百分点",
+                    ratio,
+                    base,
+                    base - ratio
+                ));
+            }
+        }
+    }
+    reasons
+}
+
+fn format_ratio(ratio: Option<f64>) -> String {
+    match ratio {
+        Some(r) => format!("{:.2}%", r),
+        None => "-".to_string(),
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct HitMissParam<'a> {
     pub domains: Vec<String>,
@@ -48,6 +171,9 @@ impl Client {
         end_date: &str,
         domains: Vec<String>,
     ) -> Result<HitMissResponse, anyhow::Error> {
+        if self.offline() {
+            return self.store()?.hit_miss(freq, start_date, end_date, &domains);
+        }
         let url = format!("https://{}{}", self.host, "/v2/tune/loganalyze/hitmiss");
         let freq = freq.to_string();
         let data = HitMissParam {
@@ -68,6 +194,58 @@ impl Client {
         Ok(response)
     }
 
+    /// 命中率图表, 命中和未命中请求次数堆叠显示
+    pub fn chart_hitmiss(
+        &self,
+        response: HitMissResponse,
+        kind: ChartKind,
+        start_date: &str,
+        end_date: &str,
+        domains: Vec<String>,
+    ) {
+        if response.code != 200 {
+            let msg = format!("code: {}, message: {}", response.code, response.error);
+            print_err(msg.as_str(), true);
+        }
+        if response.data.is_none() {
+            print_err(NOT_FOUND_MSG, true);
+        }
+        let data = response.data.unwrap();
+        println!(
+            "域名 {} {}～{}命中率如下: ",
+            domains.join(",").yellow().bold(),
+            start_date.bold(),
+            end_date.bold(),
+        );
+        print_chart(
+            &data.points,
+            &[
+                Series::new(
+                    "Hit",
+                    data.hit.iter().map(|x| *x as f64).collect(),
+                    Color::Green,
+                ),
+                Series::new(
+                    "Miss",
+                    data.miss.iter().map(|x| *x as f64).collect(),
+                    Color::Red,
+                ),
+            ],
+            kind,
+            "",
+        );
+        let total = data.total(0, data.points.len());
+        if let Some(ratio) = total.request_ratio() {
+            println!(
+                "{}{}  {}{}",
+                "请求命中率: ".yellow().bold(),
+                format!("{:.2}%", ratio).bold(),
+                "流量命中率: ".yellow().bold(),
+                format!("{:.2}%", total.traffic_ratio().unwrap_or(0.0)).bold(),
+            );
+        }
+    }
+
     pub fn print_hitmiss(
         &self,
         response: HitMissResponse,
@@ -165,6 +343,225 @@ impl Client {
     }
 }
 
+impl Client {
+    /// 查询`day`之前7天(不含`day`)的命中率基线
+    pub async fn hitmiss_baseline(
+        &self,
+        day: &str,
+        domain: &str,
+    ) -> Result<HitRatio, anyhow::Error> {
+        let day_dt = NaiveDate::parse_from_str(day, "%Y-%m-%d")?;
+        let start_date = (day_dt - Duration::days(7)).format("%Y-%m-%d").to_string();
+        let end_date = (day_dt - Duration::days(1)).format("%Y-%m-%d").to_string();
+        let response = self
+            .hit_miss(
+                Freq::OneDay,
+                &start_date,
+                &end_date,
+                vec![domain.to_string()],
+            )
+            .await?;
+        if response.code != 200 {
+            return Err(anyhow!(
+                "code: {}, message: {}",
+                response.code,
+                response.error
+            ));
+        }
+        match response.data {
+            Some(data) => Ok(data.total(0, data.points.len())),
+            None => Ok(HitRatio::default()),
+        }
+    }
+
+    /// 5分钟命中率告警，检查最近5个有请求的时间点
+    pub async fn check_hitmiss(
+        &self,
+        start_date: &str,
+        end_date: &str,
+        domain: &str,
+    ) -> Result<(), anyhow::Error> {
+        let hitmiss_config = match self.config.monitor.hitmiss.clone() {
+            Some(k) => k,
+            None => return Ok(()),
+        };
+        if self.config.monitor.qy_robot.is_none() {
+            return Ok(());
+        }
+        let response = self
+            .hit_miss(
+                Freq::FiveMin,
+                start_date,
+                end_date,
+                vec![domain.to_string()],
+            )
+            .await?;
+        if response.code != 200 {
+            return Err(anyhow!(
+                "code: {}, message: {}",
+                response.code,
+                response.error
+            ));
+        }
+        let data = match response.data {
+            Some(k) => k,
+            None => return Ok(()),
+        };
+        let min_requests = hitmiss_config.min_requests.unwrap_or(100);
+        let rows: Vec<(&String, HitRatio)> = data
+            .points
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (t, data.ratio_at(i)))
+            .filter(|(_, r)| r.requests() > 0)
+            .collect();
+        let mut skip = 0;
+        if rows.len() > 5 {
+            skip = rows.len() - 5;
+        }
+        let baseline = if hitmiss_config.baseline_drop.is_some() {
+            Some(self.hitmiss_baseline(end_date, domain).await?)
+        } else {
+            None
+        };
+        if self.config.debug.unwrap_or(false) {
+            println!("[DEBUG] hitmiss baseline of {}: {:?}", domain, baseline);
+        }
+        for (t, ratio) in rows.iter().skip(skip) {
+            if ratio.requests() < min_requests {
+                continue;
+            }
+            let reasons = hitmiss_alert_reasons(
+                ratio,
+                baseline.as_ref(),
+                hitmiss_config.request_floor,
+                hitmiss_config.traffic_floor,
+                hitmiss_config.baseline_drop,
+            );
+            if reasons.is_empty() {
+                continue;
+            }
+            let send_mark_file_path = PathBuf::from(
+                format!("/tmp/qiniu/monitor/hitmiss/{}{}", domain, t).replace(" ", ""),
+            );
+            if send_mark_file_path.exists() {
+                continue;
+            }
+            let msg = format!(
+                "## 🚨七牛CDN命中率告警\n\n域名`{}`在`{}` 5分钟内:\n\n- {}\n\n> 命中率骤降通常是缓存头配置异常或刚刚进行了大量刷新，请留意！",
+                domain,
+                t,
+                reasons.join("\n- "),
+            );
+            if QyRobot::new(self.config.monitor.qy_robot.clone().unwrap())
+                .send_message(&msg)
+                .await
+                .is_err()
+            {
+                print_err(format!("消息发送失败: {}", msg).as_str(), false);
+            } else {
+                let send_mark_dir = PathBuf::from("/tmp/qiniu/monitor/hitmiss");
+                if !send_mark_dir.exists() {
+                    fs::create_dir_all(send_mark_dir).await?;
+                }
+                File::create(send_mark_file_path).await?.write_i8(1).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 按域名输出`day`当天的命中率日报，并和近7天基线对比
+    pub async fn hitmiss_report(
+        &self,
+        day: &str,
+        domains: Vec<String>,
+        no_qy_notify: bool,
+    ) -> Result<(), anyhow::Error> {
+        let day_dt = NaiveDate::parse_from_str(day, "%Y-%m-%d")?;
+        let start_date = (day_dt - Duration::days(7)).format("%Y-%m-%d").to_string();
+        let mut rows = vec![];
+        for d in domains.iter() {
+            if self.config.debug.unwrap_or(false) {
+                println!("[DEBUG] hitmiss report domain: {}", d);
+            }
+            let response = self
+                .hit_miss(Freq::OneDay, &start_date, day, vec![d.clone()])
+                .await?;
+            if response.code != 200 {
+                print_err(
+                    format!("{} code: {}, message: {}", d, response.code, response.error).as_str(),
+                    false,
+                );
+                continue;
+            }
+            let data = match response.data {
+                Some(k) => k,
+                None => continue,
+            };
+            // 最后一个点是当天，之前的是基线
+            let len = data.points.len();
+            if len == 0 {
+                continue;
+            }
+            let current = data.ratio_at(len - 1);
+            let baseline = data.total(0, len - 1);
+            rows.push((d.clone(), current, baseline));
+        }
+        if rows.is_empty() {
+            print_err(NOT_FOUND_MSG, false);
+            return Ok(());
+        }
+        let width = rows.iter().map(|x| x.0.len()).max().unwrap_or(0).max(6);
+        println!("{} 命中率日报: ", day.bold());
+        println!(
+            "{:^width$} {:^16} {:^16} {:^16} {:^16}",
+            "Domain".bold().green(),
+            "HitRate".bold().green(),
+            "TrafficHitRate".bold().green(),
+            "7dHitRate".bold().green(),
+            "7dTrafficHitRate".bold().green(),
+            width = width,
+        );
+        let mut lines = vec![];
+        for (d, current, baseline) in rows.iter() {
+            let diff = match (current.request_ratio(), baseline.request_ratio()) {
+                (Some(c), Some(b)) => format!("{:+.2}", c - b),
+                _ => "-".to_string(),
+            };
+            println!(
+                "{:<width$} {:^16} {:^16} {:^16} {:^16}",
+                d,
+                format_ratio(current.request_ratio()),
+                format_ratio(current.traffic_ratio()),
+                format_ratio(baseline.request_ratio()),
+                format_ratio(baseline.traffic_ratio()),
+                width = width,
+            );
+            lines.push(format!(
+                "`{}` 请求命中率`{}`({}) 流量命中率`{}`，7天基线`{}`/`{}`",
+                d,
+                format_ratio(current.request_ratio()),
+                diff,
+                format_ratio(current.traffic_ratio()),
+                format_ratio(baseline.request_ratio()),
+                format_ratio(baseline.traffic_ratio()),
+            ));
+        }
+        if no_qy_notify || self.config.monitor.qy_robot.is_none() {
+            return Ok(());
+        }
+        let msg = format!(
+            "## 📊七牛CDN命中率日报\n\n`{}`\n\n- {}",
+            day,
+            lines.join("\n- ")
+        );
+        QyRobot::new(self.config.monitor.qy_robot.clone().unwrap())
+            .send_message(&msg)
+            .await?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
@@ -188,4 +585,35 @@ mod tests {
             .unwrap();
         println!("{:#?}", response)
     }
+
+    #[test]
+    fn hitmiss_alert_reasons_test() {
+        let current = HitRatio {
+            hit: 60,
+            miss: 40,
+            traffic_hit: 900,
+            traffic_miss: 100,
+        };
+        let baseline = HitRatio {
+            hit: 950,
+            miss: 50,
+            traffic_hit: 950,
+            traffic_miss: 50,
+        };
+        let reasons = hitmiss_alert_reasons(&current, None, Some(80.0), Some(85.0), None);
+        assert_eq!(reasons.len(), 1);
+        assert!(reasons[0].contains("请求命中率"));
+        let reasons = hitmiss_alert_reasons(&current, Some(&baseline), None, None, Some(10.0));
+        assert_eq!(reasons.len(), 1);
+        assert!(reasons[0].contains("基线"));
+        assert!(hitmiss_alert_reasons(
+            &baseline,
+            Some(&baseline),
+            Some(90.0),
+            Some(90.0),
+            Some(1.0)
+        )
+        .is_empty());
+        assert!(HitRatio::default().request_ratio().is_none());
+    }
 }
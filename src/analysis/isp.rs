@@ -3,14 +3,16 @@
 
 use std::{collections::HashMap, ops::Div};
 
+use anyhow::anyhow;
 use chrono::Local;
-use colored::Colorize;
+use colored::{Color, Colorize};
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 use unicode_width::UnicodeWidthStr;
 
 use crate::{
     utils::{
+        chart::{print_chart, ChartKind, Series},
         print_err,
         region_isp::{
             get_isp_name_from_code, get_region_name_from_code, ISP_CODES, REGION_CODE_LIST,
@@ -100,6 +102,11 @@ impl Client {
         end_date: &str,
         domains: Vec<String>,
     ) -> Result<ISPTrafficResponse, anyhow::Error> {
+        if self.offline() {
+            return self
+                .store()?
+                .isp_traffic(freq, regions, isp, start_date, end_date, &domains);
+        }
         let url = format!("https://{}{}", self.host, "/v2/tune/loganalyze/traffic");
         let freq = freq.to_string();
         let data = ISPTrafficParam {
@@ -188,6 +195,54 @@ impl Client {
         }
     }
 
+    /// 运营商流量图表
+    pub fn chart_isp_traffic(
+        &self,
+        response: ISPTrafficResponse,
+        kind: ChartKind,
+        isp: &str,
+        regions: &str,
+        start_date: &str,
+        end_date: &str,
+        domains: Vec<String>,
+    ) {
+        if response.code != 200 {
+            let msg = format!("code: {}, message: {}", response.code, response.error);
+            print_err(msg.as_str(), true);
+        }
+        let (points, values) = match response.data {
+            Some(ISPTrafficData {
+                points: Some(points),
+                value,
+            }) => (points, value.unwrap_or_default()),
+            _ => {
+                print_err(NOT_FOUND_MSG, true);
+                return;
+            }
+        };
+        println!(
+            "域名 {} 运营商 {} 区域 {} {}～{}流量如下: ",
+            domains.join(",").yellow().bold(),
+            isp.bold().yellow(),
+            regions.bold().yellow(),
+            start_date.bold(),
+            end_date.bold(),
+        );
+        print_chart(
+            &points,
+            &[Series::new(
+                "Traffic",
+                values
+                    .iter()
+                    .map(|v| (*v as f64).div(1024.0).div(1024.0))
+                    .collect(),
+                Color::Green,
+            )],
+            kind,
+            "MB",
+        );
+    }
+
     /// ### [查询 ISP 请求次数](https://developer.qiniu.com/fusion/4081/cdn-log-analysis#9)
     pub async fn isp_count(
         &self,
@@ -197,6 +252,9 @@ impl Client {
         end_date: &str,
         domains: Vec<String>,
     ) -> Result<ISPCountResponse, anyhow::Error> {
+        if self.offline() {
+            return Err(anyhow!("离线模式不支持查询运营商请求次数, 请去掉--offline"));
+        }
         let url = format!("https://{}{}", self.host, "/v2/tune/loganalyze/ispreqcount");
         let freq = freq.to_string();
         let data = ISPCountParam {
@@ -325,6 +383,9 @@ impl Client {
         end_date: &str,
         domains: Vec<String>,
     ) -> Result<ISPTrafficRatioResponse, anyhow::Error> {
+        if self.offline() {
+            return Err(anyhow!("离线模式不支持查询运营商流量占比, 请去掉--offline"));
+        }
         let url = format!("https://{}{}", self.host, "/v2/tune/loganalyze/isptraffic");
         let data = ISPTrafficRatioParam {
             domains,
@@ -10,14 +10,14 @@ use serde::{Deserialize, Serialize};
 use super::Freq;
 use crate::{utils::print_err, Client, NOT_FOUND_MSG};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct StatusResponse {
     pub code: i32,
     pub error: String,
     pub data: Option<StatusData>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct StatusData {
     pub points: Vec<String>,
     pub codes: HashMap<String, Vec<i64>>,
@@ -46,6 +46,11 @@ impl Client {
         end_date: &str,
         domains: Vec<String>,
     ) -> Result<StatusResponse, anyhow::Error> {
+        if self.offline() {
+            return self
+                .store()?
+                .status_code(freq, regions, isp, start_date, end_date, &domains);
+        }
         let url = format!("https://{}{}", self.host, "/v2/tune/loganalyze/statuscode");
         let freq = freq.to_string();
         let data = StatusParam {
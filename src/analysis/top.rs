@@ -7,33 +7,33 @@ use anyhow::Ok;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use crate::{
-    utils::{max_length, print_err},
+    utils::{ipdb::IpDb, max_length, print_err},
     Client, NOT_FOUND_MSG,
 };
 use colored::Colorize;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct TopIpResponse {
     pub code: i32,
     pub error: String,
     pub data: Option<TopIpData>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct TopIpData {
     pub ips: Option<Vec<String>>,
     pub count: Option<Vec<i64>>,
     pub traffic: Option<Vec<i64>>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct TopUrlResponse {
     pub code: i32,
     pub error: String,
     pub data: Option<TopUrlData>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct TopUrlData {
     pub urls: Option<Vec<String>>,
     pub count: Option<Vec<i64>>,
@@ -138,6 +138,21 @@ impl Client {
         }
         let data = response.data.unwrap();
         let limit = limit.unwrap_or(10000);
+        let ipdb = IpDb::from_config_or_warn(&self.config);
+        // 配置了IP库时在最后一列附加归属地
+        let location_header = match ipdb {
+            Some(_) => format!(" {}", "Location".bold().green()),
+            None => "".to_string(),
+        };
+        let location = |ip: &str| match ipdb.as_ref() {
+            Some(ipdb) => format!(
+                " {}",
+                ipdb.lookup_str(ip)
+                    .map(|x| x.to_string())
+                    .unwrap_or("未知".to_string())
+            ),
+            None => "".to_string(),
+        };
         match filter_type {
             FilterType::Traffic => {
                 if data.traffic.is_none() || data.traffic.clone().unwrap().is_empty() {
@@ -152,9 +167,10 @@ impl Client {
                         end_date.bold(),
                     );
                     println!(
-                        "{:^width$} {:^20}",
+                        "{:^width$} {:^20}{}",
                         "IP".bold().green(),
                         "Traffic(MB)".bold().green(),
+                        location_header,
                         width = width,
                     );
                     let traffic = data.traffic.unwrap();
@@ -164,9 +180,10 @@ impl Client {
                             None => 0f64,
                         };
                         println!(
-                            "{:^width$} {:^20}",
+                            "{:^width$} {:^20}{}",
                             ip,
                             format!("{:.4}", traffic_),
+                            location(ip),
                             width = width
                         );
                         if (i + 1) as i32 >= limit {
@@ -188,9 +205,10 @@ impl Client {
                         end_date.bold(),
                     );
                     println!(
-                        "{:^width$} {:^20}",
+                        "{:^width$} {:^20}{}",
                         "IP".bold().green(),
                         "Count".bold().green(),
+                        location_header,
                         width = width,
                     );
                     let count = data.count.unwrap();
@@ -199,7 +217,13 @@ impl Client {
                             Some(v) => *v,
                             None => 0i64,
                         };
-                        println!("{:^width$} {:^20}", ip, count_, width = width);
+                        println!(
+                            "{:^width$} {:^20}{}",
+                            ip,
+                            count_,
+                            location(ip),
+                            width = width
+                        );
                         if (i + 1) as i32 >= limit {
                             break;
                         }
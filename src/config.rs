@@ -2,7 +2,12 @@
 
 #![allow(deprecated)]
 
-use std::{env, fs, path::PathBuf, process::exit};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+    process::exit,
+};
 
 use colored::Colorize;
 use serde::Deserialize;
@@ -22,29 +27,208 @@ pub struct CDNConfig {
 pub struct Config {
     pub debug: Option<bool>,
     pub download_log_domain_dir: Option<bool>,
+    /// 同时下载或处理的日志文件数, 默认8
+    pub download_concurrency: Option<usize>,
     pub cdn: CDNConfig,
     pub monitor: Monitor,
     pub blackip: BlackIP,
     pub five_minute_traffic: Option<i64>,
     pub five_minute_count: Option<i64>,
+    pub daemon: Option<Daemon>,
+    pub exporter: Option<Exporter>,
+    pub store: Option<Store>,
+    pub push: Option<Push>,
+    pub ship: Option<Ship>,
+    pub serve: Option<Serve>,
+    pub cache: Option<Cache>,
+    pub ipdb: Option<IpDatabase>,
+    /// 离线模式，从本地历史数据查询，由命令行--offline设置
+    #[serde(skip)]
+    pub offline: Option<bool>,
 }
 
 /// monitor config
 #[derive(Deserialize, Debug, Clone)]
 pub struct Monitor {
     pub qy_robot: Option<String>,
+    pub hitmiss: Option<HitMissMonitor>,
+    pub latency: Option<LatencyMonitor>,
+}
+
+/// 命中率告警配置
+#[derive(Deserialize, Debug, Clone)]
+pub struct HitMissMonitor {
+    /// 请求命中率下限(%)
+    pub request_floor: Option<f64>,
+    /// 流量命中率下限(%)
+    pub traffic_floor: Option<f64>,
+    /// 相比近7天基线下降的百分点
+    pub baseline_drop: Option<f64>,
+    /// 5分钟内请求次数低于该值时不做判断, 默认100
+    pub min_requests: Option<i64>,
+}
+
+/// 离线IP库配置
+#[derive(Deserialize, Debug, Clone)]
+pub struct IpDatabase {
+    /// ip2region的xdb文件或MaxMind的mmdb文件
+    pub path: Option<String>,
+}
+
+/// 回源响应时间告警配置
+#[derive(Deserialize, Debug, Clone)]
+pub struct LatencyMonitor {
+    /// 未命中请求的响应时间百分位超过该值(毫秒)时告警
+    pub miss_threshold: Option<u64>,
+    /// 百分位, 默认90
+    pub percentile: Option<f64>,
+    /// 1小时内未命中请求次数低于该值时不做判断, 默认100
+    pub min_requests: Option<u64>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct BlackIP {
     pub policy: Option<String>,
     pub rewrite: Option<bool>,
+    /// B策略统计的UA分类, 多个以英文逗号隔开, 默认scraper,library,downloader,empty
+    pub bot_classes: Option<String>,
+}
+
+/// daemon config
+#[derive(Deserialize, Debug, Clone)]
+pub struct Daemon {
+    /// 任务执行前的随机延迟上限(秒), 默认0
+    pub jitter: Option<u64>,
+    pub jobs: Option<Vec<DaemonJob>>,
+}
+
+/// 常驻进程中的任务
+#[derive(Deserialize, Debug, Clone)]
+pub struct DaemonJob {
+    /// 任务名, 默认同kind
+    pub name: Option<String>,
+    /// 任务类型
+    pub kind: String,
+    /// cron表达式, 如 */5 * * * *
+    pub schedule: String,
+    /// 覆盖[daemon]的jitter
+    pub jitter: Option<u64>,
+    /// 域名，多个以英文逗号隔开, 默认所有域名
+    pub domains: Option<String>,
+    /// 排除域名，多个以英文逗号隔开
+    pub domain_exclude: Option<String>,
+    /// 诊断后配置IP黑名单
+    pub apply_black_ip: Option<bool>,
+    /// 日志下载(log-download)及同步(log-sync)目录
+    pub dir: Option<String>,
+}
+
+/// Prometheus exporter config
+#[derive(Deserialize, Debug, Clone)]
+pub struct Exporter {
+    /// 监听地址, 默认0.0.0.0:9527
+    pub listen: Option<String>,
+    /// 采集间隔(秒), 默认300
+    pub interval: Option<u64>,
+    /// 域名，多个以英文逗号隔开, 默认所有域名
+    pub domains: Option<String>,
+    /// 排除域名，多个以英文逗号隔开
+    pub domain_exclude: Option<String>,
+}
+
+/// 本地历史数据 config
+#[derive(Deserialize, Debug, Clone)]
+pub struct Store {
+    /// SQLite数据库路径, 默认$XDG_DATA_HOME/qiniu-cdn-manager/history.db
+    pub path: Option<String>,
+}
+
+/// 日志缓存 config
+#[derive(Deserialize, Debug, Clone)]
+pub struct Cache {
+    /// 缓存目录, 默认$XDG_CACHE_HOME/qiniu-cdn-manager/logs
+    pub dir: Option<String>,
+    /// 缓存上限(MB), 超过时淘汰最久未使用的日志, 默认2048
+    pub max_size: Option<u64>,
+    /// log-sql的SQLite数据库, 默认$XDG_CACHE_HOME/qiniu-cdn-manager/log-sql.db
+    pub sql_db: Option<String>,
+}
+
+/// 推送时间序列 config
+#[derive(Deserialize, Debug, Clone)]
+pub struct Push {
+    /// 推送类型: influx(默认) graphite
+    pub kind: Option<String>,
+    /// influx line protocol的HTTP写入地址, 如 http://127.0.0.1:8086/write?db=cdn
+    pub url: Option<String>,
+    /// influx的Authorization Token
+    pub token: Option<String>,
+    /// graphite的TCP地址, 如 127.0.0.1:2003
+    pub address: Option<String>,
+    /// graphite指标前缀, 默认qiniu.cdn
+    pub prefix: Option<String>,
+    /// 只推送早于该分钟数之前的点, 默认10
+    pub settle: Option<i64>,
+    /// 域名，多个以英文逗号隔开, 默认所有域名
+    pub domains: Option<String>,
+    /// 排除域名，多个以英文逗号隔开
+    pub domain_exclude: Option<String>,
+}
+
+/// 推送日志 config
+#[derive(Deserialize, Debug, Clone)]
+pub struct Ship {
+    /// 推送类型: elasticsearch(默认, 也支持OpenSearch) loki
+    pub kind: Option<String>,
+    /// Elasticsearch或Loki地址, 如 http://127.0.0.1:9200
+    pub url: Option<String>,
+    /// Elasticsearch索引模板, 支持{domain} {date}, 默认qiniu-cdn-{domain}-{date}
+    pub index: Option<String>,
+    /// Loki标签模板, 支持{domain} {date}, 默认{job="qiniu-cdn", domain="{domain}"}
+    pub labels: Option<BTreeMap<String, String>>,
+    /// Basic认证用户名
+    pub username: Option<String>,
+    /// Basic认证密码
+    pub password: Option<String>,
+    /// Bearer token, 未配置username时使用
+    pub token: Option<String>,
+    /// 每批推送的条数, 默认1000
+    pub batch_size: Option<usize>,
+    /// 失败重试次数, 默认3
+    pub retries: Option<usize>,
+    /// 域名，多个以英文逗号隔开, 默认所有域名
+    pub domains: Option<String>,
+    /// 排除域名，多个以英文逗号隔开
+    pub domain_exclude: Option<String>,
+}
+
+/// REST API config
+#[derive(Deserialize, Debug, Clone)]
+pub struct Serve {
+    /// 监听地址, 默认127.0.0.1:9528
+    pub listen: Option<String>,
+    /// 审计日志路径(JSONL), 默认$XDG_DATA_HOME/qiniu-cdn-manager/audit.jsonl
+    pub audit_log: Option<String>,
+    pub tokens: Option<Vec<ServeToken>>,
+}
+
+/// API访问token
+#[derive(Deserialize, Debug, Clone)]
+pub struct ServeToken {
+    /// 名称, 记录在审计日志中
+    pub name: String,
+    /// Bearer token
+    pub token: String,
+    /// 权限: read refresh prefetch ipacl
+    pub scopes: Vec<String>,
+    /// 允许访问的域名，多个以英文逗号隔开, 默认不限制
+    pub domains: Option<String>,
 }
 
 impl Config {
-    /// parse config from path
-    pub fn parse(config_path: Option<PathBuf>) -> Self {
-        let config_path = match config_path {
+    /// 确定配置文件路径
+    pub fn resolve_path(config_path: Option<PathBuf>) -> PathBuf {
+        match config_path {
             Some(k) => {
                 if !k.exists() {
                     print_err(
@@ -75,9 +259,18 @@ impl Config {
                     }
                 }
             }
-        };
-        let config_str = fs::read_to_string(config_path).unwrap();
-        toml::from_str(&config_str).unwrap()
+        }
+    }
+
+    /// parse config from path
+    pub fn parse(config_path: Option<PathBuf>) -> Self {
+        Self::try_parse(&Self::resolve_path(config_path)).unwrap()
+    }
+
+    /// 解析配置文件, 出错时返回错误而不是退出
+    pub fn try_parse(config_path: &Path) -> Result<Self, anyhow::Error> {
+        let config_str = fs::read_to_string(config_path)?;
+        Ok(toml::from_str(&config_str)?)
     }
 }
 
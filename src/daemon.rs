@@ -0,0 +1,423 @@
+//! 常驻进程，按计划执行告警、诊断、日志下载、日报等任务
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use chrono::{Duration as ChronoDuration, Local};
+use colored::Colorize;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::Mutex,
+    task::JoinSet,
+    time::{sleep, timeout},
+};
+
+use crate::{
+    analysis::Freq,
+    config::{Config, DaemonJob},
+    exporter::serve_exporter,
+    log::stream::LogSource,
+    push::push,
+    utils::{cron::Schedule, get_domains, print_err, random_u64},
+    Client, SubFunctionEnum,
+};
+
+/// 退出时等待运行中任务的最长时间
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 任务类型
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobKind {
+    // 5分钟流量告警
+    Traffic,
+    // 5分钟请求次数告警
+    Count,
+    // 5分钟命中率告警
+    Hitmiss,
+    // 前一天的命中率日报
+    HitmissReport,
+    // 诊断疑似IP
+    Diagnostic,
+    // 下载前一天的日志
+    LogDownload,
+    // 增量同步前一天和当天的日志
+    LogSync,
+    // 推送前一天和当天的日志到Elasticsearch/Loki
+    LogShip,
+    // 同步前一天和当天的历史数据
+    Sync,
+    // 推送时间序列
+    Push,
+}
+
+impl FromStr for JobKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "traffic" => Ok(Self::Traffic),
+            "count" => Ok(Self::Count),
+            "hitmiss" => Ok(Self::Hitmiss),
+            "hitmiss-report" => Ok(Self::HitmissReport),
+            "diagnostic" => Ok(Self::Diagnostic),
+            "log-download" => Ok(Self::LogDownload),
+            "log-sync" => Ok(Self::LogSync),
+            "log-ship" => Ok(Self::LogShip),
+            "sync" => Ok(Self::Sync),
+            "push" => Ok(Self::Push),
+            _ => Err(anyhow!("不支持的任务类型: {}", s)),
+        }
+    }
+}
+
+impl Display for JobKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Traffic => f.write_str("traffic"),
+            Self::Count => f.write_str("count"),
+            Self::Hitmiss => f.write_str("hitmiss"),
+            Self::HitmissReport => f.write_str("hitmiss-report"),
+            Self::Diagnostic => f.write_str("diagnostic"),
+            Self::LogDownload => f.write_str("log-download"),
+            Self::LogSync => f.write_str("log-sync"),
+            Self::LogShip => f.write_str("log-ship"),
+            Self::Sync => f.write_str("sync"),
+            Self::Push => f.write_str("push"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Job {
+    name: String,
+    kind: JobKind,
+    schedule: Schedule,
+    jitter: u64,
+    conf: DaemonJob,
+    next: Option<chrono::DateTime<Local>>,
+    // 同一个任务不重叠执行
+    running: Arc<AtomicBool>,
+}
+
+fn log_line<T: Display>(msg: T) {
+    println!("[{}] {}", Local::now().format("%Y-%m-%d %H:%M:%S"), msg);
+}
+
+/// 根据配置生成任务，`running`保留重载前的运行状态
+fn build_jobs(
+    config: &Config,
+    running: &HashMap<String, Arc<AtomicBool>>,
+) -> Result<Vec<Job>, anyhow::Error> {
+    let daemon = config
+        .daemon
+        .clone()
+        .ok_or_else(|| anyhow!("未找到[daemon]配置"))?;
+    let default_jitter = daemon.jitter.unwrap_or(0);
+    let mut jobs: Vec<Job> = vec![];
+    let now = Local::now();
+    for conf in daemon.jobs.unwrap_or_default() {
+        let kind: JobKind = conf.kind.parse()?;
+        let schedule: Schedule = conf
+            .schedule
+            .parse()
+            .map_err(|e| anyhow!("任务{}的schedule错误: {}", conf.kind, e))?;
+        let name = conf.name.clone().unwrap_or(conf.kind.clone());
+        if jobs.iter().any(|j| j.name == name) {
+            return Err(anyhow!("任务名重复: {}", name));
+        }
+        jobs.push(Job {
+            running: running
+                .get(&name)
+                .cloned()
+                .unwrap_or_else(|| Arc::new(AtomicBool::new(false))),
+            name,
+            kind,
+            next: schedule.next_after(now),
+            schedule,
+            jitter: conf.jitter.unwrap_or(default_jitter),
+            conf,
+        });
+    }
+    if jobs.is_empty() {
+        return Err(anyhow!("[daemon]未配置任何任务"));
+    }
+    Ok(jobs)
+}
+
+/// 执行任务需要的域名，未指定时使用缓存的账户域名列表
+async fn job_domains(
+    config: &Config,
+    conf: &DaemonJob,
+    account_domains: &Mutex<Option<Vec<String>>>,
+) -> Result<Vec<String>, anyhow::Error> {
+    if conf.domains.is_some() {
+        return get_domains(config, &conf.domain_exclude, &conf.domains).await;
+    }
+    let mut cached = account_domains.lock().await;
+    if cached.is_none() {
+        *cached = Some(get_domains(config, &None, &None).await?);
+    }
+    let exclude = conf.domain_exclude.clone().unwrap_or_default();
+    let exclude: Vec<&str> = exclude.split(',').collect();
+    Ok(cached
+        .clone()
+        .unwrap()
+        .into_iter()
+        .filter(|d| !exclude.contains(&d.as_str()))
+        .collect())
+}
+
+async fn run_job(
+    config: Config,
+    kind: JobKind,
+    conf: DaemonJob,
+    domains: Vec<String>,
+) -> Result<(), anyhow::Error> {
+    let today = Local::now().format("%Y-%m-%d").to_string();
+    let yesterday = (Local::now() - ChronoDuration::days(1))
+        .format("%Y-%m-%d")
+        .to_string();
+    match kind {
+        JobKind::Traffic => {
+            Client::new(&config, SubFunctionEnum::Traffic)
+                .all_domain_charge_traffic(&today, &today, "5min", false, domains)
+                .await?;
+        }
+        JobKind::Count => {
+            Client::new(&config, SubFunctionEnum::AnalysisCount)
+                .all_domain_req_count(Freq::FiveMin, "global", &today, &today, false, domains)
+                .await?;
+        }
+        JobKind::Hitmiss => {
+            let client = Client::new(&config, SubFunctionEnum::AnalysisHitmiss);
+            for d in domains {
+                if let Err(e) = client.check_hitmiss(&today, &today, &d).await {
+                    print_err(format!("{}: {}", d, e).as_str(), false);
+                }
+            }
+        }
+        JobKind::HitmissReport => {
+            Client::new(&config, SubFunctionEnum::AnalysisHitmiss)
+                .hitmiss_report(&yesterday, domains, false)
+                .await?;
+        }
+        JobKind::Diagnostic => {
+            Client::new(&config, SubFunctionEnum::Domain)
+                .all_domain_diagnostic(
+                    conf.apply_black_ip.unwrap_or(false),
+                    true,
+                    false,
+                    domains,
+                    &today,
+                )
+                .await?;
+        }
+        JobKind::LogDownload => {
+            let dir = conf.dir.clone().unwrap_or("./logs".to_string());
+            // 单个域名失败不影响其他域名, 最后汇总报错
+            let mut failed = 0;
+            for d in domains {
+                if let Err(e) = Client::new(&config, SubFunctionEnum::Log)
+                    .download(&yesterday, Some(&dir), None, false, false, &d)
+                    .await
+                {
+                    print_err(format!("{}: {}", d, e).as_str(), false);
+                    failed += 1;
+                }
+            }
+            if failed > 0 {
+                return Err(anyhow!("{}个域名的日志下载失败", failed));
+            }
+        }
+        JobKind::LogSync => {
+            let dir = conf.dir.clone().unwrap_or("./logs".to_string());
+            Client::new(&config, SubFunctionEnum::Log)
+                .log_sync(&yesterday, &today, &dir, domains)
+                .await?;
+        }
+        JobKind::LogShip => {
+            Client::new(&config, SubFunctionEnum::Log)
+                .log_ship(&yesterday, &today, &LogSource::Api, domains, false)
+                .await?;
+        }
+        JobKind::Sync => {
+            Client::new(&config, SubFunctionEnum::Traffic)
+                .sync(&yesterday, &today, domains)
+                .await?;
+        }
+        JobKind::Push => {
+            push(&config, Some(domains.join(",")), None, false).await?;
+        }
+    }
+    Ok(())
+}
+
+/// 打印任务及下次执行时间
+pub fn list_jobs(config: &Config) -> Result<(), anyhow::Error> {
+    let jobs = build_jobs(config, &HashMap::new())?;
+    let width = jobs.iter().map(|x| x.name.len()).max().unwrap_or(0).max(4);
+    println!(
+        "{:<width$} {:<16} {:<20} {:^8} {:^20}",
+        "Name".bold().green(),
+        "Kind".bold().green(),
+        "Schedule".bold().green(),
+        "Jitter".bold().green(),
+        "Next".bold().green(),
+        width = width,
+    );
+    for job in jobs {
+        println!(
+            "{:<width$} {:<16} {:<20} {:^8} {:^20}",
+            job.name,
+            job.kind.to_string(),
+            job.conf.schedule,
+            format!("{}s", job.jitter),
+            job.next
+                .map(|x| x.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or("-".to_string()),
+            width = width,
+        );
+    }
+    Ok(())
+}
+
+/// 启动常驻进程，SIGTERM/SIGINT优雅退出，SIGHUP重新加载配置
+pub async fn run_daemon(config_path: PathBuf, debug: bool) -> Result<(), anyhow::Error> {
+    let load = || -> Result<Config, anyhow::Error> {
+        let mut config = Config::try_parse(&config_path)?;
+        if debug {
+            config.debug = Some(true);
+        }
+        Ok(config)
+    };
+    let mut config = load()?;
+    let mut jobs = build_jobs(&config, &HashMap::new())?;
+    let account_domains: Arc<Mutex<Option<Vec<String>>>> = Arc::new(Mutex::new(None));
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sighup = signal(SignalKind::hangup())?;
+    let mut tasks = JoinSet::new();
+    // 配置了[exporter]时同时暴露Prometheus指标
+    let exporter = config.exporter.is_some().then(|| {
+        let config = config.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_exporter(config, None, None, None).await {
+                log_line(format!("exporter异常退出: {}", e).red());
+            }
+        })
+    });
+    log_line(format!("daemon启动，共{}个任务", jobs.len()).green());
+    loop {
+        let next = jobs.iter().filter_map(|j| j.next).min();
+        let wait = match next {
+            Some(t) => (t - Local::now()).to_std().unwrap_or(Duration::ZERO),
+            // 没有可执行的任务，等待信号
+            None => Duration::from_secs(3600),
+        };
+        tokio::select! {
+            _ = sleep(wait) => {
+                let now = Local::now();
+                for job in jobs.iter_mut() {
+                    if job.next.is_none_or(|t| t > now) {
+                        continue;
+                    }
+                    job.next = job.schedule.next_after(now);
+                    if job.running.swap(true, Ordering::SeqCst) {
+                        log_line(format!("任务{}上次执行尚未结束，跳过本次", job.name).yellow());
+                        continue;
+                    }
+                    let config = config.clone();
+                    let conf = job.conf.clone();
+                    let kind = job.kind;
+                    let name = job.name.clone();
+                    let running = job.running.clone();
+                    let jitter = job.jitter;
+                    let account_domains = account_domains.clone();
+                    tasks.spawn(async move {
+                        if jitter > 0 {
+                            sleep(Duration::from_millis(random_u64() % (jitter * 1000))).await;
+                        }
+                        log_line(format!("任务{}开始", name));
+                        let start = Local::now().timestamp_millis();
+                        let result = match job_domains(&config, &conf, &account_domains).await {
+                            Ok(domains) => run_job(config, kind, conf, domains).await,
+                            Err(e) => Err(e),
+                        };
+                        let elapsed = Local::now().timestamp_millis() - start;
+                        match result {
+                            Ok(_) => log_line(format!("任务{}结束，耗时{}ms", name, elapsed)),
+                            Err(e) => log_line(format!("任务{}失败: {}", name, e).red()),
+                        }
+                        running.store(false, Ordering::SeqCst);
+                    });
+                }
+            }
+            Some(_) = tasks.join_next(), if !tasks.is_empty() => {}
+            _ = sighup.recv() => {
+                log_line("收到SIGHUP，重新加载配置");
+                let reloaded = load().and_then(|c| {
+                    let running = jobs
+                        .iter()
+                        .map(|j| (j.name.clone(), j.running.clone()))
+                        .collect();
+                    build_jobs(&c, &running).map(|j| (c, j))
+                });
+                match reloaded {
+                    Ok((c, j)) => {
+                        config = c;
+                        jobs = j;
+                        *account_domains.lock().await = None;
+                        log_line(format!("配置已重新加载，共{}个任务", jobs.len()).green());
+                    }
+                    Err(e) => log_line(format!("重新加载配置失败，继续使用旧配置: {}", e).red()),
+                }
+            }
+            _ = sigterm.recv() => break,
+            _ = sigint.recv() => break,
+        }
+    }
+    log_line(format!("收到退出信号，等待{}个运行中的任务结束", tasks.len()).yellow());
+    if timeout(SHUTDOWN_TIMEOUT, async {
+        while tasks.join_next().await.is_some() {}
+    })
+    .await
+    .is_err()
+    {
+        log_line("等待超时，强制退出".red());
+        tasks.abort_all();
+    }
+    if let Some(exporter) = exporter {
+        exporter.abort();
+    }
+    log_line("daemon已退出");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn build_jobs_test() {
+        let mut config = Config::parse(Some(PathBuf::from("./qiniu-cdn.toml.example")));
+        let jobs = build_jobs(&config, &HashMap::new()).unwrap();
+        assert!(!jobs.is_empty());
+        assert!(jobs.iter().all(|x| x.next.is_some()));
+        let mut daemon = config.daemon.clone().unwrap();
+        daemon.jobs.as_mut().unwrap()[0].kind = "unknown".to_string();
+        config.daemon = Some(daemon);
+        assert!(build_jobs(&config, &HashMap::new()).is_err());
+    }
+}
@@ -0,0 +1,774 @@
+//! 终端仪表盘
+
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use chrono::{Duration as ChronoDuration, Local};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Gauge, Paragraph, Row, Sparkline, Table, TableState, Tabs},
+    DefaultTerminal, Frame,
+};
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use crate::{
+    analysis::{hitmiss::HitRatio, top::FilterType, Freq},
+    config::Config,
+    log::stream::LogSource,
+    utils::{parse_point_time, qiniu_now},
+    Client, SubFunctionEnum,
+};
+
+/// 时间范围
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Range {
+    Today,
+    Yesterday,
+    Week,
+}
+
+impl Range {
+    fn label(&self) -> &'static str {
+        match self {
+            Range::Today => "今天",
+            Range::Yesterday => "昨天",
+            Range::Week => "近7天",
+        }
+    }
+
+    fn next(&self) -> Self {
+        match self {
+            Range::Today => Range::Yesterday,
+            Range::Yesterday => Range::Week,
+            Range::Week => Range::Today,
+        }
+    }
+
+    /// 开始和结束日期
+    pub fn dates(&self) -> (String, String) {
+        let now = qiniu_now();
+        let day = |n: i64| {
+            (now - ChronoDuration::days(n))
+                .format("%Y-%m-%d")
+                .to_string()
+        };
+        match self {
+            Range::Today => (day(0), day(0)),
+            Range::Yesterday => (day(1), day(1)),
+            Range::Week => (day(6), day(0)),
+        }
+    }
+
+    fn freq(&self) -> Freq {
+        match self {
+            Range::Week => Freq::OneHour,
+            _ => Freq::FiveMin,
+        }
+    }
+
+    fn granularity(&self) -> &'static str {
+        match self {
+            Range::Week => "hour",
+            _ => "5min",
+        }
+    }
+}
+
+/// 一个域名在某个时间范围内的数据
+#[derive(Debug, Default)]
+struct Panel {
+    traffic: Vec<u64>,
+    traffic_total: i64,
+    requests: Vec<u64>,
+    requests_total: i64,
+    codes: Vec<(String, i64)>,
+    ratio: HitRatio,
+    top_ips: Vec<(String, i64)>,
+    top_urls: Vec<(String, i64)>,
+    errors: Vec<String>,
+}
+
+/// 数据请求的标识，切换域名或范围后丢弃过期的结果
+type PanelKey = (String, Range, FilterType);
+
+enum Msg {
+    Panel(PanelKey, Panel),
+    IpUrl(String, Result<Vec<(String, i32)>, String>),
+    /// ip, 域名, 结果
+    Ban(String, String, Result<usize, String>),
+}
+
+enum Mode {
+    Normal,
+    IpUrl {
+        ip: String,
+        rows: Option<Result<Vec<(String, i32)>, String>>,
+        state: TableState,
+    },
+    /// ip, 域名
+    ConfirmBan(String, String),
+}
+
+struct App {
+    config: Config,
+    domains: Vec<String>,
+    domain_idx: usize,
+    range: Range,
+    filter_type: FilterType,
+    interval: Duration,
+    panel: Option<Panel>,
+    loading: bool,
+    updated_at: String,
+    status: String,
+    ip_state: TableState,
+    mode: Mode,
+    last_fetch: Instant,
+}
+
+/// 字节数转换成可读的单位
+pub fn human_bytes(bytes: i64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut i = 0;
+    while value >= 1024.0 && i < units.len() - 1 {
+        value /= 1024.0;
+        i += 1;
+    }
+    format!("{:.2}{}", value, units[i])
+}
+
+/// 已发布的点，去掉未来补0的点
+fn published(points: &[String], values: Vec<i64>) -> Vec<i64> {
+    let now = qiniu_now().naive_local();
+    values
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| {
+            points
+                .get(*i)
+                .and_then(|p| parse_point_time(p))
+                .is_none_or(|t| t <= now)
+        })
+        .map(|(_, v)| v)
+        .collect()
+}
+
+fn top_values(
+    keys: Option<Vec<String>>,
+    traffic: Option<Vec<i64>>,
+    count: Option<Vec<i64>>,
+    filter_type: FilterType,
+) -> Vec<(String, i64)> {
+    let values = match filter_type {
+        FilterType::Traffic => traffic,
+        FilterType::ReqCount => count,
+    }
+    .unwrap_or_default();
+    keys.unwrap_or_default().into_iter().zip(values).collect()
+}
+
+async fn fetch_panel(config: Config, key: PanelKey) -> Panel {
+    let (domain, range, filter_type) = key;
+    let (start_date, end_date) = range.dates();
+    let domains = vec![domain.clone()];
+    let mut panel = Panel::default();
+
+    match Client::new(&config, SubFunctionEnum::Traffic)
+        .charge_traffic(&start_date, &end_date, range.granularity(), &domain)
+        .await
+    {
+        Ok(response) => {
+            let points = response.time.unwrap_or_default();
+            if let Some(data) = response.data.and_then(|x| x.get(&domain).cloned()) {
+                let china = data.china.unwrap_or_default();
+                let oversea = data.oversea.unwrap_or_default();
+                let total: Vec<i64> = (0..points.len())
+                    .map(|i| china.get(i).unwrap_or(&0) + oversea.get(i).unwrap_or(&0))
+                    .collect();
+                let total = published(&points, total);
+                panel.traffic_total = total.iter().sum();
+                panel.traffic = total.into_iter().map(|x| x.max(0) as u64).collect();
+            }
+        }
+        Err(e) => panel.errors.push(format!("流量: {}", e)),
+    }
+
+    match Client::new(&config, SubFunctionEnum::AnalysisCount)
+        .req_count(
+            range.freq(),
+            "global",
+            &start_date,
+            &end_date,
+            domains.clone(),
+        )
+        .await
+    {
+        Ok(response) => {
+            if let Some(data) = response.data {
+                let count = published(&data.points, data.req_count);
+                panel.requests_total = count.iter().sum();
+                panel.requests = count.into_iter().map(|x| x.max(0) as u64).collect();
+            }
+        }
+        Err(e) => panel.errors.push(format!("请求次数: {}", e)),
+    }
+
+    match Client::new(&config, SubFunctionEnum::AnalysisStatus)
+        .status_code(
+            Freq::OneDay,
+            "global",
+            "all",
+            &start_date,
+            &end_date,
+            domains.clone(),
+        )
+        .await
+    {
+        Ok(response) => {
+            if let Some(data) = response.data {
+                let mut codes: Vec<(String, i64)> = data
+                    .codes
+                    .into_iter()
+                    .map(|(k, v)| (k, v.iter().sum()))
+                    .collect();
+                codes.sort_by_key(|x| std::cmp::Reverse(x.1));
+                panel.codes = codes;
+            }
+        }
+        Err(e) => panel.errors.push(format!("状态码: {}", e)),
+    }
+
+    match Client::new(&config, SubFunctionEnum::AnalysisHitmiss)
+        .hit_miss(Freq::OneDay, &start_date, &end_date, domains.clone())
+        .await
+    {
+        Ok(response) => {
+            if let Some(data) = response.data {
+                panel.ratio = data.total(0, data.points.len());
+            }
+        }
+        Err(e) => panel.errors.push(format!("命中率: {}", e)),
+    }
+
+    let client = Client::new(&config, SubFunctionEnum::AnalysisTop);
+    match client
+        .top_ip(
+            "global",
+            &start_date,
+            &end_date,
+            filter_type,
+            domains.clone(),
+        )
+        .await
+    {
+        Ok(response) => {
+            if let Some(data) = response.data {
+                panel.top_ips = top_values(data.ips, data.traffic, data.count, filter_type);
+            }
+        }
+        Err(e) => panel.errors.push(format!("Top IP: {}", e)),
+    }
+    match client
+        .top_url("global", &start_date, &end_date, filter_type, domains)
+        .await
+    {
+        Ok(response) => {
+            if let Some(data) = response.data {
+                panel.top_urls = top_values(data.urls, data.traffic, data.count, filter_type);
+            }
+        }
+        Err(e) => panel.errors.push(format!("Top URL: {}", e)),
+    }
+    panel
+}
+
+/// 只在域名已开启IP黑名单时追加, 避免把白名单覆盖成只有一个IP的黑名单
+async fn ban_ip(client: &Client, ip: &str, domain: &str) -> Result<usize, anyhow::Error> {
+    let acl = client.current_ip_acl(domain).await?;
+    if acl.ip_acltype != "black" {
+        let current = if acl.ip_acltype.is_empty() {
+            "未开启"
+        } else {
+            "白名单"
+        };
+        return Err(anyhow!(
+            "{}当前IP名单{}, 请使用ipacl子命令设置",
+            domain,
+            current
+        ));
+    }
+    Ok(client
+        .update_ip_acl(true, false, false, ip, false, domain)
+        .await?
+        .count())
+}
+
+fn centered(area: Rect, percent_x: u16, percent_y: u16) -> Rect {
+    let vertical = Layout::vertical([
+        Constraint::Percentage((100 - percent_y) / 2),
+        Constraint::Percentage(percent_y),
+        Constraint::Percentage((100 - percent_y) / 2),
+    ])
+    .split(area);
+    Layout::horizontal([
+        Constraint::Percentage((100 - percent_x) / 2),
+        Constraint::Percentage(percent_x),
+        Constraint::Percentage((100 - percent_x) / 2),
+    ])
+    .split(vertical[1])[1]
+}
+
+impl App {
+    fn domain(&self) -> String {
+        self.domains[self.domain_idx].clone()
+    }
+
+    fn key(&self) -> PanelKey {
+        (self.domain(), self.range, self.filter_type)
+    }
+
+    fn refresh(&mut self, tx: &UnboundedSender<Msg>) {
+        self.loading = true;
+        self.last_fetch = Instant::now();
+        let key = self.key();
+        let config = self.config.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let panel = fetch_panel(config, key.clone()).await;
+            let _ = tx.send(Msg::Panel(key, panel));
+        });
+    }
+
+    fn selected_ip(&self) -> Option<String> {
+        let panel = self.panel.as_ref()?;
+        let i = self.ip_state.selected()?;
+        panel.top_ips.get(i).map(|x| x.0.clone())
+    }
+
+    fn on_msg(&mut self, msg: Msg) {
+        match msg {
+            Msg::Panel(key, panel) => {
+                if key != self.key() {
+                    return;
+                }
+                self.loading = false;
+                self.updated_at = Local::now().format("%H:%M:%S").to_string();
+                self.status = panel.errors.join("; ");
+                if panel.top_ips.is_empty() {
+                    self.ip_state.select(None);
+                } else if self
+                    .ip_state
+                    .selected()
+                    .is_none_or(|i| i >= panel.top_ips.len())
+                {
+                    self.ip_state.select(Some(0));
+                }
+                self.panel = Some(panel);
+            }
+            Msg::IpUrl(ip, result) => {
+                if let Mode::IpUrl {
+                    ip: current,
+                    rows,
+                    state,
+                } = &mut self.mode
+                {
+                    if *current == ip {
+                        if result.as_ref().is_ok_and(|x| !x.is_empty()) {
+                            state.select(Some(0));
+                        }
+                        *rows = Some(result);
+                    }
+                }
+            }
+            Msg::Ban(ip, domain, result) => {
+                self.status = match result {
+                    Ok(_) => format!("已将{}加入{}的黑名单 ✅", ip, domain),
+                    Err(e) => format!("封禁{}失败: {}", ip, e),
+                };
+            }
+        }
+    }
+
+    /// 处理按键，返回false时退出
+    fn on_key(&mut self, key: KeyEvent, tx: &UnboundedSender<Msg>) -> bool {
+        match &mut self.mode {
+            Mode::ConfirmBan(ip, domain) => {
+                if let KeyCode::Char('y') | KeyCode::Char('Y') = key.code {
+                    let (ip, domain) = (ip.clone(), domain.clone());
+                    let client = Client::new(&self.config, SubFunctionEnum::Domain);
+                    let tx = tx.clone();
+                    self.status = format!("正在封禁{}...", ip);
+                    tokio::spawn(async move {
+                        let result = ban_ip(&client, &ip, &domain)
+                            .await
+                            .map_err(|e| e.to_string());
+                        let _ = tx.send(Msg::Ban(ip, domain, result));
+                    });
+                }
+                self.mode = Mode::Normal;
+                return true;
+            }
+            Mode::IpUrl { rows, state, .. } => {
+                let len = match rows {
+                    Some(Ok(k)) => k.len(),
+                    _ => 0,
+                };
+                match key.code {
+                    KeyCode::Esc | KeyCode::Char('q') => self.mode = Mode::Normal,
+                    KeyCode::Down | KeyCode::Char('j') if len > 0 => {
+                        state.select(Some((state.selected().unwrap_or(0) + 1).min(len - 1)));
+                    }
+                    KeyCode::Up | KeyCode::Char('k') if len > 0 => {
+                        state.select(Some(state.selected().unwrap_or(0).saturating_sub(1)));
+                    }
+                    _ => {}
+                }
+                return true;
+            }
+            Mode::Normal => {}
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Right | KeyCode::Tab | KeyCode::Char('l') => {
+                self.domain_idx = (self.domain_idx + 1) % self.domains.len();
+                self.panel = None;
+                self.refresh(tx);
+            }
+            KeyCode::Left | KeyCode::BackTab | KeyCode::Char('h') => {
+                self.domain_idx = (self.domain_idx + self.domains.len() - 1) % self.domains.len();
+                self.panel = None;
+                self.refresh(tx);
+            }
+            KeyCode::Char('r') => {
+                self.range = self.range.next();
+                self.panel = None;
+                self.refresh(tx);
+            }
+            KeyCode::Char('t') => {
+                self.filter_type = match self.filter_type {
+                    FilterType::Traffic => FilterType::ReqCount,
+                    FilterType::ReqCount => FilterType::Traffic,
+                };
+                self.refresh(tx);
+            }
+            KeyCode::Char('u') | KeyCode::F(5) => self.refresh(tx),
+            KeyCode::Down | KeyCode::Char('j') => {
+                let len = self.panel.as_ref().map(|x| x.top_ips.len()).unwrap_or(0);
+                if len > 0 {
+                    let i = self.ip_state.selected().map(|i| i + 1).unwrap_or(0);
+                    self.ip_state.select(Some(i.min(len - 1)));
+                }
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                let i = self.ip_state.selected().unwrap_or(0);
+                self.ip_state.select(Some(i.saturating_sub(1)));
+            }
+            KeyCode::Enter => {
+                if let Some(ip) = self.selected_ip() {
+                    let (start_date, end_date) = self.range.dates();
+                    let domain = self.domain();
+                    let client = Client::new(&self.config, SubFunctionEnum::Log);
+                    let tx = tx.clone();
+                    let ip_ = ip.clone();
+                    tokio::spawn(async move {
+                        let result = client
+                            .ip_url_count(&ip_, &start_date, &end_date, &LogSource::Api, &domain)
+                            .await
+                            .map(|(rows, _)| rows)
+                            .map_err(|e| e.to_string());
+                        let _ = tx.send(Msg::IpUrl(ip_, result));
+                    });
+                    self.mode = Mode::IpUrl {
+                        ip,
+                        rows: None,
+                        state: TableState::default(),
+                    };
+                }
+            }
+            KeyCode::Char('b') => {
+                if let Some(ip) = self.selected_ip() {
+                    self.mode = Mode::ConfirmBan(ip, self.domain());
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let chunks = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Length(8),
+            Constraint::Length(9),
+            Constraint::Min(6),
+            Constraint::Length(1),
+        ])
+        .split(frame.area());
+
+        let (start_date, end_date) = self.range.dates();
+        let title = format!(
+            " {} {}～{} {} ",
+            self.range.label(),
+            start_date,
+            end_date,
+            if self.loading {
+                "加载中...".to_string()
+            } else {
+                format!("更新于 {}", self.updated_at)
+            }
+        );
+        let tabs = Tabs::new(self.domains.iter().map(|x| x.as_str()))
+            .select(self.domain_idx)
+            .highlight_style(Style::default().fg(Color::Yellow).bold())
+            .block(Block::default().borders(Borders::ALL).title(title));
+        frame.render_widget(tabs, chunks[0]);
+
+        let empty = Panel::default();
+        let panel = self.panel.as_ref().unwrap_or(&empty);
+        let traffic = Sparkline::default()
+            .block(Block::default().borders(Borders::ALL).title(format!(
+                " 流量({}) 总计 {} ",
+                self.range.granularity(),
+                human_bytes(panel.traffic_total)
+            )))
+            .data(&panel.traffic)
+            .style(Style::default().fg(Color::Cyan));
+        frame.render_widget(traffic, chunks[1]);
+
+        let middle = Layout::horizontal([
+            Constraint::Percentage(40),
+            Constraint::Percentage(30),
+            Constraint::Percentage(30),
+        ])
+        .split(chunks[2]);
+        let requests = Sparkline::default()
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(" 请求次数 总计 {} ", panel.requests_total)),
+            )
+            .data(&panel.requests)
+            .style(Style::default().fg(Color::Green));
+        frame.render_widget(requests, middle[0]);
+
+        let total_codes: i64 = panel.codes.iter().map(|x| x.1).sum();
+        let code_rows = panel.codes.iter().map(|(code, count)| {
+            let color = if code.starts_with('2') || code.starts_with('3') {
+                Color::Green
+            } else {
+                Color::Red
+            };
+            Row::new(vec![
+                Span::styled(code.clone(), Style::default().fg(color)),
+                Span::raw(count.to_string()),
+                Span::raw(format!(
+                    "{:.2}%",
+                    *count as f64 / total_codes.max(1) as f64 * 100.0
+                )),
+            ])
+        });
+        let codes = Table::new(
+            code_rows,
+            [
+                Constraint::Length(5),
+                Constraint::Min(8),
+                Constraint::Length(8),
+            ],
+        )
+        .block(Block::default().borders(Borders::ALL).title(" 状态码 "));
+        frame.render_widget(codes, middle[1]);
+
+        let ratio_block = Block::default().borders(Borders::ALL).title(" 命中率 ");
+        let inner = ratio_block.inner(middle[2]);
+        frame.render_widget(ratio_block, middle[2]);
+        let gauges = Layout::vertical([Constraint::Length(2), Constraint::Length(2)]).split(inner);
+        for (i, (label, ratio)) in [
+            ("请求", panel.ratio.request_ratio()),
+            ("流量", panel.ratio.traffic_ratio()),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let ratio = ratio.unwrap_or(0.0);
+            let gauge = Gauge::default()
+                .gauge_style(Style::default().fg(Color::Magenta))
+                .label(format!("{} {:.2}%", label, ratio))
+                .ratio((ratio / 100.0).clamp(0.0, 1.0));
+            frame.render_widget(gauge, gauges[i]);
+        }
+
+        let bottom = Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)])
+            .split(chunks[3]);
+        let value = |v: i64| match self.filter_type {
+            FilterType::Traffic => human_bytes(v),
+            FilterType::ReqCount => v.to_string(),
+        };
+        let by = match self.filter_type {
+            FilterType::Traffic => "流量",
+            FilterType::ReqCount => "请求次数",
+        };
+        let ip_rows = panel
+            .top_ips
+            .iter()
+            .map(|(ip, v)| Row::new(vec![ip.clone(), value(*v)]));
+        let ips = Table::new(ip_rows, [Constraint::Min(16), Constraint::Length(12)])
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(" Top IP({}) ", by)),
+            )
+            .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(ips, bottom[0], &mut self.ip_state);
+        let url_rows = panel
+            .top_urls
+            .iter()
+            .map(|(url, v)| Row::new(vec![url.clone(), value(*v)]));
+        let urls = Table::new(url_rows, [Constraint::Min(20), Constraint::Length(12)]).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!(" Top URL({}) ", by)),
+        );
+        frame.render_widget(urls, bottom[1]);
+
+        let footer = if self.status.is_empty() {
+            Line::from(
+                "q退出 ←→切换域名 r切换范围 t切换排序 u刷新 ↑↓选择IP Enter查看IP请求 b封禁IP"
+                    .dark_gray(),
+            )
+        } else {
+            Line::from(self.status.clone().yellow())
+        };
+        frame.render_widget(Paragraph::new(footer), chunks[4]);
+
+        match &mut self.mode {
+            Mode::Normal => {}
+            Mode::ConfirmBan(ip, domain) => {
+                let area = centered(frame.area(), 50, 20);
+                frame.render_widget(Clear, area);
+                let text = Paragraph::new(vec![
+                    Line::from(format!("确认将 {} 加入 {} 的IP黑名单?", ip, domain)),
+                    Line::from(""),
+                    Line::from("y 确认  其他键取消".dark_gray()),
+                ])
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(" 封禁IP ")
+                        .red(),
+                );
+                frame.render_widget(text, area);
+            }
+            Mode::IpUrl { ip, rows, state } => {
+                let area = centered(frame.area(), 80, 70);
+                frame.render_widget(Clear, area);
+                let block = Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(" {} URL请求次数 (Esc返回) ", ip));
+                match rows {
+                    None => frame.render_widget(Paragraph::new("日志查询中...").block(block), area),
+                    Some(Err(e)) => {
+                        frame.render_widget(Paragraph::new(e.clone().red()).block(block), area)
+                    }
+                    Some(Ok(rows)) if rows.is_empty() => frame
+                        .render_widget(Paragraph::new("没有找到该IP的请求日志").block(block), area),
+                    Some(Ok(rows)) => {
+                        let table = Table::new(
+                            rows.iter()
+                                .map(|(url, c)| Row::new(vec![url.clone(), c.to_string()])),
+                            [Constraint::Min(20), Constraint::Length(10)],
+                        )
+                        .block(block)
+                        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+                        frame.render_stateful_widget(table, area, state);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 启动终端仪表盘
+pub async fn run_dashboard(
+    config: Config,
+    domains: Vec<String>,
+    interval: u64,
+) -> Result<(), anyhow::Error> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut app = App {
+        config,
+        domains,
+        domain_idx: 0,
+        range: Range::Today,
+        filter_type: FilterType::Traffic,
+        interval: Duration::from_secs(interval.max(10)),
+        panel: None,
+        loading: false,
+        updated_at: String::new(),
+        status: String::new(),
+        ip_state: TableState::default(),
+        mode: Mode::Normal,
+        last_fetch: Instant::now(),
+    };
+    app.refresh(&tx);
+    let mut terminal = ratatui::try_init()?;
+    let result = run(&mut terminal, &mut app, &tx, &mut rx);
+    ratatui::restore();
+    result
+}
+
+fn run(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    tx: &UnboundedSender<Msg>,
+    rx: &mut mpsc::UnboundedReceiver<Msg>,
+) -> Result<(), anyhow::Error> {
+    loop {
+        while let Ok(msg) = rx.try_recv() {
+            app.on_msg(msg);
+        }
+        terminal.draw(|frame| app.draw(frame))?;
+        if event::poll(Duration::from_millis(200))? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !app.on_key(key, tx) {
+                    return Ok(());
+                }
+            }
+        }
+        if !app.loading && app.last_fetch.elapsed() >= app.interval {
+            app.refresh(tx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dashboard_helpers_test() {
+        assert_eq!(human_bytes(512), "512.00B");
+        assert_eq!(human_bytes(1536), "1.50KB");
+        assert_eq!(human_bytes(3 * 1024 * 1024 * 1024), "3.00GB");
+        let (start, end) = Range::Week.dates();
+        assert!(start < end);
+        assert_eq!(Range::Today.dates().0, Range::Today.dates().1);
+        assert_eq!(Range::Week.next(), Range::Today);
+        assert_eq!(
+            top_values(
+                Some(vec!["1.1.1.1".to_string(), "2.2.2.2".to_string()]),
+                Some(vec![100, 50]),
+                None,
+                FilterType::Traffic,
+            ),
+            vec![("1.1.1.1".to_string(), 100), ("2.2.2.2".to_string(), 50)]
+        );
+        let points = vec![
+            "2000-01-01 00:00:00".to_string(),
+            "2999-01-01 00:00:00".to_string(),
+        ];
+        assert_eq!(published(&points, vec![1, 0]), vec![1]);
+    }
+}
@@ -13,7 +13,8 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     analysis::top::FilterType,
-    utils::{print_err, prompt, qy_robot::QyRobot},
+    log::bot::UaClass,
+    utils::{ipdb::IpDb, print_err, prompt, qy_robot::QyRobot},
     Client,
 };
 use colored::Colorize;
@@ -24,6 +25,17 @@ pub struct Response {
     pub error: Option<String>,
 }
 
+/// 接口返回的code不是200时转换成错误
+fn check_response(response: Response) -> Result<(), anyhow::Error> {
+    match response.code {
+        Some(code) if code != 200 => Err(anyhow!(
+            "{}",
+            response.error.unwrap_or(format!("code: {}", code))
+        )),
+        _ => Ok(()),
+    }
+}
+
 #[derive(Debug, Serialize, Clone)]
 pub struct IpACLParam<'a> {
     #[serde(rename = "ipACLType")]
@@ -38,7 +50,29 @@ pub enum IpACLType {
     Blank,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// 设置IP黑白名单的结果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IpAclChange {
+    /// 关闭了黑白名单
+    Closed,
+    /// 与线上配置相同，没有调用接口
+    Unchanged,
+    /// 移除后名单为空，关闭了黑白名单
+    Emptied,
+    /// 设置成功，值为名单中的IP数
+    Updated(usize),
+}
+
+impl IpAclChange {
+    pub fn count(&self) -> usize {
+        match self {
+            IpAclChange::Updated(k) => *k,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct IpACL {
     #[serde(rename = "ipACLType")]
     pub ip_acltype: String,
@@ -46,7 +80,7 @@ pub struct IpACL {
     pub ip_aclvalues: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Referer {
     #[serde(rename = "refererType")]
     pub referer_type: String,
@@ -56,7 +90,7 @@ pub struct Referer {
     pub null_referer: bool,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Https {
     #[serde(rename = "certId")]
     pub cert_id: String,
@@ -66,7 +100,7 @@ pub struct Https {
     pub http2_enable: bool,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct DomainInfoResponse {
     pub code: Option<i32>,
     pub error: Option<String>,
@@ -298,7 +332,20 @@ impl Client {
         }
     }
 
-    pub async fn set_ip_acl(
+    /// 查询域名当前的IP黑白名单, 没有配置时类型为空
+    pub async fn current_ip_acl(&self, domain: &str) -> Result<IpACL, anyhow::Error> {
+        let domain_info = self.domain_info(domain).await?;
+        if let Some(error) = domain_info.error {
+            return Err(anyhow!("查询域名{}失败: {}", domain, error));
+        }
+        Ok(domain_info.ip_acl.unwrap_or(IpACL {
+            ip_acltype: String::new(),
+            ip_aclvalues: vec![],
+        }))
+    }
+
+    /// 设置IP黑白名单, 不输出到终端, 参数错误或接口失败时返回错误
+    pub async fn update_ip_acl(
         &self,
         black: bool,
         white: bool,
@@ -306,93 +353,89 @@ impl Client {
         ips: &str,
         rewrite: bool,
         domain: &str,
-    ) -> Result<usize, anyhow::Error> {
-        let mut check_num = 0;
-        let mut ip_acltype = IpACLType::Blank;
-        if black {
-            check_num += 1;
-            ip_acltype = IpACLType::Black;
-        }
-        if white {
-            check_num += 1;
-            ip_acltype = IpACLType::White;
-        }
-        if close {
-            check_num += 1;
-            ip_acltype = IpACLType::Blank;
-        }
-        // 参数互斥
-        if check_num != 1 {
-            print_err("参数错误", true);
-        }
-        if (white || black) && ips.is_empty() {
-            print_err("ips参数错误", true);
-        }
+    ) -> Result<IpAclChange, anyhow::Error> {
+        let (ip_acltype, type_name) = match (black, white, close) {
+            (true, false, false) => (IpACLType::Black, "black"),
+            (false, true, false) => (IpACLType::White, "white"),
+            (false, false, true) => (IpACLType::Blank, ""),
+            // 参数互斥
+            _ => return Err(anyhow!("参数错误")),
+        };
         // 关闭黑白名单
         if close {
-            self.ip_acl(vec![], IpACLType::Blank, domain).await?;
-            println!("{}", "关闭成功 ✅".green());
-            return Ok(0);
+            check_response(self.ip_acl(vec![], IpACLType::Blank, domain).await?)?;
+            return Ok(IpAclChange::Closed);
         }
-        let ips: Vec<&str> = ips.split(',').collect();
+        let ips: Vec<&str> = ips
+            .split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .collect();
         if ips.is_empty() {
-            print_err("ip列表为空！", true);
+            return Err(anyhow!("ips参数错误"));
+        }
+        if rewrite {
+            let count = ips.len();
+            check_response(self.ip_acl(ips, ip_acltype, domain).await?)?;
+            return Ok(IpAclChange::Updated(count));
         }
         // 不是重写模式，先查询，再追加
-        if !rewrite {
-            let domain_info = self.domain_info(domain).await?;
-            let origin_ip_acl = domain_info.ip_acl.clone().unwrap();
-            let (remove_ips, mut ips): (Vec<&str>, Vec<&str>) =
-                ips.iter().partition(|&s| s.starts_with('d'));
-            let mut should_merge = false;
-            if origin_ip_acl.ip_acltype == "white" && white {
-                should_merge = true;
-            }
-            if origin_ip_acl.ip_acltype == "black" && black {
-                should_merge = true;
-            }
-            if should_merge {
-                for ip in origin_ip_acl.ip_aclvalues.iter() {
-                    if ips.contains(&(*ip).as_str()) {
-                        continue;
-                    }
-                    if remove_ips.contains(&format!("d{}", *ip).as_str()) {
-                        continue;
-                    }
-                    ips.push(ip.as_str());
-                }
-                // 检查ip名单是否相同
-                let mut should_invoke_api = false;
-                for ip in ips.iter() {
-                    if !origin_ip_acl.ip_aclvalues.contains(&(*ip).to_string()) {
-                        should_invoke_api = true;
-                        break;
-                    }
+        let origin_ip_acl = self.current_ip_acl(domain).await?;
+        let (remove_ips, mut ips): (Vec<&str>, Vec<&str>) =
+            ips.iter().partition(|&s| s.starts_with('d'));
+        if origin_ip_acl.ip_acltype == type_name {
+            for ip in origin_ip_acl.ip_aclvalues.iter() {
+                if ips.contains(&ip.as_str()) {
+                    continue;
                 }
-                if !should_invoke_api && !ips.is_empty() {
-                    println!("{}", "与线上配置相同，跳过设置！".yellow());
-                    return Ok(0);
+                if remove_ips.contains(&format!("d{}", ip).as_str()) {
+                    continue;
                 }
-            } else if !remove_ips.is_empty() {
-                return Err(anyhow!("当前IP模式和线上不一致，无法移除IP！"));
+                ips.push(ip.as_str());
             }
-            if ips.is_empty() {
-                println!("[WARN] {}", "ip列表为空，将IP黑/白名单关闭".yellow());
-                ip_acltype = IpACLType::Blank;
-            }
-            let response = self.ip_acl(ips.clone(), ip_acltype, domain).await?;
-            if response.code.is_some() && response.code.unwrap() != 200 {
-                return Err(anyhow!("{}", response.error.unwrap()));
+            // 检查ip名单是否相同
+            if !ips.is_empty()
+                && ips.len() == origin_ip_acl.ip_aclvalues.len()
+                && ips
+                    .iter()
+                    .all(|ip| origin_ip_acl.ip_aclvalues.contains(&ip.to_string()))
+            {
+                return Ok(IpAclChange::Unchanged);
             }
-            println!("{}", "操作成功 ✅".green());
-        } else {
-            let response = self.ip_acl(ips.clone(), ip_acltype, domain).await?;
-            if response.code.is_some() && response.code.unwrap() != 200 {
-                return Err(anyhow!("{}", response.error.unwrap()));
+        } else if !remove_ips.is_empty() {
+            return Err(anyhow!("当前IP模式和线上不一致，无法移除IP！"));
+        }
+        if ips.is_empty() {
+            check_response(self.ip_acl(vec![], IpACLType::Blank, domain).await?)?;
+            return Ok(IpAclChange::Emptied);
+        }
+        let count = ips.len();
+        check_response(self.ip_acl(ips, ip_acltype, domain).await?)?;
+        Ok(IpAclChange::Updated(count))
+    }
+
+    pub async fn set_ip_acl(
+        &self,
+        black: bool,
+        white: bool,
+        close: bool,
+        ips: &str,
+        rewrite: bool,
+        domain: &str,
+    ) -> Result<usize, anyhow::Error> {
+        let change = self
+            .update_ip_acl(black, white, close, ips, rewrite, domain)
+            .await?;
+        match change {
+            IpAclChange::Closed => println!("{}", "关闭成功 ✅".green()),
+            IpAclChange::Unchanged => println!("{}", "与线上配置相同，跳过设置！".yellow()),
+            IpAclChange::Emptied => {
+                println!("[WARN] {}", "ip列表为空，将IP黑/白名单关闭".yellow());
+                println!("{}", "操作成功 ✅".green());
             }
-            println!("{}", "操作成功 ✅".green());
+            IpAclChange::Updated(_) => println!("{}", "操作成功 ✅".green()),
         }
-        Ok(ips.len())
+        Ok(change.count())
     }
 
     pub async fn diagnose_ip(
@@ -425,7 +468,7 @@ impl Client {
             if ps.len() != 3 {
                 print_err("policy字段配置错误", true);
             }
-            if *ps.first().unwrap() != "T" && *ps.first().unwrap() != "C" {
+            if !["T", "C", "B"].contains(ps.first().unwrap()) {
                 print_err("policy字段配置错误", true);
             }
             let mut filter_type = FilterType::Traffic;
@@ -442,6 +485,25 @@ impl Client {
                 print_err("policy字段配置错误", true);
             }
             let start_date = start_dt.format("%Y-%m-%d").to_string();
+            // 按UA识别的采集类请求次数, 需要下载日志
+            if *ps.first().unwrap() == "B" {
+                let classes = match self.config.blackip.bot_classes.as_deref() {
+                    Some(classes) => UaClass::parse_list(classes)?,
+                    None => UaClass::SCRAPING.to_vec(),
+                };
+                let log_client = Client::new(&self.config, crate::SubFunctionEnum::Log);
+                let ip_result = log_client
+                    .bot_ips(&classes, num as u64, &start_date, day, domain)
+                    .await?;
+                if self.config.debug.unwrap_or(false) {
+                    println!(
+                        "start_date: {} end_date: {} num: {} days: {}, mode: Bot",
+                        start_date, day, num, days
+                    );
+                }
+                ips.push(ip_result);
+                continue;
+            }
             let response = analysis_client
                 .top_ip(
                     "global",
@@ -550,9 +612,13 @@ impl Client {
             return Ok(());
         }
         println!("{}", "诊断出符合条件的IP: ".green());
+        let ipdb = IpDb::from_config_or_warn(&self.config);
         let mut ipss = vec![];
         for ip in ips {
-            println!("{}", ip);
+            match ipdb.as_ref().and_then(|x| x.lookup_str(&ip)) {
+                Some(info) => println!("{} {}", ip, info),
+                None => println!("{}", ip),
+            }
             ipss.push(ip);
         }
         // 不设置黑名单
@@ -0,0 +1,435 @@
+//! Prometheus指标
+
+use std::{fmt::Write, sync::Arc, time::Duration};
+
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use chrono::Local;
+use colored::Colorize;
+use tokio::{net::TcpListener, sync::RwLock};
+
+use crate::{
+    analysis::Freq,
+    config::Config,
+    utils::{get_domains, parse_point_time, print_err, qiniu_now},
+    Client, SubFunctionEnum,
+};
+
+/// 5分钟粒度的秒数，用于计算带宽
+const FIVE_MINUTES: f64 = 300.0;
+
+#[derive(Debug, Clone)]
+struct Sample {
+    name: &'static str,
+    help: &'static str,
+    labels: Vec<(&'static str, String)>,
+    value: f64,
+}
+
+/// 一次采集的所有指标
+#[derive(Debug, Default)]
+pub struct Metrics {
+    samples: Vec<Sample>,
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Metrics {
+    pub fn gauge(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        labels: Vec<(&'static str, String)>,
+        value: f64,
+    ) {
+        self.samples.push(Sample {
+            name,
+            help,
+            labels,
+            value,
+        });
+    }
+
+    /// 输出Prometheus文本格式
+    pub fn render(&self) -> String {
+        let mut names: Vec<&'static str> = vec![];
+        for s in self.samples.iter() {
+            if !names.contains(&s.name) {
+                names.push(s.name);
+            }
+        }
+        let mut out = String::new();
+        for name in names {
+            let samples: Vec<&Sample> = self.samples.iter().filter(|s| s.name == name).collect();
+            writeln!(out, "# HELP {} {}", name, samples[0].help).unwrap();
+            writeln!(out, "# TYPE {} gauge", name).unwrap();
+            for s in samples {
+                let labels: Vec<String> = s
+                    .labels
+                    .iter()
+                    .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+                    .collect();
+                if labels.is_empty() {
+                    writeln!(out, "{} {}", name, s.value).unwrap();
+                } else {
+                    writeln!(out, "{}{{{}}} {}", name, labels.join(","), s.value).unwrap();
+                }
+            }
+        }
+        out
+    }
+}
+
+/// 最近一个已发布(有数据且不晚于当前时间)的点及其下标
+fn latest_index(points: &[String], values: &[i64]) -> Option<usize> {
+    let now = qiniu_now().naive_local();
+    (0..points.len()).rev().find(|i| {
+        values.get(*i).is_some_and(|v| *v > 0)
+            && parse_point_time(&points[*i]).is_some_and(|t| t <= now)
+    })
+}
+
+/// 采集单个域名的指标，返回是否全部采集成功
+async fn collect_domain(config: &Config, domain: &str, metrics: &mut Metrics) -> bool {
+    let today = qiniu_now().format("%Y-%m-%d").to_string();
+    let domains = vec![domain.to_string()];
+    let label = || vec![("domain", domain.to_string())];
+    let mut success = true;
+
+    // 计费流量
+    let client = Client::new(config, SubFunctionEnum::Traffic);
+    match client.charge_traffic(&today, &today, "5min", domain).await {
+        Ok(response) => {
+            let points = response.time.unwrap_or_default();
+            if let Some(data) = response.data.and_then(|x| x.get(domain).cloned()) {
+                for (region, values) in [("china", data.china), ("oversea", data.oversea)] {
+                    let values = values.unwrap_or_default();
+                    let mut region_label = label();
+                    region_label.push(("region", region.to_string()));
+                    let latest = latest_index(&points, &values)
+                        .map(|i| values[i] as f64)
+                        .unwrap_or(0.0);
+                    metrics.gauge(
+                        "qiniu_cdn_traffic_bytes",
+                        "Traffic of the latest published 5 minute bucket",
+                        region_label.clone(),
+                        latest,
+                    );
+                    metrics.gauge(
+                        "qiniu_cdn_bandwidth_bps",
+                        "Average bandwidth of the latest published 5 minute bucket",
+                        region_label.clone(),
+                        latest * 8.0 / FIVE_MINUTES,
+                    );
+                    metrics.gauge(
+                        "qiniu_cdn_traffic_today_bytes",
+                        "Traffic of today",
+                        region_label,
+                        values.iter().sum::<i64>() as f64,
+                    );
+                }
+            }
+        }
+        Err(e) => {
+            print_err(format!("{} 流量采集失败: {}", domain, e).as_str(), false);
+            success = false;
+        }
+    }
+
+    // 请求次数
+    let client = Client::new(config, SubFunctionEnum::AnalysisCount);
+    match client
+        .req_count(Freq::FiveMin, "global", &today, &today, domains.clone())
+        .await
+    {
+        Ok(response) => {
+            if let Some(data) = response.data {
+                let latest = latest_index(&data.points, &data.req_count)
+                    .map(|i| data.req_count[i] as f64)
+                    .unwrap_or(0.0);
+                metrics.gauge(
+                    "qiniu_cdn_requests",
+                    "Requests of the latest published 5 minute bucket",
+                    label(),
+                    latest,
+                );
+                metrics.gauge(
+                    "qiniu_cdn_requests_today",
+                    "Requests of today",
+                    label(),
+                    data.req_count.iter().sum::<i64>() as f64,
+                );
+            }
+        }
+        Err(e) => {
+            print_err(
+                format!("{} 请求次数采集失败: {}", domain, e).as_str(),
+                false,
+            );
+            success = false;
+        }
+    }
+
+    // 状态码
+    let client = Client::new(config, SubFunctionEnum::AnalysisStatus);
+    match client
+        .status_code(
+            Freq::OneDay,
+            "global",
+            "all",
+            &today,
+            &today,
+            domains.clone(),
+        )
+        .await
+    {
+        Ok(response) => {
+            if let Some(data) = response.data {
+                for (code, values) in data.codes {
+                    let mut code_label = label();
+                    code_label.push(("code", code));
+                    metrics.gauge(
+                        "qiniu_cdn_status_code_requests_today",
+                        "Requests of today by status code",
+                        code_label,
+                        values.iter().sum::<i64>() as f64,
+                    );
+                }
+            }
+        }
+        Err(e) => {
+            print_err(format!("{} 状态码采集失败: {}", domain, e).as_str(), false);
+            success = false;
+        }
+    }
+
+    // 命中率
+    let client = Client::new(config, SubFunctionEnum::AnalysisHitmiss);
+    match client
+        .hit_miss(Freq::OneDay, &today, &today, domains.clone())
+        .await
+    {
+        Ok(response) => {
+            if let Some(data) = response.data {
+                let total = data.total(0, data.points.len());
+                for (result, requests, traffic) in [
+                    ("hit", total.hit, total.traffic_hit),
+                    ("miss", total.miss, total.traffic_miss),
+                ] {
+                    let mut result_label = label();
+                    result_label.push(("result", result.to_string()));
+                    metrics.gauge(
+                        "qiniu_cdn_cache_requests_today",
+                        "Requests of today by cache result",
+                        result_label.clone(),
+                        requests as f64,
+                    );
+                    metrics.gauge(
+                        "qiniu_cdn_cache_traffic_today_bytes",
+                        "Traffic of today by cache result",
+                        result_label,
+                        traffic as f64,
+                    );
+                }
+                for (kind, ratio) in [
+                    ("requests", total.request_ratio()),
+                    ("traffic", total.traffic_ratio()),
+                ] {
+                    if let Some(ratio) = ratio {
+                        let mut kind_label = label();
+                        kind_label.push(("kind", kind.to_string()));
+                        metrics.gauge(
+                            "qiniu_cdn_cache_hit_ratio",
+                            "Cache hit ratio of today (0-1)",
+                            kind_label,
+                            ratio / 100.0,
+                        );
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            print_err(format!("{} 命中率采集失败: {}", domain, e).as_str(), false);
+            success = false;
+        }
+    }
+
+    // 运营商流量占比
+    let client = Client::new(config, SubFunctionEnum::AnalysisIsp);
+    match client
+        .isp_traffic_ratio("global", &today, &today, domains)
+        .await
+    {
+        Ok(response) => {
+            for (isp, ratio) in response.data.unwrap_or_default() {
+                let mut isp_label = label();
+                isp_label.push(("isp", isp));
+                metrics.gauge(
+                    "qiniu_cdn_isp_traffic_share",
+                    "Traffic share of today by ISP (0-1)",
+                    isp_label,
+                    ratio / 100.0,
+                );
+            }
+        }
+        Err(e) => {
+            print_err(
+                format!("{} 运营商占比采集失败: {}", domain, e).as_str(),
+                false,
+            );
+            success = false;
+        }
+    }
+
+    // IP黑白名单
+    let client = Client::new(config, SubFunctionEnum::Domain);
+    match client.domain_info(domain).await {
+        Ok(response) => {
+            if let Some(acl) = response.ip_acl {
+                let mut acl_label = label();
+                let acl_type = if acl.ip_acltype.is_empty() {
+                    "none".to_string()
+                } else {
+                    acl.ip_acltype
+                };
+                acl_label.push(("type", acl_type));
+                metrics.gauge(
+                    "qiniu_cdn_ip_acl_entries",
+                    "Number of IP ACL entries",
+                    acl_label,
+                    acl.ip_aclvalues.len() as f64,
+                );
+            }
+        }
+        Err(e) => {
+            print_err(
+                format!("{} 域名信息采集失败: {}", domain, e).as_str(),
+                false,
+            );
+            success = false;
+        }
+    }
+    success
+}
+
+/// 采集所有域名的指标
+pub async fn collect(config: &Config, domains: &[String]) -> Metrics {
+    let mut metrics = Metrics::default();
+    for domain in domains {
+        if config.debug.unwrap_or(false) {
+            println!("[DEBUG] exporter collect domain: {}", domain);
+        }
+        let success = collect_domain(config, domain, &mut metrics).await;
+        metrics.gauge(
+            "qiniu_cdn_exporter_collect_success",
+            "Whether the last collection of the domain succeeded",
+            vec![("domain", domain.to_string())],
+            if success { 1.0 } else { 0.0 },
+        );
+    }
+    metrics.gauge(
+        "qiniu_cdn_exporter_last_collect_timestamp_seconds",
+        "Unix time of the last collection",
+        vec![],
+        Local::now().timestamp() as f64,
+    );
+    metrics
+}
+
+async fn metrics_handler(State(body): State<Arc<RwLock<String>>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        body.read().await.clone(),
+    )
+}
+
+/// 按[exporter]配置定时采集指标，并通过`/metrics`暴露
+pub async fn serve_exporter(
+    config: Config,
+    listen: Option<String>,
+    domains: Option<String>,
+    domain_exclude: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let exporter_config = config.exporter.clone();
+    let listen = listen
+        .or(exporter_config.as_ref().and_then(|x| x.listen.clone()))
+        .unwrap_or("0.0.0.0:9527".to_string());
+    let interval = exporter_config
+        .as_ref()
+        .and_then(|x| x.interval)
+        .unwrap_or(300);
+    let domains = domains.or(exporter_config.as_ref().and_then(|x| x.domains.clone()));
+    let domain_exclude = domain_exclude.or(exporter_config
+        .as_ref()
+        .and_then(|x| x.domain_exclude.clone()));
+    let body = Arc::new(RwLock::new(String::new()));
+    let body_ = body.clone();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            let domain_list = match get_domains(&config, &domain_exclude, &domains).await {
+                Ok(k) => k,
+                Err(e) => {
+                    print_err(format!("获取域名列表失败: {}", e).as_str(), false);
+                    continue;
+                }
+            };
+            let metrics = collect(&config, &domain_list).await;
+            *body_.write().await = metrics.render();
+        }
+    });
+    let app = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .with_state(body);
+    let listener = TcpListener::bind(&listen).await?;
+    println!(
+        "{}{}",
+        "Prometheus指标地址: ".green().bold(),
+        format!("http://{}/metrics", listen).bold()
+    );
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_render_test() {
+        let mut metrics = Metrics::default();
+        metrics.gauge(
+            "qiniu_cdn_requests",
+            "Requests",
+            vec![("domain", "a.com".to_string())],
+            10.0,
+        );
+        metrics.gauge(
+            "qiniu_cdn_requests",
+            "Requests",
+            vec![("domain", "b\"c".to_string())],
+            2.5,
+        );
+        metrics.gauge("qiniu_cdn_up", "Up", vec![], 1.0);
+        assert_eq!(
+            metrics.render(),
+            "# HELP qiniu_cdn_requests Requests\n# TYPE qiniu_cdn_requests gauge\nqiniu_cdn_requests{domain=\"a.com\"} 10\nqiniu_cdn_requests{domain=\"b\\\"c\"} 2.5\n# HELP qiniu_cdn_up Up\n# TYPE qiniu_cdn_up gauge\nqiniu_cdn_up 1\n"
+        );
+    }
+
+    #[test]
+    fn latest_index_test() {
+        let points = vec![
+            "2024-07-16-00-00-00".to_string(),
+            "2024-07-16-00-05-00".to_string(),
+            "2099-07-16-00-10-00".to_string(),
+        ];
+        assert_eq!(latest_index(&points, &[1, 0, 3]), Some(0));
+        assert_eq!(latest_index(&points, &[0, 0, 0]), None);
+    }
+}
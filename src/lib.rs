@@ -5,10 +5,16 @@ use config::Config;
 
 pub mod analysis;
 pub mod config;
+pub mod daemon;
+pub mod dashboard;
 pub mod domain;
+pub mod exporter;
 pub mod log;
 pub mod prefetch;
+pub mod push;
 pub mod refresh;
+pub mod serve;
+pub mod store;
 pub mod traffic;
 pub mod utils;
 
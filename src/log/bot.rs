@@ -0,0 +1,543 @@
+//! 爬虫识别
+//!
+//! 按UserAgent把请求分为浏览器、搜索引擎爬虫、SEO/采集工具、HTTP库、下载工具、空UA及其他，
+//! 统计各类的请求次数、流量及TOP IP，diagnostic可以据此筛选采集类的IP
+
+#![allow(clippy::too_many_arguments)]
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+    net::IpAddr,
+    ops::Div,
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+use colored::Colorize;
+use futures::StreamExt;
+
+use super::{
+    query::Expr,
+    record::LogRecord,
+    stream::{LogSource, ParseErrors},
+};
+use crate::{
+    utils::{ipdb::IpDb, max_length, pad, WaitBlinker},
+    Client,
+};
+
+/// 已知的搜索引擎爬虫
+const SEARCH_CRAWLERS: [&str; 19] = [
+    "googlebot",
+    "google-inspectiontool",
+    "bingbot",
+    "baiduspider",
+    "yandexbot",
+    "duckduckbot",
+    "slurp",
+    // 搜狗浏览器的UA中也有sogou
+    "sogou web spider",
+    "sogou inst spider",
+    "sogou pic spider",
+    "360spider",
+    "haosouspider",
+    "yisouspider",
+    "bytespider",
+    "applebot",
+    "petalbot",
+    "yodaobot",
+    "facebookexternalhit",
+    "twitterbot",
+];
+
+/// SEO分析及采集工具, 包括无头浏览器
+const SCRAPERS: [&str; 14] = [
+    "ahrefsbot",
+    "semrushbot",
+    "mj12bot",
+    "dotbot",
+    "blexbot",
+    "dataforseobot",
+    "screaming frog",
+    "scrapy",
+    "httrack",
+    "headlesschrome",
+    "phantomjs",
+    "puppeteer",
+    "selenium",
+    "playwright",
+];
+
+/// HTTP库及命令行工具, 不包括okhttp、Java及Dart等App默认的UA, 避免把App用户当成采集
+const HTTP_LIBRARIES: [&str; 12] = [
+    "curl",
+    "python-requests",
+    "python-urllib",
+    "python-httpx",
+    "aiohttp",
+    "go-http-client",
+    "apache-httpclient",
+    "node-fetch",
+    "axios",
+    "undici",
+    "reqwest",
+    "libwww-perl",
+];
+
+/// 下载工具
+const DOWNLOAD_MANAGERS: [&str; 10] = [
+    "wget",
+    "aria2",
+    "axel",
+    "thunder",
+    "xunlei",
+    "internet download manager",
+    "idman",
+    "flashget",
+    "qqdownload",
+    "bitcomet",
+];
+
+/// 浏览器UA中的标识
+const BROWSERS: [&str; 8] = [
+    "chrome", "safari", "firefox", "edg", "opera", "msie", "trident", "mobile",
+];
+
+/// 未知的爬虫: 产品名(`name/version`中的name)包含bot/spider/crawl, 或带有`+http`说明地址的同类标识,
+/// 只看产品名避免把CUBOT等手机型号识别为爬虫
+fn is_unknown_crawler(ua: &str) -> bool {
+    let is_crawler = |x: &str| ["bot", "spider", "crawl"].iter().any(|k| x.contains(k));
+    let has_url = ua.contains("+http");
+    ua.split([' ', ';', '(', ')', ','])
+        .filter(|x| !x.is_empty())
+        .any(|token| match token.split_once('/') {
+            Some((name, _)) => is_crawler(name),
+            None => has_url && is_crawler(token),
+        })
+}
+
+/// UserAgent分类
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum UaClass {
+    Browser,
+    SearchCrawler,
+    Scraper,
+    HttpLibrary,
+    DownloadManager,
+    Empty,
+    Other,
+}
+
+impl UaClass {
+    pub const ALL: [UaClass; 7] = [
+        UaClass::Browser,
+        UaClass::SearchCrawler,
+        UaClass::Scraper,
+        UaClass::HttpLibrary,
+        UaClass::DownloadManager,
+        UaClass::Empty,
+        UaClass::Other,
+    ];
+
+    /// diagnostic默认针对的分类
+    pub const SCRAPING: [UaClass; 4] = [
+        UaClass::Scraper,
+        UaClass::HttpLibrary,
+        UaClass::DownloadManager,
+        UaClass::Empty,
+    ];
+
+    pub fn classify(ua: &str) -> UaClass {
+        let ua = ua.trim().to_lowercase();
+        let contains = |list: &[&str]| list.iter().any(|x| ua.contains(x));
+        if ua.is_empty() || ua == "-" {
+            UaClass::Empty
+        } else if contains(&SEARCH_CRAWLERS) {
+            UaClass::SearchCrawler
+        } else if contains(&SCRAPERS) {
+            UaClass::Scraper
+        } else if contains(&HTTP_LIBRARIES) {
+            UaClass::HttpLibrary
+        } else if contains(&DOWNLOAD_MANAGERS) {
+            UaClass::DownloadManager
+        } else if is_unknown_crawler(&ua) {
+            // 未知的爬虫按采集工具处理
+            UaClass::Scraper
+        } else if ua.starts_with("mozilla/") && contains(&BROWSERS) {
+            UaClass::Browser
+        } else {
+            UaClass::Other
+        }
+    }
+
+    pub fn parse_list(s: &str) -> Result<Vec<UaClass>, anyhow::Error> {
+        s.split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(UaClass::from_str)
+            .collect()
+    }
+
+    pub fn code(&self) -> &str {
+        match self {
+            UaClass::Browser => "browser",
+            UaClass::SearchCrawler => "crawler",
+            UaClass::Scraper => "scraper",
+            UaClass::HttpLibrary => "library",
+            UaClass::DownloadManager => "downloader",
+            UaClass::Empty => "empty",
+            UaClass::Other => "other",
+        }
+    }
+}
+
+impl FromStr for UaClass {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        UaClass::ALL
+            .into_iter()
+            .find(|x| x.code() == s.to_lowercase())
+            .ok_or(anyhow!(
+                "无效的UA分类: {}, 可选browser crawler scraper library downloader empty other",
+                s
+            ))
+    }
+}
+
+impl Display for UaClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            UaClass::Browser => "浏览器",
+            UaClass::SearchCrawler => "搜索引擎爬虫",
+            UaClass::Scraper => "SEO/采集工具",
+            UaClass::HttpLibrary => "HTTP库",
+            UaClass::DownloadManager => "下载工具",
+            UaClass::Empty => "空UA",
+            UaClass::Other => "其他",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// 各分类的请求次数、字节数及IP
+#[derive(Debug, Default)]
+pub struct BotReport {
+    pub count: u64,
+    pub bytes: u64,
+    pub classes: BTreeMap<UaClass, (u64, u64)>,
+    pub ips: HashMap<UaClass, HashMap<IpAddr, (u64, u64)>>,
+}
+
+impl BotReport {
+    pub fn add(&mut self, record: &LogRecord) {
+        let class = UaClass::classify(&record.user_agent);
+        self.count += 1;
+        self.bytes += record.bytes;
+        let v = self.classes.entry(class).or_default();
+        v.0 += 1;
+        v.1 += record.bytes;
+        let v = self
+            .ips
+            .entry(class)
+            .or_default()
+            .entry(record.ip)
+            .or_default();
+        v.0 += 1;
+        v.1 += record.bytes;
+    }
+
+    pub fn merge(&mut self, other: BotReport) {
+        self.count += other.count;
+        self.bytes += other.bytes;
+        for (class, (count, bytes)) in other.classes {
+            let v = self.classes.entry(class).or_default();
+            v.0 += count;
+            v.1 += bytes;
+        }
+        for (class, ips) in other.ips {
+            let t = self.ips.entry(class).or_default();
+            for (ip, (count, bytes)) in ips {
+                let v = t.entry(ip).or_default();
+                v.0 += count;
+                v.1 += bytes;
+            }
+        }
+    }
+
+    /// 分类下按请求次数或字节数倒序的前`limit`个IP
+    pub fn top_ips(&self, class: UaClass, limit: usize, by_bytes: bool) -> Vec<(IpAddr, u64, u64)> {
+        let mut rows: Vec<(IpAddr, u64, u64)> = self
+            .ips
+            .get(&class)
+            .map(|t| t.iter().map(|(k, v)| (*k, v.0, v.1)).collect())
+            .unwrap_or_default();
+        if by_bytes {
+            rows.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        } else {
+            rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        }
+        rows.truncate(limit);
+        rows
+    }
+
+    /// 各分类请求次数合计达到`min_count`的IP
+    pub fn ips_over(&self, classes: &[UaClass], min_count: u64) -> HashSet<String> {
+        let mut counts: HashMap<IpAddr, u64> = HashMap::new();
+        for class in classes {
+            for (ip, (count, _)) in self.ips.get(class).into_iter().flatten() {
+                *counts.entry(*ip).or_default() += count;
+            }
+        }
+        counts
+            .into_iter()
+            .filter(|(_, c)| *c >= min_count)
+            .map(|(ip, _)| ip.to_string())
+            .collect()
+    }
+}
+
+impl Client {
+    async fn bot_report(
+        &self,
+        query: Option<Expr>,
+        start_date: &str,
+        end_date: &str,
+        source: &LogSource,
+        domain: &str,
+    ) -> Result<(BotReport, ParseErrors), anyhow::Error> {
+        let logs = self.log_files(source, start_date, end_date, domain).await?;
+        let mut results = self.log_stream(logs, move |records| {
+            let mut report = BotReport::default();
+            for record in records.filter(|x| query.as_ref().is_none_or(|e| e.matches(x))) {
+                report.add(&record);
+            }
+            report
+        });
+        let mut report = BotReport::default();
+        let mut errors = ParseErrors::default();
+        while let Some(result) = results.next().await {
+            let result = result?;
+            errors.add(&result);
+            report.merge(result.value);
+        }
+        Ok((report, errors))
+    }
+
+    /// 按UserAgent分类统计请求, 并列出各分类的TOP IP
+    pub async fn log_bot(
+        self,
+        query: Option<Expr>,
+        limit: Option<i32>,
+        by_bytes: bool,
+        start_date: &str,
+        end_date: &str,
+        wait_blink: Option<WaitBlinker>,
+        source: &LogSource,
+        domain: &str,
+    ) -> Result<(), anyhow::Error> {
+        let (report, errors) = self
+            .bot_report(query, start_date, end_date, source, domain)
+            .await?;
+        if let Some(blinker) = wait_blink {
+            blinker.sender.send(true).unwrap();
+            blinker.handle.await?;
+        }
+        errors.report(self.config.debug.unwrap_or(false));
+        println!(
+            "域名 {} {} 爬虫识别, 请求次数: {}, 流量: {}MB",
+            domain.bold().yellow(),
+            source.describe(start_date, end_date).bold(),
+            report.count.to_string().bold(),
+            format!("{:.4}", (report.bytes as f64).div(1024.0).div(1024.0)).bold(),
+        );
+        // IP的归属地附加在最后一列
+        let row = |key: &str, count: u64, bytes: u64, width: usize, location: &str| {
+            println!(
+                "{} {:^10} {:^10} {:^16} {:^10}{}",
+                pad(key, width),
+                count,
+                format!("{:.2}%", count as f64 / report.count.max(1) as f64 * 100.0),
+                format!("{:.4}", (bytes as f64).div(1024.0).div(1024.0)),
+                format!("{:.2}%", bytes as f64 / report.bytes.max(1) as f64 * 100.0),
+                location,
+            );
+        };
+        let header = |key: &str, width: usize, location: &str| {
+            println!(
+                "{} {:^10} {:^10} {:^16} {:^10}{}",
+                pad(key, width).bold().green(),
+                "Count".bold().green(),
+                "Percent".bold().green(),
+                "Traffic(MB)".bold().green(),
+                "Percent".bold().green(),
+                location.bold().green(),
+            );
+        };
+        println!();
+        header("Class", 24, "");
+        for (class, (count, bytes)) in report.classes.iter() {
+            row(
+                &format!("{}({})", class, class.code()),
+                *count,
+                *bytes,
+                24,
+                "",
+            );
+        }
+        let ipdb = IpDb::from_config_or_warn(&self.config);
+        let limit = limit.unwrap_or(10).max(0) as usize;
+        for class in report.classes.keys() {
+            let rows = report.top_ips(*class, limit, by_bytes);
+            let width = max_length(&rows.iter().map(|x| x.0.to_string()), limit as i32).max(24);
+            println!();
+            header(
+                &format!("{} IP", class),
+                width,
+                if ipdb.is_some() { " Location" } else { "" },
+            );
+            for (ip, count, bytes) in rows {
+                let location = match ipdb.as_ref() {
+                    Some(ipdb) => format!(
+                        " {}",
+                        ipdb.lookup(ip)
+                            .map(|x| x.to_string())
+                            .unwrap_or("未知".to_string())
+                    ),
+                    None => "".to_string(),
+                };
+                row(&ip.to_string(), count, bytes, width, &location);
+            }
+        }
+        Ok(())
+    }
+
+    /// 诊断疑似IP: `start_date`到`end_date`期间`classes`分类的请求次数达到`min_count`的IP
+    pub async fn bot_ips(
+        &self,
+        classes: &[UaClass],
+        min_count: u64,
+        start_date: &str,
+        end_date: &str,
+        domain: &str,
+    ) -> Result<HashSet<String>, anyhow::Error> {
+        let (report, errors) = self
+            .bot_report(None, start_date, end_date, &LogSource::Api, domain)
+            .await?;
+        errors.report(self.config.debug.unwrap_or(false));
+        Ok(report.ips_over(classes, min_count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_test() {
+        let cases = [
+            (
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0 Safari/537.36",
+                UaClass::Browser,
+            ),
+            (
+                "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)",
+                UaClass::SearchCrawler,
+            ),
+            (
+                "Mozilla/5.0 (compatible; Baiduspider/2.0; +http://www.baidu.com/search/spider.html)",
+                UaClass::SearchCrawler,
+            ),
+            (
+                "Mozilla/5.0 (compatible; AhrefsBot/7.0; +http://ahrefs.com/robot/)",
+                UaClass::Scraper,
+            ),
+            (
+                "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) HeadlessChrome/120.0 Safari/537.36",
+                UaClass::Scraper,
+            ),
+            ("MyCrawler/1.0", UaClass::Scraper),
+            (
+                "Mozilla/5.0 (compatible; FooBot; +http://foo.com/bot.html)",
+                UaClass::Scraper,
+            ),
+            (
+                "Sogou web spider/4.0(+http://www.sogou.com/docs/help/webmasters.htm#07)",
+                UaClass::SearchCrawler,
+            ),
+            // CUBOT手机及搜狗浏览器
+            (
+                "Mozilla/5.0 (Linux; Android 10; CUBOT NOTE 7 Build/QP1A.190711.020) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0 Mobile Safari/537.36",
+                UaClass::Browser,
+            ),
+            (
+                "Mozilla/5.0 (Linux; Android 12; CUBOT) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0 Mobile Safari/537.36",
+                UaClass::Browser,
+            ),
+            (
+                "Mozilla/5.0 (Linux; Android 11; V2055A) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/87.0 Mobile Safari/537.36 SogouMobileBrowser/11.8.0",
+                UaClass::Browser,
+            ),
+            (
+                "Mozilla/5.0 (Windows NT 10.0; WOW64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/86.0 Safari/537.36 SE 2.X MetaSr 1.0",
+                UaClass::Browser,
+            ),
+            (
+                "Dalvik/2.1.0 (Linux; U; Android 9; CUBOT P30 Build/PPR1.180610.011)",
+                UaClass::Other,
+            ),
+            ("curl/8.4.0", UaClass::HttpLibrary),
+            ("python-requests/2.31.0", UaClass::HttpLibrary),
+            ("Go-http-client/1.1", UaClass::HttpLibrary),
+            // App内置的HTTP库
+            ("okhttp/4.12.0", UaClass::Other),
+            ("Dalvik/2.1.0 (Linux; U; Android 13) okhttp/3.12.1", UaClass::Other),
+            ("Java/17.0.2", UaClass::Other),
+            ("Wget/1.21.4", UaClass::DownloadManager),
+            ("aria2/1.36.0", UaClass::DownloadManager),
+            ("-", UaClass::Empty),
+            ("", UaClass::Empty),
+            ("Dalvik/2.1.0 (Linux; U; Android 13)", UaClass::Other),
+        ];
+        for (ua, class) in cases {
+            assert_eq!(UaClass::classify(ua), class, "{}", ua);
+        }
+        assert_eq!(
+            UaClass::parse_list("library, empty").unwrap(),
+            vec![UaClass::HttpLibrary, UaClass::Empty]
+        );
+        assert!(UaClass::parse_list("robot").is_err());
+
+        let line = |ip: &str, ua: &str| {
+            format!(
+                r#"{} HIT 0 [16/Jul/2024:00:00:01 +0800] "GET http://a.com/a.mp4 HTTP/1.1" 200 100 "-" "{}""#,
+                ip, ua
+            )
+            .parse::<LogRecord>()
+            .unwrap()
+        };
+        let mut report = BotReport::default();
+        let mut other = BotReport::default();
+        for _ in 0..3 {
+            report.add(&line("1.1.1.1", "curl/8.4.0"));
+            other.add(&line("1.1.1.1", "-"));
+            other.add(&line("2.2.2.2", "Wget/1.21"));
+        }
+        report.add(&line("3.3.3.3", "Mozilla/5.0 (iPhone) Mobile Safari"));
+        report.merge(other);
+        assert_eq!(report.count, 10);
+        assert_eq!(report.classes[&UaClass::HttpLibrary], (3, 300));
+        assert_eq!(
+            report.top_ips(UaClass::DownloadManager, 10, false),
+            vec![("2.2.2.2".parse().unwrap(), 3, 300)]
+        );
+        // 同一IP在多个分类中的请求合计
+        assert_eq!(
+            report.ips_over(&UaClass::SCRAPING, 6),
+            HashSet::from(["1.1.1.1".to_string()])
+        );
+        assert_eq!(report.ips_over(&UaClass::SCRAPING, 3).len(), 2);
+        assert!(report.ips_over(&[UaClass::Browser], 2).is_empty());
+    }
+}
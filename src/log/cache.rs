@@ -0,0 +1,206 @@
+//! 日志缓存
+//!
+//! 下载的日志按`缓存目录/md5/文件名`保存，读取时更新修改时间，
+//! 超过上限时按修改时间从旧到新淘汰。下载先写入唯一的`.part`文件再重命名，
+//! 多个进程同时运行也不会读到不完整的文件
+
+use std::{
+    env,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use super::LogData;
+use crate::{config::Config, utils::random_u64};
+
+/// 默认缓存上限(MB)
+const DEFAULT_MAX_SIZE: u64 = 2048;
+
+/// 超过该时间的`.part`文件视为中断后残留
+const STALE_PART: Duration = Duration::from_secs(24 * 3600);
+
+/// 缓存目录, 优先使用`$XDG_CACHE_HOME`, 默认`$HOME/.cache/qiniu-cdn-manager`
+pub fn cache_dir() -> PathBuf {
+    match env::var("XDG_CACHE_HOME") {
+        Ok(k) if !k.is_empty() => PathBuf::from(k),
+        _ => PathBuf::from(env::var("HOME").unwrap_or(".".to_string())).join(".cache"),
+    }
+    .join("qiniu-cdn-manager")
+}
+
+/// 缓存中的文件
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+impl CacheEntry {
+    fn is_part(&self) -> bool {
+        self.path.extension().is_some_and(|x| x == "part")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LogCache {
+    pub dir: PathBuf,
+    /// 上限(字节)
+    pub max_size: u64,
+}
+
+impl LogCache {
+    pub fn new(config: &Config) -> Self {
+        let cache = config.cache.as_ref();
+        Self {
+            dir: cache
+                .and_then(|x| x.dir.clone())
+                .map(PathBuf::from)
+                .unwrap_or(cache_dir().join("logs")),
+            max_size: cache.and_then(|x| x.max_size).unwrap_or(DEFAULT_MAX_SIZE) * 1024 * 1024,
+        }
+    }
+
+    /// 日志在缓存中的路径
+    pub fn path(&self, md5: &str, name: &str) -> PathBuf {
+        self.dir.join(md5).join(LogData::base_name(name))
+    }
+
+    /// 已缓存时返回路径, 并更新修改时间
+    pub fn get(&self, md5: &str, name: &str) -> Option<PathBuf> {
+        let path = self.path(md5, name);
+        if !path.is_file() {
+            return None;
+        }
+        let _ = File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|f| f.set_modified(SystemTime::now()));
+        Some(path)
+    }
+
+    /// 写入`path`前使用的临时文件, 每次调用都不同
+    pub fn part_path(&self, path: &Path) -> PathBuf {
+        PathBuf::from(format!("{}.{:x}.part", path.display(), random_u64()))
+    }
+
+    /// 缓存中的所有文件, 按修改时间从旧到新排序
+    pub fn entries(&self) -> Result<Vec<CacheEntry>, anyhow::Error> {
+        let mut entries = vec![];
+        if self.dir.exists() {
+            walk(&self.dir, &mut entries)?;
+        }
+        entries.sort_by_key(|x| x.modified);
+        Ok(entries)
+    }
+
+    /// 文件数及总大小
+    pub fn stats(&self) -> Result<(usize, u64), anyhow::Error> {
+        let entries = self.entries()?;
+        Ok((entries.len(), entries.iter().map(|x| x.size).sum()))
+    }
+
+    /// 淘汰最久未使用的文件直到不超过`max_size`, 同时清理残留的`.part`文件，
+    /// `keep`不会被淘汰，返回删除的文件数及大小
+    pub fn prune(&self, max_size: u64, keep: Option<&Path>) -> Result<(usize, u64), anyhow::Error> {
+        let entries = self.entries()?;
+        let now = SystemTime::now();
+        let mut total: u64 = entries.iter().map(|x| x.size).sum();
+        let (mut count, mut size) = (0, 0);
+        for entry in entries {
+            let stale_part = entry.is_part()
+                && now
+                    .duration_since(entry.modified)
+                    .is_ok_and(|x| x > STALE_PART);
+            if !stale_part && (total <= max_size || entry.is_part()) {
+                continue;
+            }
+            if keep.is_some_and(|x| x == entry.path) {
+                continue;
+            }
+            // 可能已被其他进程删除
+            if fs::remove_file(&entry.path).is_ok() {
+                count += 1;
+                size += entry.size;
+            }
+            total -= entry.size;
+            if let Some(parent) = entry.path.parent() {
+                let _ = fs::remove_dir(parent);
+            }
+        }
+        Ok((count, size))
+    }
+
+    /// 清空缓存
+    pub fn clear(&self) -> Result<(usize, u64), anyhow::Error> {
+        let stats = self.stats()?;
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(stats)
+    }
+}
+
+fn walk(dir: &Path, entries: &mut Vec<CacheEntry>) -> Result<(), anyhow::Error> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let meta = match entry.metadata() {
+            Ok(meta) => meta,
+            Err(_) => continue,
+        };
+        if meta.is_dir() {
+            walk(&entry.path(), entries)?;
+        } else {
+            entries.push(CacheEntry {
+                path: entry.path(),
+                size: meta.len(),
+                modified: meta.modified()?,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::TempDir;
+
+    #[test]
+    fn prune_test() {
+        let dir = TempDir::new("cache-test");
+        let cache = LogCache {
+            dir: dir.join("cache"),
+            max_size: 10,
+        };
+        let now = SystemTime::now();
+        let write = |md5: &str, name: &str, secs: u64| {
+            let path = cache.path(md5, name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "12345").unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(now - Duration::from_secs(secs))
+                .unwrap();
+            path
+        };
+        let a = write("a", "x/a.gz", 300);
+        let b = write("b", "b.gz", 200);
+        let c = write("c", "c.gz", 100);
+        let part = write("d", "d.gz.1.part", 2 * 24 * 3600);
+        assert_eq!(cache.stats().unwrap(), (4, 20));
+        // 读取后a变为最近使用
+        assert_eq!(cache.get("a", "a.gz"), Some(a.clone()));
+        assert_eq!(cache.prune(cache.max_size, None).unwrap(), (2, 10));
+        assert!(a.exists() && c.exists());
+        assert!(!b.exists() && !part.exists());
+        assert!(!cache.dir.join("b").exists());
+        assert_eq!(cache.prune(0, Some(&c)).unwrap(), (1, 5));
+        assert!(c.exists());
+        assert_eq!(cache.clear().unwrap(), (1, 5));
+        assert!(!cache.dir.exists());
+    }
+}
@@ -0,0 +1,530 @@
+//! 日志导出
+//!
+//! 把解析后的日志导出为JSONL、CSV或Parquet，字段固定，可以按日期及域名分区，
+//! 分区目录为`day=2024-07-16/domain=a.com`，数据仓库可以直接按分区加载
+
+#![allow(clippy::too_many_arguments)]
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::anyhow;
+use colored::Colorize;
+use futures::StreamExt;
+use parquet::{
+    basic::Compression,
+    data_type::{BoolType, ByteArray, ByteArrayType, Int32Type, Int64Type},
+    file::{
+        properties::WriterProperties,
+        writer::{SerializedColumnWriter, SerializedFileWriter},
+    },
+    schema::parser::parse_message_type,
+};
+use serde::Serialize;
+
+use super::{
+    query::Expr,
+    record::{CacheStatus, LogRecord},
+    stream::{LogSource, ParseErrors},
+};
+use crate::{utils::print_err, Client, NOT_FOUND_MSG};
+
+/// Parquet每个row group的行数
+const ROW_GROUP_SIZE: usize = 100_000;
+
+/// Parquet的schema, 列的顺序和`ExportRow`一致
+const PARQUET_SCHEMA: &str = "
+message log {
+    REQUIRED BYTE_ARRAY ip (UTF8);
+    REQUIRED INT64 timestamp (TIMESTAMP(MILLIS, true));
+    REQUIRED BYTE_ARRAY method (UTF8);
+    REQUIRED BYTE_ARRAY url (UTF8);
+    REQUIRED BYTE_ARRAY path (UTF8);
+    REQUIRED BYTE_ARRAY query (UTF8);
+    REQUIRED INT32 status;
+    REQUIRED INT64 bytes;
+    REQUIRED INT64 response_time;
+    REQUIRED BYTE_ARRAY referer (UTF8);
+    REQUIRED BYTE_ARRAY user_agent (UTF8);
+    OPTIONAL BOOLEAN hit;
+}";
+
+/// 导出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Jsonl,
+    Csv,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Jsonl => "jsonl",
+            Self::Csv => "csv",
+            Self::Parquet => "parquet",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "jsonl" | "json" => Ok(Self::Jsonl),
+            "csv" => Ok(Self::Csv),
+            "parquet" => Ok(Self::Parquet),
+            _ => Err(anyhow!("不支持的导出格式: {}, 可选jsonl csv parquet", s)),
+        }
+    }
+}
+
+impl Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// 分区字段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Partition {
+    Day,
+    Domain,
+}
+
+impl Partition {
+    pub fn parse_list(s: &str) -> Result<Vec<Partition>, anyhow::Error> {
+        s.split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(Partition::from_str)
+            .collect()
+    }
+}
+
+impl FromStr for Partition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "day" => Ok(Self::Day),
+            "domain" => Ok(Self::Domain),
+            _ => Err(anyhow!("无效的分区字段: {}, 可选day domain", s)),
+        }
+    }
+}
+
+/// 导出的一行
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ExportRow {
+    pub ip: String,
+    /// RFC3339格式, 保留日志中的时区
+    pub timestamp: String,
+    pub method: String,
+    pub url: String,
+    pub path: String,
+    pub query: String,
+    pub status: u16,
+    pub bytes: u64,
+    /// 响应时间(毫秒)
+    pub response_time: u64,
+    pub referer: String,
+    pub user_agent: String,
+    /// 命中状态未知时为空
+    pub hit: Option<bool>,
+    #[serde(skip)]
+    pub timestamp_millis: i64,
+    #[serde(skip)]
+    pub day: String,
+}
+
+impl ExportRow {
+    pub const COLUMNS: [&'static str; 12] = [
+        "ip",
+        "timestamp",
+        "method",
+        "url",
+        "path",
+        "query",
+        "status",
+        "bytes",
+        "response_time",
+        "referer",
+        "user_agent",
+        "hit",
+    ];
+
+    fn csv_values(&self) -> [String; 12] {
+        [
+            self.ip.clone(),
+            self.timestamp.clone(),
+            self.method.clone(),
+            self.url.clone(),
+            self.path.clone(),
+            self.query.clone(),
+            self.status.to_string(),
+            self.bytes.to_string(),
+            self.response_time.to_string(),
+            self.referer.clone(),
+            self.user_agent.clone(),
+            self.hit.map(|x| x.to_string()).unwrap_or_default(),
+        ]
+    }
+}
+
+impl From<LogRecord> for ExportRow {
+    fn from(record: LogRecord) -> Self {
+        Self {
+            ip: record.ip.to_string(),
+            timestamp: record.time.to_rfc3339(),
+            path: record.path().to_string(),
+            query: record.query().to_string(),
+            status: record.status,
+            bytes: record.bytes,
+            response_time: record.response_time,
+            hit: match record.cache {
+                CacheStatus::Hit => Some(true),
+                CacheStatus::Miss => Some(false),
+                CacheStatus::Unknown => None,
+            },
+            timestamp_millis: record.time.timestamp_millis(),
+            day: record.time.format("%Y-%m-%d").to_string(),
+            method: record.method,
+            url: record.url,
+            referer: record.referer,
+            user_agent: record.user_agent,
+        }
+    }
+}
+
+/// CSV字段, 包含逗号、引号或换行时加引号
+pub fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn write_strings<'a>(
+    column: &mut SerializedColumnWriter,
+    values: impl Iterator<Item = &'a String>,
+) -> Result<(), anyhow::Error> {
+    let values: Vec<ByteArray> = values
+        .map(|x| ByteArray::from(x.as_bytes().to_vec()))
+        .collect();
+    column
+        .typed::<ByteArrayType>()
+        .write_batch(&values, None, None)?;
+    Ok(())
+}
+
+enum Writer {
+    Jsonl(BufWriter<File>),
+    Csv(BufWriter<File>),
+    Parquet {
+        writer: SerializedFileWriter<File>,
+        rows: Vec<ExportRow>,
+    },
+}
+
+/// 单个导出文件, 先写入`.part`文件, 完成后重命名
+pub struct ExportFile {
+    path: PathBuf,
+    part: PathBuf,
+    writer: Writer,
+    pub rows: usize,
+}
+
+impl ExportFile {
+    pub fn create(path: PathBuf, format: ExportFormat) -> Result<Self, anyhow::Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let part = PathBuf::from(format!("{}.part", path.display()));
+        let file = File::create(&part)?;
+        let writer = match format {
+            ExportFormat::Jsonl => Writer::Jsonl(BufWriter::new(file)),
+            ExportFormat::Csv => {
+                let mut w = BufWriter::new(file);
+                writeln!(w, "{}", ExportRow::COLUMNS.join(","))?;
+                Writer::Csv(w)
+            }
+            ExportFormat::Parquet => Writer::Parquet {
+                writer: SerializedFileWriter::new(
+                    file,
+                    Arc::new(parse_message_type(PARQUET_SCHEMA)?),
+                    Arc::new(
+                        WriterProperties::builder()
+                            .set_compression(Compression::SNAPPY)
+                            .build(),
+                    ),
+                )?,
+                rows: vec![],
+            },
+        };
+        Ok(Self {
+            path,
+            part,
+            writer,
+            rows: 0,
+        })
+    }
+
+    pub fn write(&mut self, row: ExportRow) -> Result<(), anyhow::Error> {
+        self.rows += 1;
+        match &mut self.writer {
+            Writer::Jsonl(w) => writeln!(w, "{}", serde_json::to_string(&row)?)?,
+            Writer::Csv(w) => writeln!(
+                w,
+                "{}",
+                row.csv_values()
+                    .iter()
+                    .map(|x| csv_field(x))
+                    .collect::<Vec<String>>()
+                    .join(",")
+            )?,
+            Writer::Parquet { rows, .. } => {
+                rows.push(row);
+                if rows.len() >= ROW_GROUP_SIZE {
+                    self.flush_row_group()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_row_group(&mut self) -> Result<(), anyhow::Error> {
+        let Writer::Parquet { writer, rows } = &mut self.writer else {
+            return Ok(());
+        };
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let mut row_group = writer.next_row_group()?;
+        let mut index = 0;
+        while let Some(mut column) = row_group.next_column()? {
+            match index {
+                0 => write_strings(&mut column, rows.iter().map(|x| &x.ip))?,
+                1 => {
+                    let values: Vec<i64> = rows.iter().map(|x| x.timestamp_millis).collect();
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&values, None, None)?;
+                }
+                2 => write_strings(&mut column, rows.iter().map(|x| &x.method))?,
+                3 => write_strings(&mut column, rows.iter().map(|x| &x.url))?,
+                4 => write_strings(&mut column, rows.iter().map(|x| &x.path))?,
+                5 => write_strings(&mut column, rows.iter().map(|x| &x.query))?,
+                6 => {
+                    let values: Vec<i32> = rows.iter().map(|x| x.status as i32).collect();
+                    column
+                        .typed::<Int32Type>()
+                        .write_batch(&values, None, None)?;
+                }
+                7 => {
+                    let values: Vec<i64> = rows.iter().map(|x| x.bytes as i64).collect();
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&values, None, None)?;
+                }
+                8 => {
+                    let values: Vec<i64> = rows.iter().map(|x| x.response_time as i64).collect();
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&values, None, None)?;
+                }
+                9 => write_strings(&mut column, rows.iter().map(|x| &x.referer))?,
+                10 => write_strings(&mut column, rows.iter().map(|x| &x.user_agent))?,
+                _ => {
+                    let values: Vec<bool> = rows.iter().filter_map(|x| x.hit).collect();
+                    let levels: Vec<i16> = rows.iter().map(|x| x.hit.is_some() as i16).collect();
+                    column
+                        .typed::<BoolType>()
+                        .write_batch(&values, Some(&levels), None)?;
+                }
+            }
+            column.close()?;
+            index += 1;
+        }
+        row_group.close()?;
+        rows.clear();
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(PathBuf, usize), anyhow::Error> {
+        self.flush_row_group()?;
+        match self.writer {
+            Writer::Jsonl(mut w) | Writer::Csv(mut w) => w.flush()?,
+            Writer::Parquet { writer, .. } => {
+                writer.close()?;
+            }
+        }
+        fs::rename(&self.part, &self.path)?;
+        Ok((self.path, self.rows))
+    }
+}
+
+/// 行对应的导出文件, 不分区时为`output`本身
+pub fn export_path(
+    output: &Path,
+    partitions: &[Partition],
+    format: ExportFormat,
+    row: &ExportRow,
+    domain: &str,
+) -> PathBuf {
+    if partitions.is_empty() {
+        return output.to_path_buf();
+    }
+    let mut path = output.to_path_buf();
+    for p in partitions {
+        path = match p {
+            Partition::Day => path.join(format!("day={}", row.day)),
+            Partition::Domain => path.join(format!("domain={}", domain)),
+        };
+    }
+    path.join(format!("part-0.{}", format.extension()))
+}
+
+impl Client {
+    /// 把各域名日期区间内的日志导出到`output`, 分区时`output`为目录
+    pub async fn log_export(
+        &self,
+        query: Option<Expr>,
+        format: ExportFormat,
+        partitions: Vec<Partition>,
+        output: &str,
+        start_date: &str,
+        end_date: &str,
+        source: &LogSource,
+        domains: Vec<String>,
+    ) -> Result<(), anyhow::Error> {
+        // 导出的字段中没有域名, 多个域名写到同一个文件后无法区分
+        if domains.len() > 1 && !partitions.contains(&Partition::Domain) {
+            return Err(anyhow!(
+                "导出多个域名时需要按域名分区, 请指定--partition domain"
+            ));
+        }
+        let output = PathBuf::from(output);
+        let query = Arc::new(query);
+        let mut files: BTreeMap<PathBuf, ExportFile> = BTreeMap::new();
+        let mut errors = ParseErrors::default();
+        for domain in domains.iter() {
+            let logs = self.log_files(source, start_date, end_date, domain).await?;
+            let query = query.clone();
+            let mut results = self.log_stream(logs, move |records| {
+                records
+                    .filter(|x| query.as_ref().as_ref().is_none_or(|e| e.matches(x)))
+                    .map(ExportRow::from)
+                    .collect::<Vec<ExportRow>>()
+            });
+            while let Some(result) = results.next().await {
+                let result = result?;
+                errors.add(&result);
+                for row in result.value {
+                    let path = export_path(&output, &partitions, format, &row, domain);
+                    if !files.contains_key(&path) {
+                        files.insert(path.clone(), ExportFile::create(path.clone(), format)?);
+                    }
+                    files.get_mut(&path).unwrap().write(row)?;
+                }
+            }
+        }
+        errors.report(self.config.debug.unwrap_or(false));
+        if files.is_empty() {
+            print_err(NOT_FOUND_MSG, false);
+            return Ok(());
+        }
+        let mut total = 0;
+        for (_, file) in files {
+            let (path, rows) = file.finish()?;
+            total += rows;
+            println!("{} {}行", path.display().to_string().green(), rows);
+        }
+        println!("{}{}", "Total: ".cyan().bold(), total);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    use super::*;
+    use crate::log::TempDir;
+
+    #[test]
+    fn export_test() {
+        let dir = TempDir::new("log-export");
+        let rows: Vec<ExportRow> = [
+            r#"1.2.3.4 HIT 5 [16/Jul/2024:23:59:59 +0800] "GET http://a.com/a.js?v=1 HTTP/1.1" 200 100 "-" "Mozilla, \"bot\"""#,
+            r#"::1 - 0 [17/Jul/2024:00:00:00 +0800] "HEAD /b HTTP/1.1" 404 0 "-" "curl""#,
+        ]
+        .iter()
+        .map(|x| ExportRow::from(x.parse::<LogRecord>().unwrap()))
+        .collect();
+        assert_eq!(rows[0].path, "/a.js");
+        assert_eq!(rows[0].query, "v=1");
+        assert_eq!(rows[0].timestamp, "2024-07-16T23:59:59+08:00");
+        assert_eq!(rows[1].hit, None);
+
+        let partitions = Partition::parse_list("day,domain").unwrap();
+        let path = export_path(
+            dir.path(),
+            &partitions,
+            ExportFormat::Csv,
+            &rows[1],
+            "a.com",
+        );
+        assert_eq!(path, dir.join("day=2024-07-17/domain=a.com/part-0.csv"));
+
+        for format in [
+            ExportFormat::Jsonl,
+            ExportFormat::Csv,
+            ExportFormat::Parquet,
+        ] {
+            let path = dir.join(format!("out.{}", format));
+            let mut file = ExportFile::create(path.clone(), format).unwrap();
+            for row in rows.iter() {
+                file.write(row.clone()).unwrap();
+            }
+            assert_eq!(file.finish().unwrap(), (path.clone(), 2));
+            match format {
+                ExportFormat::Jsonl => {
+                    let content = fs::read_to_string(&path).unwrap();
+                    let v: serde_json::Value =
+                        serde_json::from_str(content.lines().next().unwrap()).unwrap();
+                    assert_eq!(v["user_agent"], "Mozilla, \"bot\"");
+                    assert_eq!(v["hit"], true);
+                }
+                ExportFormat::Csv => {
+                    let content = fs::read_to_string(&path).unwrap();
+                    let lines: Vec<&str> = content.lines().collect();
+                    assert!(lines[0].starts_with("ip,timestamp,method"));
+                    assert!(lines[1].ends_with(r#","Mozilla, ""bot""",true"#));
+                    assert!(lines[2].ends_with(",curl,"));
+                }
+                ExportFormat::Parquet => {
+                    let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+                    assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+                    assert_eq!(
+                        reader
+                            .metadata()
+                            .file_metadata()
+                            .schema_descr()
+                            .num_columns(),
+                        12
+                    );
+                }
+            }
+        }
+    }
+}
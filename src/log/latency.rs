@@ -0,0 +1,351 @@
+//! 响应时间分析
+//!
+//! 按日志中的响应时间(毫秒)统计整体、URL前缀、状态码类别、命中状态及每小时的p50/p90/p99/max，
+//! 未命中(回源)请求的响应时间超过阈值时告警
+
+#![allow(clippy::too_many_arguments)]
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+use colored::Colorize;
+use futures::StreamExt;
+use tokio::{
+    fs::{self, File},
+    io::AsyncWriteExt,
+};
+
+use super::{
+    query::Expr,
+    record::{CacheStatus, LogRecord},
+    stats::Dimension,
+    stream::{LogSource, ParseErrors},
+};
+use crate::{
+    utils::{max_length, pad, print_err, qy_robot::QyRobot, WaitBlinker},
+    Client,
+};
+
+/// 响应时间分布, 按毫秒值计数, 可以合并且百分位是精确的
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Latency {
+    pub counts: BTreeMap<u64, u64>,
+    pub count: u64,
+}
+
+impl Latency {
+    pub fn add(&mut self, ms: u64) {
+        *self.counts.entry(ms).or_default() += 1;
+        self.count += 1;
+    }
+
+    pub fn merge(&mut self, other: &Latency) {
+        for (ms, n) in other.counts.iter() {
+            *self.counts.entry(*ms).or_default() += n;
+        }
+        self.count += other.count;
+    }
+
+    /// 第`p`百分位(nearest-rank)
+    pub fn percentile(&self, p: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((p / 100.0 * self.count as f64).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0;
+        for (ms, n) in self.counts.iter() {
+            seen += n;
+            if seen >= rank {
+                return *ms;
+            }
+        }
+        self.max()
+    }
+
+    pub fn max(&self) -> u64 {
+        self.counts.keys().last().copied().unwrap_or(0)
+    }
+}
+
+/// 各维度的响应时间分布
+#[derive(Debug, Default)]
+pub struct LatencyReport {
+    pub overall: Latency,
+    pub prefix: HashMap<String, Latency>,
+    pub status: BTreeMap<String, Latency>,
+    pub cache: BTreeMap<String, Latency>,
+    /// key为`2024-07-16 02`
+    pub hour: BTreeMap<String, Latency>,
+    /// 每小时未命中请求的分布
+    pub miss_hour: BTreeMap<String, Latency>,
+}
+
+impl LatencyReport {
+    pub fn add(&mut self, record: &LogRecord, prefix_depth: usize) {
+        let ms = record.response_time;
+        let hour = record.time.format("%Y-%m-%d %H").to_string();
+        self.overall.add(ms);
+        self.prefix
+            .entry(Dimension::Prefix.key(record, prefix_depth))
+            .or_default()
+            .add(ms);
+        self.status
+            .entry(format!("{}xx", record.status / 100))
+            .or_default()
+            .add(ms);
+        self.cache
+            .entry(record.cache.to_string())
+            .or_default()
+            .add(ms);
+        if record.cache == CacheStatus::Miss {
+            self.miss_hour.entry(hour.clone()).or_default().add(ms);
+        }
+        self.hour.entry(hour).or_default().add(ms);
+    }
+
+    pub fn merge(&mut self, other: LatencyReport) {
+        self.overall.merge(&other.overall);
+        for (tables, others) in [
+            (&mut self.status, &other.status),
+            (&mut self.cache, &other.cache),
+            (&mut self.hour, &other.hour),
+            (&mut self.miss_hour, &other.miss_hour),
+        ] {
+            for (k, v) in others.iter() {
+                tables.entry(k.clone()).or_default().merge(v);
+            }
+        }
+        for (k, v) in other.prefix.iter() {
+            self.prefix.entry(k.clone()).or_default().merge(v);
+        }
+    }
+
+    /// 未命中请求第`percentile`百分位超过`threshold`毫秒的小时
+    pub fn slow_miss_hours(
+        &self,
+        percentile: f64,
+        threshold: u64,
+        min_requests: u64,
+    ) -> Vec<(String, u64, u64)> {
+        self.miss_hour
+            .iter()
+            .filter(|(_, v)| v.count >= min_requests)
+            .map(|(k, v)| (k.clone(), v.percentile(percentile), v.count))
+            .filter(|(_, ms, _)| *ms > threshold)
+            .collect()
+    }
+}
+
+fn print_table(name: &str, rows: &[(String, &Latency)], total: u64) {
+    if rows.is_empty() {
+        return;
+    }
+    let width = max_length(&rows.iter().map(|x| x.0.clone()), rows.len() as i32).clamp(16, 80);
+    println!();
+    println!(
+        "{} {:^10} {:^10} {:^10} {:^10} {:^10} {:^10}",
+        pad(name, width).bold().green(),
+        "Count".bold().green(),
+        "Percent".bold().green(),
+        "p50(ms)".bold().green(),
+        "p90(ms)".bold().green(),
+        "p99(ms)".bold().green(),
+        "Max(ms)".bold().green(),
+    );
+    for (k, v) in rows {
+        println!(
+            "{} {:^10} {:^10} {:^10} {:^10} {:^10} {:^10}",
+            pad(k, width),
+            v.count,
+            format!("{:.2}%", v.count as f64 / total.max(1) as f64 * 100.0),
+            v.percentile(50.0),
+            v.percentile(90.0),
+            v.percentile(99.0),
+            v.max(),
+        );
+    }
+}
+
+impl Client {
+    /// 统计日志中的响应时间分布
+    pub async fn log_latency(
+        self,
+        query: Option<Expr>,
+        limit: Option<i32>,
+        prefix_depth: usize,
+        start_date: &str,
+        end_date: &str,
+        no_qy_notify: bool,
+        wait_blink: Option<WaitBlinker>,
+        source: &LogSource,
+        domain: &str,
+    ) -> Result<(), anyhow::Error> {
+        let logs = self.log_files(source, start_date, end_date, domain).await?;
+        let mut results = self.log_stream(logs, move |records| {
+            let mut report = LatencyReport::default();
+            for record in records.filter(|x| query.as_ref().is_none_or(|e| e.matches(x))) {
+                report.add(&record, prefix_depth);
+            }
+            report
+        });
+        let mut report = LatencyReport::default();
+        let mut errors = ParseErrors::default();
+        while let Some(result) = results.next().await {
+            let result = result?;
+            errors.add(&result);
+            report.merge(result.value);
+        }
+        if let Some(blinker) = wait_blink {
+            blinker.sender.send(true).unwrap();
+            blinker.handle.await?;
+        }
+        errors.report(self.config.debug.unwrap_or(false));
+        println!(
+            "域名 {} {} 响应时间, 请求次数: {}",
+            domain.bold().yellow(),
+            source.describe(start_date, end_date).bold(),
+            report.overall.count.to_string().bold(),
+        );
+        let total = report.overall.count;
+        print_table("Overall", &[("all".to_string(), &report.overall)], total);
+        let mut prefix: Vec<(String, &Latency)> =
+            report.prefix.iter().map(|(k, v)| (k.clone(), v)).collect();
+        prefix.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(&b.0)));
+        prefix.truncate(limit.unwrap_or(10).max(0) as usize);
+        print_table("Prefix", &prefix, total);
+        for (name, table) in [
+            ("Status", &report.status),
+            ("Cache", &report.cache),
+            ("Hour", &report.hour),
+        ] {
+            let rows: Vec<(String, &Latency)> = table.iter().map(|(k, v)| (k.clone(), v)).collect();
+            print_table(name, &rows, total);
+        }
+        self.check_latency(&report, domain, no_qy_notify).await
+    }
+
+    /// 未命中请求的响应时间超过`[monitor.latency]`配置的阈值时告警
+    async fn check_latency(
+        &self,
+        report: &LatencyReport,
+        domain: &str,
+        no_qy_notify: bool,
+    ) -> Result<(), anyhow::Error> {
+        let latency_config = match self.config.monitor.latency.clone() {
+            Some(k) => k,
+            None => return Ok(()),
+        };
+        let threshold = match latency_config.miss_threshold {
+            Some(k) => k,
+            None => return Ok(()),
+        };
+        let percentile = latency_config.percentile.unwrap_or(90.0);
+        let slow = report.slow_miss_hours(
+            percentile,
+            threshold,
+            latency_config.min_requests.unwrap_or(100),
+        );
+        if slow.is_empty() {
+            return Ok(());
+        }
+        let reasons: Vec<String> = slow
+            .iter()
+            .map(|(hour, ms, count)| {
+                format!(
+                    "`{}时` 未命中请求{}次, p{}为{}ms, 超过{}ms",
+                    hour, count, percentile, ms, threshold
+                )
+            })
+            .collect();
+        println!();
+        for reason in reasons.iter() {
+            print_err(format!("域名 {} {}", domain, reason).as_str(), false);
+        }
+        if no_qy_notify || self.config.monitor.qy_robot.is_none() {
+            return Ok(());
+        }
+        // 同一域名同一小时只告警一次
+        let send_mark_dir = PathBuf::from("/tmp/qiniu/monitor/latency");
+        let mut pending = vec![];
+        for ((hour, _, _), reason) in slow.iter().zip(reasons.iter()) {
+            let path = send_mark_dir.join(format!("{}{}", domain, hour).replace(" ", ""));
+            if !path.exists() {
+                pending.push((path, reason));
+            }
+        }
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let msg = format!(
+            "## 🐢七牛CDN回源响应时间告警\n\n域名`{}`:\n\n- {}\n\n> 未命中请求的响应时间反映源站的响应速度，请检查源站！",
+            domain,
+            pending
+                .iter()
+                .map(|x| x.1.as_str())
+                .collect::<Vec<&str>>()
+                .join("\n- "),
+        );
+        if QyRobot::new(self.config.monitor.qy_robot.clone().unwrap())
+            .send_message(&msg)
+            .await
+            .is_err()
+        {
+            print_err(format!("消息发送失败: {}", msg).as_str(), false);
+        } else {
+            if !send_mark_dir.exists() {
+                fs::create_dir_all(&send_mark_dir).await?;
+            }
+            for (path, _) in pending {
+                File::create(path).await?.write_i8(1).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_test() {
+        let mut latency = Latency::default();
+        for ms in 1..=100 {
+            latency.add(ms);
+        }
+        assert_eq!(latency.percentile(50.0), 50);
+        assert_eq!(latency.percentile(90.0), 90);
+        assert_eq!(latency.percentile(99.0), 99);
+        assert_eq!(latency.max(), 100);
+        assert_eq!(Latency::default().percentile(50.0), 0);
+
+        let line = |cache: &str, rt: u64, hour: u32, status: u16| {
+            format!(
+                r#"1.2.3.4 {} {} [16/Jul/2024:{:02}:00:00 +0800] "GET /static/a.js HTTP/1.1" {} 10 "-" "-""#,
+                cache, rt, hour, status
+            )
+            .parse::<LogRecord>()
+            .unwrap()
+        };
+        let mut report = LatencyReport::default();
+        let mut other = LatencyReport::default();
+        for i in 0..10 {
+            report.add(&line("HIT", 5, 1, 200), 1);
+            other.add(&line("MISS", 100 + i * 100, 2, 502), 1);
+        }
+        report.merge(other);
+        assert_eq!(report.overall.count, 20);
+        assert_eq!(report.cache["MISS"].percentile(90.0), 900);
+        assert_eq!(report.status["5xx"].count, 10);
+        assert_eq!(report.prefix["/static/"].percentile(50.0), 5);
+        assert_eq!(report.hour["2024-07-16 01"].max(), 5);
+        assert_eq!(
+            report.slow_miss_hours(90.0, 500, 5),
+            vec![("2024-07-16 02".to_string(), 900, 10)]
+        );
+        assert!(report.slow_miss_hours(90.0, 500, 50).is_empty());
+        assert!(report.slow_miss_hours(90.0, 1000, 5).is_empty());
+    }
+}
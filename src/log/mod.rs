@@ -0,0 +1,836 @@
+//! 日志下载
+
+#![allow(clippy::too_many_arguments)]
+
+pub mod bot;
+pub mod cache;
+pub mod export;
+pub mod latency;
+pub mod query;
+pub mod record;
+pub mod ship;
+pub mod sql;
+pub mod stats;
+pub mod stream;
+pub mod sync;
+pub mod tail;
+
+use anyhow::anyhow;
+use chrono::{Duration, NaiveDate};
+use colored::Colorize;
+use flate2::read::GzDecoder;
+use futures::{stream as futures_stream, StreamExt};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use ipnet::IpNet;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{copy, BufWriter, Write},
+    net::IpAddr,
+    ops::Div,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    utils::{ipdb::IpDb, max_length, print_err, WaitBlinker},
+    Client, NOT_FOUND_MSG,
+};
+
+use query::Expr;
+use record::{Field, LogRecord};
+use stream::{LogSource, ParseErrors};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LogResponse {
+    pub code: Option<i32>,
+    pub error: String,
+    pub data: Option<HashMap<String, Vec<LogData>>>,
+}
+
+impl LogResponse {
+    /// code不是200时返回错误, 避免把接口错误当成没有日志
+    pub fn check(&self) -> Result<(), anyhow::Error> {
+        let code = self.code.unwrap_or(-1);
+        if code != 200 {
+            return Err(anyhow!(
+                "日志列表接口响应错误, code: {}, message: {}",
+                code,
+                self.error
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LogData {
+    pub name: String,
+    pub size: i64,
+    pub mtime: i64,
+    pub url: String,
+    pub md5: String,
+}
+
+impl LogData {
+    /// 去掉目录后的文件名, 如`v2/a.com_2024-07-16-02_part-00000.gz`为`a.com_2024-07-16-02_part-00000.gz`
+    pub fn file_name(&self) -> &str {
+        Self::base_name(&self.name)
+    }
+
+    /// `name`中最后一个`/`之后的部分
+    pub fn base_name(name: &str) -> &str {
+        name.rsplit('/').next().unwrap_or(name)
+    }
+
+    /// 文件名中的小时, 如`v2/a.com_2024-07-16-02_part-00000.gz`为2
+    pub fn hour(&self) -> Option<u32> {
+        let name = self.file_name();
+        name.split('_').find_map(|x| {
+            let date = x.get(..10)?;
+            NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+            if x.get(10..11)? != "-" {
+                return None;
+            }
+            x.get(11..13)?.parse().ok().filter(|h| *h < 24)
+        })
+    }
+}
+
+#[cfg(test)]
+impl LogData {
+    /// 测试用的日志文件
+    pub fn for_test(name: &str, md5: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            size: 0,
+            mtime: 0,
+            url: "".to_string(),
+            md5: md5.to_string(),
+        }
+    }
+}
+
+/// 测试用的临时目录, 离开作用域时删除, 断言失败时也会清理
+#[cfg(test)]
+pub struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("qiniu-{}-{}", name, crate::utils::random_u64()));
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// 小时范围, 如`02-05`、`2,20-23`, 起始大于结束时跨过0点, 如`22-02`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hours(Vec<(u32, u32)>);
+
+impl Hours {
+    pub fn contains(&self, hour: u32) -> bool {
+        self.0.iter().any(|(start, end)| {
+            if start <= end {
+                (*start..=*end).contains(&hour)
+            } else {
+                hour >= *start || hour <= *end
+            }
+        })
+    }
+}
+
+impl FromStr for Hours {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |x: &str| match x.trim().parse::<u32>() {
+            Ok(h) if h < 24 => Ok(h),
+            _ => Err(anyhow!("无效的小时: {}, 应为0-23", x)),
+        };
+        let mut ranges = vec![];
+        for part in s.split(',').filter(|x| !x.trim().is_empty()) {
+            match part.split_once('-') {
+                Some((start, end)) => ranges.push((parse(start)?, parse(end)?)),
+                None => {
+                    let h = parse(part)?;
+                    ranges.push((h, h));
+                }
+            }
+        }
+        if ranges.is_empty() {
+            return Err(anyhow!("小时范围不能为空"));
+        }
+        Ok(Self(ranges))
+    }
+}
+
+/// 合并查询条件及要包含的字符串, 以!!开头表示不包含
+pub fn filter_expr(query: Option<Expr>, filter_string: &[String]) -> Option<Expr> {
+    let mut expr = query;
+    for f in filter_string.iter() {
+        let contains = match f.strip_prefix("!!") {
+            Some(f) => Expr::Not(Box::new(Expr::Contains(f.to_string()))),
+            None => Expr::Contains(f.to_string()),
+        };
+        expr = Some(match expr {
+            Some(e) => Expr::And(Box::new(e), Box::new(contains)),
+            None => contains,
+        });
+    }
+    expr
+}
+
+/// 解析IP列表, 多个以英文逗号隔开, 支持网段及IPv6, 如`1.2.3.4,10.0.0.0/8,2408:8000::/20`
+pub fn parse_ip_nets(ips: &str) -> Result<Vec<IpNet>, anyhow::Error> {
+    let nets = ips
+        .split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            x.parse::<IpNet>()
+                .or_else(|_| x.parse::<IpAddr>().map(IpNet::from))
+                .map(|net| net.trunc())
+                .map_err(|_| anyhow!("无效的IP或网段: {}", x))
+        })
+        .collect::<Result<Vec<IpNet>, anyhow::Error>>()?;
+    if nets.is_empty() {
+        return Err(anyhow!("IP不能为空"));
+    }
+    Ok(nets)
+}
+
+/// 日志中的IP是否在列表中, IPv4映射的IPv6地址按IPv4匹配
+pub fn ip_in_nets(ip: &IpAddr, nets: &[IpNet]) -> bool {
+    let ip = ip.to_canonical();
+    nets.iter().any(|net| net.contains(&ip))
+}
+
+/// 请求是否匹配URL, 以`/`开头时匹配路径(不含参数), 否则匹配完整URL
+pub fn url_matches(record: &LogRecord, url: &str, prefix: bool) -> bool {
+    let target = if url.starts_with('/') {
+        record.path()
+    } else {
+        record.url.as_str()
+    };
+    if prefix {
+        target.starts_with(url)
+    } else {
+        target == url
+    }
+}
+
+/// 输出的日志行, 指定字段时以tab分隔
+pub fn format_record(record: &LogRecord, fields: Option<&[Field]>) -> String {
+    match fields {
+        Some(fields) => fields
+            .iter()
+            .map(|f| f.value(record))
+            .collect::<Vec<String>>()
+            .join("\t"),
+        None => record.to_string(),
+    }
+}
+
+impl Client {
+    /// ### [日志下载](https://developer.qiniu.com/fusion/1226/download-the-log)
+    pub async fn download(
+        self,
+        day: &str,
+        download_dir: Option<&str>,
+        limit: Option<i32>,
+        unzip_keep: bool,
+        unzip_not_keep: bool,
+        domain: &str,
+    ) -> Result<LogResponse, anyhow::Error> {
+        let url = format!("https://{}{}", self.host, "/v2/tune/log/list");
+        let mut data = HashMap::new();
+        data.insert("day", day);
+        data.insert("domains", domain);
+        let domain = domain.to_string();
+        let response: LogResponse = self
+            .do_request::<LogResponse, HashMap<&str, &str>>(
+                "POST",
+                &url,
+                None,
+                Some("application/json"),
+                Some(&data),
+            )
+            .await?;
+        if let Some(download_dir) = download_dir {
+            if let Some(log_data) = response.data.as_ref() {
+                if let Some(log_list) = log_data.get(&domain) {
+                    let limit = limit.unwrap_or(1000).max(0) as usize;
+                    let logs = log_list.iter().take(limit).cloned().collect();
+                    self.download_logs(logs, download_dir, &domain, unzip_keep, unzip_not_keep)
+                        .await?;
+                } else {
+                    print_err(NOT_FOUND_MSG, false);
+                }
+            }
+        }
+        Ok(response)
+    }
+
+    /// 下载日期区间内的日志, `hours`按文件名中的小时筛选
+    pub async fn download_range(
+        &self,
+        start_date: &str,
+        end_date: &str,
+        hours: Option<&Hours>,
+        download_dir: &str,
+        limit: Option<i32>,
+        unzip_keep: bool,
+        unzip_not_keep: bool,
+        domain: &str,
+    ) -> Result<(), anyhow::Error> {
+        let logs: Vec<LogData> = self
+            .list_logs(start_date, end_date, domain)
+            .await?
+            .into_iter()
+            .filter(|x| hours.is_none_or(|h| x.hour().is_some_and(|x| h.contains(x))))
+            .take(limit.unwrap_or(i32::MAX).max(0) as usize)
+            .collect();
+        if logs.is_empty() {
+            print_err(
+                format!(
+                    "域名 {} {}～{} {}",
+                    domain, start_date, end_date, NOT_FOUND_MSG
+                )
+                .as_str(),
+                false,
+            );
+            return Ok(());
+        }
+        self.download_logs(logs, download_dir, domain, unzip_keep, unzip_not_keep)
+            .await
+    }
+
+    /// 并发下载日志并显示进度，失败的文件在最后汇总
+    async fn download_logs(
+        &self,
+        logs: Vec<LogData>,
+        download_dir: &str,
+        domain: &str,
+        unzip_keep: bool,
+        unzip_not_keep: bool,
+    ) -> Result<(), anyhow::Error> {
+        let file_count = logs.len();
+        let progress = MultiProgress::new();
+        let total = progress.add(ProgressBar::new(
+            logs.iter().map(|x| x.size.max(0) as u64).sum(),
+        ));
+        total.set_style(
+            ProgressStyle::with_template(
+                "{msg} [{bar:30.green}] {bytes}/{total_bytes} {binary_bytes_per_sec} 剩余{eta}",
+            )?
+            .progress_chars("=> "),
+        );
+        total.set_message(format!("0/{}", file_count));
+        let file_style = ProgressStyle::with_template(
+            "{msg:40} [{bar:30.cyan}] {bytes}/{total_bytes} {binary_bytes_per_sec}",
+        )?
+        .progress_chars("=> ");
+        let mut finished = 0;
+        let mut failed = vec![];
+        let mut results = futures_stream::iter(logs)
+            .map(|log| {
+                let bar = progress.insert_before(&total, ProgressBar::new(log.size.max(0) as u64));
+                bar.set_style(file_style.clone());
+                bar.set_message(log.file_name().to_string());
+                let total = total.clone();
+                async move {
+                    let result = self
+                        .download_with_url(
+                            &log,
+                            download_dir,
+                            domain,
+                            unzip_keep,
+                            unzip_not_keep,
+                            &|n| {
+                                bar.inc(n);
+                                total.inc(n);
+                            },
+                        )
+                        .await;
+                    bar.finish_and_clear();
+                    (log, result)
+                }
+            })
+            .buffer_unordered(self.log_concurrency());
+        while let Some((log, result)) = results.next().await {
+            finished += 1;
+            total.set_message(format!("{}/{}", finished, file_count));
+            match result {
+                Ok(_) => {
+                    let msg = format!("下载完成 ✅ {}", log.url.green());
+                    if progress.is_hidden() {
+                        println!("{}", msg);
+                    } else {
+                        progress.println(msg)?;
+                    }
+                }
+                Err(e) => failed.push((log.name, e.to_string())),
+            }
+        }
+        total.finish_and_clear();
+        if !failed.is_empty() {
+            for (name, e) in failed.iter() {
+                print_err(format!("{}: {}", name, e).as_str(), false);
+            }
+            return Err(anyhow!(
+                "{}个日志下载失败, 成功{}个",
+                failed.len(),
+                file_count - failed.len()
+            ));
+        }
+        println!("{}", "全部下载完成 ✅".green());
+        Ok(())
+    }
+
+    async fn download_with_url(
+        &self,
+        log: &LogData,
+        download_dir: &str,
+        domain: &str,
+        unzip_keep: bool,
+        unzip_not_keep: bool,
+        on_progress: &(dyn Fn(u64) + Send + Sync),
+    ) -> Result<(), anyhow::Error> {
+        let mut log_dir = PathBuf::from(download_dir);
+        if self.config.download_log_domain_dir.unwrap_or(true) {
+            log_dir = log_dir.join(PathBuf::from(domain));
+        }
+        if !log_dir.exists() {
+            fs::create_dir_all(log_dir.clone())?;
+        }
+        let file_name = log.file_name();
+        let tmp_file_path = self.fetch_log_with_progress(log, on_progress).await?;
+        fs::copy(tmp_file_path, log_dir.join(file_name))?;
+        if self.config.debug.unwrap_or(false) && (unzip_keep || unzip_not_keep) {
+            println!(
+                "[DEBUG] 开始解压缩 {}, unzip_keep: {}, unzip_not_keep: {}",
+                file_name, unzip_keep, unzip_not_keep
+            );
+        }
+        if unzip_keep {
+            self.unzip(&log_dir.join(file_name), true)?;
+        } else if unzip_not_keep {
+            self.unzip(&log_dir.join(file_name), false)?;
+        }
+        Ok(())
+    }
+
+    fn unzip(&self, file_path: &Path, keep_file: bool) -> Result<(), anyhow::Error> {
+        let input_file = File::open(file_path)?;
+        let mut gz = GzDecoder::new(input_file);
+        let output_file_path = file_path.to_str().unwrap().strip_suffix(".gz").unwrap();
+        let mut output_file = File::create(Path::new(output_file_path))?;
+        copy(&mut gz, &mut output_file)?;
+        if !keep_file {
+            fs::remove_file(file_path)?;
+        }
+        Ok(())
+    }
+
+    /// 日期区间内的日志文件列表，按文件名(即时间)排序
+    pub async fn list_logs(
+        &self,
+        start_date: &str,
+        end_date: &str,
+        domain: &str,
+    ) -> Result<Vec<LogData>, anyhow::Error> {
+        let start_date_dt = NaiveDate::parse_from_str(start_date, "%Y-%m-%d")?;
+        let end_date_dt = NaiveDate::parse_from_str(end_date, "%Y-%m-%d")?;
+        if start_date_dt > end_date_dt {
+            return Err(anyhow!("开始日期不能大于结束日期！"));
+        }
+        let days = (end_date_dt - start_date_dt).num_days() + 1;
+        if days > 30 {
+            return Err(anyhow!("间隔不能大于30天！"));
+        }
+        let responses: Vec<Result<LogResponse, anyhow::Error>> = futures_stream::iter(0..days)
+            .map(|i| {
+                let dt = (start_date_dt + Duration::days(i))
+                    .format("%Y-%m-%d")
+                    .to_string();
+                let this = self.clone();
+                async move {
+                    if this.config.debug.unwrap_or(false) {
+                        println!("[DEBUG] Log dt: {}", dt);
+                    }
+                    this.download(&dt, None, None, false, false, domain).await
+                }
+            })
+            .buffered(self.log_concurrency())
+            .collect()
+            .await;
+        let mut logs = vec![];
+        for response in responses {
+            let response = response?;
+            response.check()?;
+            if let Some(mut data) = response.data {
+                logs.extend(data.remove(domain).unwrap_or_default());
+            }
+        }
+        logs.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(logs)
+    }
+
+    /// IP请求的URL及次数，按次数倒序, `ip`支持多个IP及网段
+    pub async fn ip_url_count(
+        self,
+        ip: &str,
+        start_date: &str,
+        end_date: &str,
+        source: &LogSource,
+        domain: &str,
+    ) -> Result<(Vec<(String, i32)>, ParseErrors), anyhow::Error> {
+        let nets = parse_ip_nets(ip)?;
+        let logs = self.log_files(source, start_date, end_date, domain).await?;
+        let mut results = self.log_stream(logs, move |records| {
+            let mut url_count_map: HashMap<String, i32> = HashMap::new();
+            for record in records.filter(|x| ip_in_nets(&x.ip, &nets)) {
+                *url_count_map.entry(record.url).or_insert(0) += 1;
+            }
+            url_count_map
+        });
+        let mut url_count_map = HashMap::new();
+        let mut errors = ParseErrors::default();
+        while let Some(result) = results.next().await {
+            let result = result?;
+            errors.add(&result);
+            for (url, count) in result.value {
+                *url_count_map.entry(url).or_insert(0i32) += count;
+            }
+        }
+        let mut hash_vec: Vec<(String, i32)> = url_count_map.into_iter().collect();
+        hash_vec.sort_by_key(|x| std::cmp::Reverse(x.1));
+        Ok((hash_vec, errors))
+    }
+
+    pub async fn ip_url(
+        self,
+        ip: &str,
+        start_date: &str,
+        end_date: &str,
+        limit: Option<i32>,
+        wait_blink: Option<WaitBlinker>,
+        source: &LogSource,
+        domain: &str,
+    ) -> Result<(), anyhow::Error> {
+        let (hash_vec, errors) = self
+            .clone()
+            .ip_url_count(ip, start_date, end_date, source, domain)
+            .await?;
+        if let Some(blinker) = wait_blink {
+            blinker.sender.send(true).unwrap();
+            blinker.handle.await?;
+        }
+        errors.report(self.config.debug.unwrap_or(false));
+        if hash_vec.is_empty() {
+            println!("{}", "没有找到该IP的请求日志".red());
+            return Ok(());
+        }
+        let location = match IpDb::from_config_or_warn(&self.config).and_then(|x| x.lookup_str(ip))
+        {
+            Some(info) => format!("({}) ", info),
+            None => "".to_string(),
+        };
+        println!(
+            "域名 {} IP {} {}{} URL请求次数如下: ",
+            domain.bold().yellow(),
+            ip.bold().yellow(),
+            location,
+            source.describe(start_date, end_date).bold(),
+        );
+        let limit = limit.unwrap_or(10000);
+        let width = max_length(&hash_vec.iter().map(|x| x.0.clone()), limit);
+        println!(
+            "{:^width$} {:^10}",
+            "URL".bold().green(),
+            "Count".bold().green(),
+            width = width,
+        );
+        for (url, c) in hash_vec.iter().take(limit as usize) {
+            println!("{:<width$} {:^10}", url, c);
+        }
+        println!(
+            "{:^width$} {:^10}",
+            "Total".bold().green(),
+            hash_vec.iter().map(|x| x.1).sum::<i32>(),
+            width = width,
+        );
+        Ok(())
+    }
+
+    /// 请求URL的IP及次数、流量, 按次数或流量倒序
+    pub async fn url_ip_count(
+        self,
+        url: &str,
+        prefix: bool,
+        by_bytes: bool,
+        start_date: &str,
+        end_date: &str,
+        source: &LogSource,
+        domain: &str,
+    ) -> Result<(Vec<(IpAddr, u64, u64)>, ParseErrors), anyhow::Error> {
+        let url = url.trim().to_string();
+        if url.is_empty() {
+            return Err(anyhow!("URL不能为空"));
+        }
+        let logs = self.log_files(source, start_date, end_date, domain).await?;
+        let mut results = self.log_stream(logs, move |records| {
+            let mut ip_map: HashMap<IpAddr, (u64, u64)> = HashMap::new();
+            for record in records.filter(|x| url_matches(x, &url, prefix)) {
+                let v = ip_map.entry(record.ip).or_default();
+                v.0 += 1;
+                v.1 += record.bytes;
+            }
+            ip_map
+        });
+        let mut ip_map: HashMap<IpAddr, (u64, u64)> = HashMap::new();
+        let mut errors = ParseErrors::default();
+        while let Some(result) = results.next().await {
+            let result = result?;
+            errors.add(&result);
+            for (ip, (count, bytes)) in result.value {
+                let v = ip_map.entry(ip).or_default();
+                v.0 += count;
+                v.1 += bytes;
+            }
+        }
+        let mut rows: Vec<(IpAddr, u64, u64)> =
+            ip_map.into_iter().map(|(k, v)| (k, v.0, v.1)).collect();
+        if by_bytes {
+            rows.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        } else {
+            rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        }
+        Ok((rows, errors))
+    }
+
+    pub async fn url_ip(
+        self,
+        url: &str,
+        prefix: bool,
+        by_bytes: bool,
+        start_date: &str,
+        end_date: &str,
+        limit: Option<i32>,
+        wait_blink: Option<WaitBlinker>,
+        source: &LogSource,
+        domain: &str,
+    ) -> Result<(), anyhow::Error> {
+        let (rows, errors) = self
+            .clone()
+            .url_ip_count(url, prefix, by_bytes, start_date, end_date, source, domain)
+            .await?;
+        if let Some(blinker) = wait_blink {
+            blinker.sender.send(true).unwrap();
+            blinker.handle.await?;
+        }
+        errors.report(self.config.debug.unwrap_or(false));
+        if rows.is_empty() {
+            println!("{}", "没有找到该URL的请求日志".red());
+            return Ok(());
+        }
+        println!(
+            "域名 {} URL {}{} {} 请求IP如下: ",
+            domain.bold().yellow(),
+            url.bold().yellow(),
+            if prefix { "*" } else { "" },
+            source.describe(start_date, end_date).bold(),
+        );
+        let ipdb = IpDb::from_config_or_warn(&self.config);
+        let limit = limit.unwrap_or(10000);
+        let width = max_length(&rows.iter().map(|x| x.0.to_string()), limit).max(15);
+        println!(
+            "{:^width$} {:^10} {:^16}{}",
+            "IP".bold().green(),
+            "Count".bold().green(),
+            "Traffic(MB)".bold().green(),
+            match ipdb {
+                Some(_) => format!(" {}", "Location".bold().green()),
+                None => "".to_string(),
+            },
+            width = width,
+        );
+        for (ip, count, bytes) in rows.iter().take(limit as usize) {
+            let location = match ipdb.as_ref() {
+                Some(ipdb) => format!(
+                    " {}",
+                    ipdb.lookup(*ip)
+                        .map(|x| x.to_string())
+                        .unwrap_or("未知".to_string())
+                ),
+                None => "".to_string(),
+            };
+            println!(
+                "{:<width$} {:^10} {:^16}{}",
+                ip.to_string(),
+                count,
+                format!("{:.4}", (*bytes as f64).div(1024.0).div(1024.0)),
+                location,
+                width = width,
+            );
+        }
+        println!(
+            "{:^width$} {:^10} {:^16}",
+            "Total".bold().green(),
+            rows.iter().map(|x| x.1).sum::<u64>(),
+            format!(
+                "{:.4}",
+                (rows.iter().map(|x| x.2).sum::<u64>() as f64)
+                    .div(1024.0)
+                    .div(1024.0)
+            ),
+            width = width,
+        );
+        Ok(())
+    }
+
+    pub async fn filter_log(
+        self,
+        filter_string: Vec<String>,
+        query: Option<Expr>,
+        fields: Option<Vec<Field>>,
+        start_date: &str,
+        end_date: &str,
+        output_file: bool,
+        wait_blink: Option<WaitBlinker>,
+        source: &LogSource,
+        domain: &str,
+    ) -> Result<(), anyhow::Error> {
+        let logs = self.log_files(source, start_date, end_date, domain).await?;
+        if let Some(wait_blink) = wait_blink {
+            wait_blink.sender.send(true).unwrap();
+            wait_blink.handle.await?;
+        }
+        let expr = filter_expr(query, &filter_string);
+        let mut results = self.log_stream(logs, move |records| {
+            records
+                .filter(|x| expr.as_ref().is_none_or(|e| e.matches(x)))
+                .map(|x| format_record(&x, fields.as_deref()))
+                .collect::<Vec<String>>()
+        });
+        let file_name = format!(
+            "{}.{}-{}-{}.log",
+            domain,
+            filter_string.first().map(|x| x.as_str()).unwrap_or("query"),
+            start_date,
+            end_date
+        );
+        let mut output: Box<dyn Write> = if output_file {
+            Box::new(BufWriter::new(File::create(&file_name)?))
+        } else {
+            Box::new(std::io::stdout().lock())
+        };
+        let mut total = 0;
+        let mut errors = ParseErrors::default();
+        // 按文件顺序边处理边输出
+        while let Some(result) = results.next().await {
+            let result = result?;
+            errors.add(&result);
+            for line in result.value {
+                writeln!(output, "{}", line)?;
+                total += 1;
+            }
+        }
+        output.flush()?;
+        drop(output);
+        errors.report(self.config.debug.unwrap_or(false));
+        println!("{}{}", "Total: ".cyan().bold(), total);
+        if output_file {
+            println!("符合条件的日志已导出: {}", file_name.green().bold());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::config::Config;
+
+    use super::*;
+
+    #[test]
+    fn hours_test() {
+        let log = |name: &str| LogData::for_test(name, "");
+        assert_eq!(log("v2/a.com_2024-07-16-02_part-00000.gz").hour(), Some(2));
+        assert_eq!(log("a.com/d_2024-07-16-23.log").hour(), Some(23));
+        assert_eq!(log("v2/a_b.com_part-00000.gz").hour(), None);
+        let hours: Hours = "02-05,12".parse().unwrap();
+        assert!(hours.contains(2) && hours.contains(5) && hours.contains(12));
+        assert!(!hours.contains(6) && !hours.contains(1));
+        let hours: Hours = "22-01".parse().unwrap();
+        assert!(hours.contains(23) && hours.contains(0) && !hours.contains(2));
+        assert!("24".parse::<Hours>().is_err());
+        assert!("".parse::<Hours>().is_err());
+    }
+
+    #[test]
+    fn ip_url_match_test() {
+        let nets = parse_ip_nets("1.2.3.4, 10.0.0.0/8,2408:8000::/20").unwrap();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(ip_in_nets(&ip("1.2.3.4"), &nets));
+        assert!(!ip_in_nets(&ip("1.2.3.5"), &nets));
+        assert!(ip_in_nets(&ip("10.200.1.1"), &nets));
+        assert!(ip_in_nets(&ip("::ffff:10.0.0.1"), &nets));
+        assert!(ip_in_nets(&ip("2408:8001::1"), &nets));
+        assert!(!ip_in_nets(&ip("2409::1"), &nets));
+        // 网段会截断到网络地址
+        assert_eq!(
+            parse_ip_nets("10.1.2.3/8").unwrap()[0].to_string(),
+            "10.0.0.0/8"
+        );
+        assert!(parse_ip_nets("1.2.3").is_err());
+        assert!(parse_ip_nets(" , ").is_err());
+
+        let record: LogRecord = r#"1.2.3.4 HIT 0 [16/Jul/2024:00:00:01 +0800] "GET http://a.com/video/a.mp4?t=1 HTTP/1.1" 200 100 "-" "curl""#
+            .parse()
+            .unwrap();
+        assert!(url_matches(&record, "/video/a.mp4", false));
+        assert!(url_matches(&record, "/video/", true));
+        assert!(!url_matches(&record, "/video/", false));
+        assert!(url_matches(&record, "http://a.com/video/a.mp4?t=1", false));
+        assert!(!url_matches(&record, "http://a.com/video/a.mp4", false));
+        assert!(url_matches(&record, "http://a.com/video/a.mp4", true));
+    }
+
+    #[tokio::test]
+    async fn download_log_test() {
+        let config = Config::parse(Some(PathBuf::from("./qiniu-cdn.toml")));
+        let log = Client::new(&config, crate::SubFunctionEnum::Log);
+        log.download(
+            "2024-07-16",
+            Some("./logs"),
+            Some(1),
+            false,
+            false,
+            &config.cdn.domain,
+        )
+        .await
+        .unwrap();
+    }
+}
@@ -0,0 +1,525 @@
+//! 日志查询表达式
+//!
+//! 例如: `status>=500 and ip in 10.0.0.0/8 and ua ~ /bot/i and bytes>1MB and time between 02:00 and 03:00`
+//!
+//! - 比较: `= != > >= < <=`, 数值支持`KB MB GB`及`ms s`单位
+//! - 正则: `~ /re/i`、`!~ /re/`
+//! - 集合: `in a,b`、`not in (a, b)`, IP支持CIDR
+//! - 区间: `between a and b`, 时间可以是`HH:MM[:SS]`或`"YYYY-MM-DD HH:MM:SS"`
+//! - 组合: `and` `or` `not` 及括号, 单独的字符串表示日志包含该字符串
+
+use std::{net::IpAddr, str::FromStr};
+
+use anyhow::anyhow;
+use chrono::{NaiveDateTime, NaiveTime};
+use ipnet::IpNet;
+use regex::{Regex, RegexBuilder};
+
+use super::record::{Field, LogRecord};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Regex(String, String),
+    Op(Op),
+    Match(bool),
+    LParen,
+    RParen,
+    Comma,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+/// 比较的值
+#[derive(Debug, Clone)]
+pub enum Value {
+    Number(f64),
+    Text(String),
+    Net(IpNet),
+    TimeOfDay(NaiveTime),
+    DateTime(NaiveDateTime),
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Field, Op, Value),
+    Match(Field, Regex),
+    In(Field, Vec<Value>),
+    Between(Field, Value, Value),
+    /// 日志包含字符串
+    Contains(String),
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, anyhow::Error> {
+    let mut tokens = vec![];
+    let chars: Vec<char> = s.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            }
+            ',' => {
+                tokens.push(Token::Comma);
+                i += 1;
+            }
+            '"' | '\'' => {
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        Some('\\') if chars.get(i + 1).is_some() => {
+                            value.push(chars[i + 1]);
+                            i += 2;
+                        }
+                        Some(x) if *x == c => break,
+                        Some(x) => {
+                            value.push(*x);
+                            i += 1;
+                        }
+                        None => return Err(anyhow!("引号未闭合: {}", s)),
+                    }
+                }
+                i += 1;
+                tokens.push(Token::Quoted(value));
+            }
+            // 正则只出现在 ~ 之后
+            '/' if matches!(tokens.last(), Some(Token::Match(_))) => {
+                let mut pattern = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        Some('\\') if chars.get(i + 1) == Some(&'/') => {
+                            pattern.push('/');
+                            i += 2;
+                        }
+                        Some('/') => break,
+                        Some(x) => {
+                            pattern.push(*x);
+                            i += 1;
+                        }
+                        None => return Err(anyhow!("正则未闭合: {}", s)),
+                    }
+                }
+                i += 1;
+                let mut flags = String::new();
+                while let Some(x) = chars.get(i).filter(|x| x.is_ascii_alphabetic()) {
+                    flags.push(*x);
+                    i += 1;
+                }
+                tokens.push(Token::Regex(pattern, flags));
+            }
+            '=' | '!' | '>' | '<' | '~' => {
+                let next = chars.get(i + 1).copied();
+                let (token, len) = match (c, next) {
+                    ('=', Some('=')) => (Token::Op(Op::Eq), 2),
+                    ('=', _) => (Token::Op(Op::Eq), 1),
+                    ('!', Some('=')) => (Token::Op(Op::Ne), 2),
+                    ('!', Some('~')) => (Token::Match(false), 2),
+                    ('!', _) => (Token::Word("not".to_string()), 1),
+                    ('>', Some('=')) => (Token::Op(Op::Ge), 2),
+                    ('>', _) => (Token::Op(Op::Gt), 1),
+                    ('<', Some('=')) => (Token::Op(Op::Le), 2),
+                    ('<', _) => (Token::Op(Op::Lt), 1),
+                    _ => (Token::Match(true), 1),
+                };
+                tokens.push(token);
+                i += len;
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(x) = chars.get(i) {
+                    if x.is_whitespace() || "()=!<>~,\"'".contains(*x) {
+                        break;
+                    }
+                    word.push(*x);
+                    i += 1;
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// 解析数值, 支持字节及时间单位
+fn parse_number(s: &str) -> Option<f64> {
+    let lower = s.to_lowercase();
+    let units: [(&str, f64); 9] = [
+        ("kb", 1024.0),
+        ("mb", 1024.0 * 1024.0),
+        ("gb", 1024.0 * 1024.0 * 1024.0),
+        ("ms", 1.0),
+        ("k", 1024.0),
+        ("m", 1024.0 * 1024.0),
+        ("g", 1024.0 * 1024.0 * 1024.0),
+        ("s", 1000.0),
+        ("b", 1.0),
+    ];
+    for (unit, factor) in units {
+        if let Some(n) = lower.strip_suffix(unit) {
+            if let Ok(n) = n.parse::<f64>() {
+                return Some(n * factor);
+            }
+        }
+    }
+    lower.parse::<f64>().ok()
+}
+
+fn parse_value(field: Field, s: &str) -> Result<Value, anyhow::Error> {
+    if field.is_numeric() {
+        return parse_number(s)
+            .map(Value::Number)
+            .ok_or(anyhow!("{}需要数值: {}", field, s));
+    }
+    match field {
+        Field::Ip => {
+            if let Ok(net) = s.parse::<IpNet>() {
+                return Ok(Value::Net(net));
+            }
+            s.parse::<IpAddr>()
+                .map(|ip| Value::Net(IpNet::from(ip)))
+                .map_err(|_| anyhow!("无效的IP或网段: {}", s))
+        }
+        Field::Time => {
+            for format in ["%H:%M:%S", "%H:%M"] {
+                if let Ok(t) = NaiveTime::parse_from_str(s, format) {
+                    return Ok(Value::TimeOfDay(t));
+                }
+            }
+            for format in [
+                "%Y-%m-%d %H:%M:%S",
+                "%Y-%m-%dT%H:%M:%S",
+                "%Y-%m-%d %H:%M",
+                "%Y-%m-%dT%H:%M",
+            ] {
+                if let Ok(t) = NaiveDateTime::parse_from_str(s, format) {
+                    return Ok(Value::DateTime(t));
+                }
+            }
+            Err(anyhow!(
+                "无效的时间: {}, 如02:00或\"2024-07-16 02:00:00\"",
+                s
+            ))
+        }
+        _ => Ok(Value::Text(s.to_string())),
+    }
+}
+
+fn compare<T: PartialOrd>(a: T, op: Op, b: T) -> bool {
+    match op {
+        Op::Eq => a == b,
+        Op::Ne => a != b,
+        Op::Gt => a > b,
+        Op::Ge => a >= b,
+        Op::Lt => a < b,
+        Op::Le => a <= b,
+    }
+}
+
+/// 字段值与查询值比较
+fn compare_value(field: Field, record: &LogRecord, op: Op, value: &Value) -> bool {
+    match value {
+        Value::Number(n) => field.number(record).is_some_and(|v| compare(v, op, *n)),
+        Value::Net(net) => {
+            let contains = net.contains(&record.ip);
+            match op {
+                Op::Eq => contains,
+                Op::Ne => !contains,
+                _ => compare(record.ip, op, net.addr()),
+            }
+        }
+        Value::TimeOfDay(t) => compare(record.time.time(), op, *t),
+        Value::DateTime(t) => compare(record.time.naive_local(), op, *t),
+        Value::Text(s) => {
+            let v = field.value(record);
+            match field {
+                // 方法及命中状态不区分大小写
+                Field::Method | Field::Cache => compare(v.to_uppercase(), op, s.to_uppercase()),
+                _ => compare(v.as_str(), op, s.as_str()),
+            }
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        self.peek_keyword_at(self.pos, keyword)
+    }
+
+    fn peek_keyword_at(&self, pos: usize, keyword: &str) -> bool {
+        match self.tokens.get(pos) {
+            Some(Token::Word(w)) => {
+                w.eq_ignore_ascii_case(keyword)
+                    || (keyword == "and" && w == "&&")
+                    || (keyword == "or" && w == "||")
+            }
+            _ => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, anyhow::Error> {
+        let mut expr = self.and()?;
+        while self.peek_keyword("or") {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, anyhow::Error> {
+        let mut expr = self.unary()?;
+        while self.peek_keyword("and") {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, anyhow::Error> {
+        if self.peek_keyword("not") {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err(anyhow!("缺少`)`")),
+                }
+            }
+            Some(Token::Quoted(s)) => Ok(Expr::Contains(s)),
+            Some(Token::Word(w)) => {
+                let field = match Field::from_str(&w) {
+                    Ok(field) if self.is_predicate() => field,
+                    // 不是字段条件，当作包含字符串
+                    _ => return Ok(Expr::Contains(w)),
+                };
+                self.predicate(field)
+            }
+            Some(token) => Err(anyhow!("语法错误, 不应出现: {:?}", token)),
+            None => Err(anyhow!("查询条件不完整")),
+        }
+    }
+
+    /// 字段名之后是否紧跟运算符
+    fn is_predicate(&self) -> bool {
+        matches!(self.peek(), Some(Token::Op(_)) | Some(Token::Match(_)))
+            || self.peek_keyword("in")
+            || self.peek_keyword("between")
+            || (self.peek_keyword("not") && self.peek_keyword_at(self.pos + 1, "in"))
+    }
+
+    fn value(&mut self, field: Field) -> Result<Value, anyhow::Error> {
+        match self.next() {
+            Some(Token::Word(s)) | Some(Token::Quoted(s)) => parse_value(field, &s),
+            _ => Err(anyhow!("{}缺少比较值", field)),
+        }
+    }
+
+    fn predicate(&mut self, field: Field) -> Result<Expr, anyhow::Error> {
+        match self.next() {
+            Some(Token::Op(op)) => {
+                let value = self.value(field)?;
+                if matches!(value, Value::Text(_)) && !matches!(op, Op::Eq | Op::Ne) {
+                    return Err(anyhow!("{}只支持=、!=、~及in", field));
+                }
+                Ok(Expr::Compare(field, op, value))
+            }
+            Some(Token::Match(positive)) => {
+                let (pattern, flags) = match self.next() {
+                    Some(Token::Regex(p, f)) => (p, f),
+                    Some(Token::Word(p)) | Some(Token::Quoted(p)) => (p, String::new()),
+                    _ => return Err(anyhow!("{}缺少正则", field)),
+                };
+                let re = RegexBuilder::new(&pattern)
+                    .case_insensitive(flags.contains('i'))
+                    .build()?;
+                let expr = Expr::Match(field, re);
+                Ok(if positive {
+                    expr
+                } else {
+                    Expr::Not(Box::new(expr))
+                })
+            }
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case("between") => {
+                let start = self.value(field)?;
+                if !self.peek_keyword("and") {
+                    return Err(anyhow!("between缺少and"));
+                }
+                self.pos += 1;
+                let end = self.value(field)?;
+                Ok(Expr::Between(field, start, end))
+            }
+            Some(Token::Word(w)) => {
+                let negative = w.eq_ignore_ascii_case("not") || w == "!";
+                if negative {
+                    self.pos += 1;
+                }
+                let paren = self.peek() == Some(&Token::LParen);
+                if paren {
+                    self.pos += 1;
+                }
+                let mut values = vec![self.value(field)?];
+                while self.peek() == Some(&Token::Comma) {
+                    self.pos += 1;
+                    values.push(self.value(field)?);
+                }
+                if paren && self.next() != Some(Token::RParen) {
+                    return Err(anyhow!("缺少`)`"));
+                }
+                let expr = Expr::In(field, values);
+                Ok(if negative {
+                    Expr::Not(Box::new(expr))
+                } else {
+                    expr
+                })
+            }
+            _ => Err(anyhow!("{}缺少运算符", field)),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(anyhow!("语法错误, 多余的: {:?}", token));
+        }
+        Ok(expr)
+    }
+}
+
+impl Expr {
+    pub fn matches(&self, record: &LogRecord) -> bool {
+        match self {
+            Expr::And(a, b) => a.matches(record) && b.matches(record),
+            Expr::Or(a, b) => a.matches(record) || b.matches(record),
+            Expr::Not(a) => !a.matches(record),
+            Expr::Compare(field, op, value) => compare_value(*field, record, *op, value),
+            Expr::Match(field, re) => re.is_match(&field.value(record)),
+            Expr::In(field, values) => values
+                .iter()
+                .any(|v| compare_value(*field, record, Op::Eq, v)),
+            Expr::Between(field, start, end) => {
+                // 时间区间跨零点, 如 23:00 到 01:00
+                if let (Value::TimeOfDay(s), Value::TimeOfDay(e)) = (start, end) {
+                    if s > e {
+                        let t = record.time.time();
+                        return t >= *s || t <= *e;
+                    }
+                }
+                compare_value(*field, record, Op::Ge, start)
+                    && compare_value(*field, record, Op::Le, end)
+            }
+            Expr::Contains(s) => record.to_string().contains(s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(line: &str) -> LogRecord {
+        line.parse().unwrap()
+    }
+
+    #[test]
+    fn query_test() {
+        let bot = record(
+            r#"10.1.2.3 MISS 120 [16/Jul/2024:02:30:00 +0800] "GET http://a.com/big.zip HTTP/1.1" 502 2097152 "-" "Googlebot/2.1""#,
+        );
+        let user = record(
+            r#"1.2.3.45 HIT 3 [16/Jul/2024:12:00:00 +0800] "GET http://a.com/index.html?a=1 HTTP/1.1" 200 512 "-" "Mozilla/5.0""#,
+        );
+        let q: Expr = "status>=500 and ip in 10.0.0.0/8 and ua ~ /BOT/i and bytes>1MB and time between 02:00 and 03:00"
+            .parse()
+            .unwrap();
+        assert!(q.matches(&bot));
+        assert!(!q.matches(&user));
+
+        let q: Expr = "ip = 1.2.3.4 or ip=1.2.3.45".parse().unwrap();
+        assert!(q.matches(&user));
+        assert!(!q.matches(&bot));
+        assert!(!"ip=1.2.3.4".parse::<Expr>().unwrap().matches(&user));
+
+        let q: Expr = "not (cache=hit or rt < 100ms) and status not in (200, 404)"
+            .parse()
+            .unwrap();
+        assert!(q.matches(&bot));
+        assert!(!q.matches(&user));
+
+        let q: Expr = r#"path = "/index.html" and !(method != get) and big.zip"#
+            .parse()
+            .unwrap();
+        assert!(!q.matches(&user));
+        assert!("index.html and ua !~ /bot/i"
+            .parse::<Expr>()
+            .unwrap()
+            .matches(&user));
+        assert!("time between 23:00 and 03:00"
+            .parse::<Expr>()
+            .unwrap()
+            .matches(&bot));
+        assert!(r#"time >= "2024-07-16 12:00""#.parse::<Expr>().unwrap().matches(&user));
+    }
+
+    #[test]
+    fn query_error_test() {
+        for q in [
+            "",
+            "status >= abc",
+            "ip in 1.2.3",
+            "(status=200",
+            "status between 200",
+            "ua > abc",
+            "status=200 and",
+            "ua ~ /[/",
+            "time = 25:00",
+        ] {
+            assert!(q.parse::<Expr>().is_err(), "{}", q);
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! CDN访问日志解析
+//!
+//! 七牛日志每行格式如下:
+//!
+//! `101.226.66.177 HIT 0 [07/Feb/2017:11:15:40 +0800] "GET http://www.example.com/favicon.ico HTTP/1.1" 200 1150 "-" "Mozilla/5.0"`
+//!
+//! 依次为: 客户端IP、命中状态、响应时间(毫秒)、请求时间、请求行、状态码、响应字节数、Referer、UserAgent
+
+use std::{fmt::Display, net::IpAddr, str::FromStr};
+
+use anyhow::anyhow;
+use chrono::{DateTime, FixedOffset};
+
+const TIME_FORMAT: &str = "%d/%b/%Y:%H:%M:%S %z";
+
+/// 缓存命中状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheStatus {
+    Hit,
+    Miss,
+    Unknown,
+}
+
+impl FromStr for CacheStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "HIT" => Ok(Self::Hit),
+            "MISS" => Ok(Self::Miss),
+            "-" => Ok(Self::Unknown),
+            _ => Err(anyhow!("无效的命中状态: {}", s)),
+        }
+    }
+}
+
+impl Display for CacheStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Hit => write!(f, "HIT"),
+            Self::Miss => write!(f, "MISS"),
+            Self::Unknown => write!(f, "-"),
+        }
+    }
+}
+
+/// 一条访问日志
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    pub ip: IpAddr,
+    pub cache: CacheStatus,
+    /// 响应时间(毫秒)
+    pub response_time: u64,
+    pub time: DateTime<FixedOffset>,
+    pub method: String,
+    pub url: String,
+    pub protocol: String,
+    pub status: u16,
+    pub bytes: u64,
+    pub referer: String,
+    pub user_agent: String,
+}
+
+/// 按空格切分，`[]`和`""`包含的内容作为一个字段，引号内支持`\"`转义
+fn split_fields(line: &str) -> Result<Vec<String>, anyhow::Error> {
+    let mut fields = vec![];
+    let mut chars = line.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => continue,
+            '[' => {
+                let mut field = String::new();
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some(c) => field.push(c),
+                        None => return Err(anyhow!("缺少`]`")),
+                    }
+                }
+                fields.push(field);
+            }
+            '"' => {
+                let mut field = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => match chars.next() {
+                            Some(c) => field.push(c),
+                            None => return Err(anyhow!("引号未闭合")),
+                        },
+                        Some('"') => break,
+                        Some(c) => field.push(c),
+                        None => return Err(anyhow!("引号未闭合")),
+                    }
+                }
+                fields.push(field);
+            }
+            _ => {
+                let mut field = c.to_string();
+                while let Some(c) = chars.peek() {
+                    if *c == ' ' || *c == '\t' {
+                        break;
+                    }
+                    field.push(*c);
+                    chars.next();
+                }
+                fields.push(field);
+            }
+        }
+    }
+    Ok(fields)
+}
+
+impl LogRecord {
+    fn from_fields(fields: &[String]) -> Result<Self, anyhow::Error> {
+        if fields.len() < 9 {
+            return Err(anyhow!("字段数量不足, 期望9个, 实际{}个", fields.len()));
+        }
+        let ip = fields[0]
+            .parse::<IpAddr>()
+            .map_err(|_| anyhow!("无效的IP: {}", fields[0]))?;
+        let cache = fields[1].parse::<CacheStatus>()?;
+        let response_time = match fields[2].as_str() {
+            "-" => 0,
+            v => v
+                .parse::<u64>()
+                .map_err(|_| anyhow!("无效的响应时间: {}", v))?,
+        };
+        let time = DateTime::parse_from_str(&fields[3], TIME_FORMAT)
+            .map_err(|_| anyhow!("无效的时间: {}", fields[3]))?;
+        let mut request = fields[4].splitn(3, ' ');
+        let (method, url, protocol) = match (request.next(), request.next(), request.next()) {
+            (Some(m), Some(u), Some(p)) if !m.is_empty() && !u.is_empty() => (m, u, p),
+            _ => return Err(anyhow!("无效的请求行: {}", fields[4])),
+        };
+        let status = fields[5]
+            .parse::<u16>()
+            .map_err(|_| anyhow!("无效的状态码: {}", fields[5]))?;
+        let bytes = fields[6]
+            .parse::<u64>()
+            .map_err(|_| anyhow!("无效的字节数: {}", fields[6]))?;
+        Ok(Self {
+            ip,
+            cache,
+            response_time,
+            time,
+            method: method.to_string(),
+            url: url.to_string(),
+            protocol: protocol.to_string(),
+            status,
+            bytes,
+            referer: fields[7].clone(),
+            user_agent: fields[8].clone(),
+        })
+    }
+
+    /// URL的路径部分，不含域名及参数
+    pub fn path(&self) -> &str {
+        let url = match self.url.find("://") {
+            Some(i) => match self.url[i + 3..].find('/') {
+                Some(j) => &self.url[i + 3 + j..],
+                None => "/",
+            },
+            None => &self.url,
+        };
+        url.split('?').next().unwrap_or(url)
+    }
+
+    /// URL的参数部分，不含`?`
+    pub fn query(&self) -> &str {
+        match self.url.split_once('?') {
+            Some((_, query)) => query.split('#').next().unwrap_or(query),
+            None => "",
+        }
+    }
+}
+
+/// 日志字段, 用于查询条件及选择输出列
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Ip,
+    Cache,
+    ResponseTime,
+    Time,
+    Method,
+    Url,
+    Path,
+    Protocol,
+    Status,
+    Bytes,
+    Referer,
+    UserAgent,
+}
+
+impl Field {
+    pub const ALL: [Field; 12] = [
+        Field::Ip,
+        Field::Cache,
+        Field::ResponseTime,
+        Field::Time,
+        Field::Method,
+        Field::Url,
+        Field::Path,
+        Field::Protocol,
+        Field::Status,
+        Field::Bytes,
+        Field::Referer,
+        Field::UserAgent,
+    ];
+
+    /// 是否为数值字段
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, Field::ResponseTime | Field::Status | Field::Bytes)
+    }
+
+    pub fn number(&self, record: &LogRecord) -> Option<f64> {
+        match self {
+            Field::ResponseTime => Some(record.response_time as f64),
+            Field::Status => Some(record.status as f64),
+            Field::Bytes => Some(record.bytes as f64),
+            _ => None,
+        }
+    }
+
+    /// 字段的字符串值
+    pub fn value(&self, record: &LogRecord) -> String {
+        match self {
+            Field::Ip => record.ip.to_string(),
+            Field::Cache => record.cache.to_string(),
+            Field::ResponseTime => record.response_time.to_string(),
+            Field::Time => record.time.format("%Y-%m-%d %H:%M:%S").to_string(),
+            Field::Method => record.method.clone(),
+            Field::Url => record.url.clone(),
+            Field::Path => record.path().to_string(),
+            Field::Protocol => record.protocol.clone(),
+            Field::Status => record.status.to_string(),
+            Field::Bytes => record.bytes.to_string(),
+            Field::Referer => record.referer.clone(),
+            Field::UserAgent => record.user_agent.clone(),
+        }
+    }
+
+    /// 解析逗号分隔的字段列表
+    pub fn parse_list(s: &str) -> Result<Vec<Field>, anyhow::Error> {
+        s.split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(Field::from_str)
+            .collect()
+    }
+}
+
+impl FromStr for Field {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ip" => Ok(Field::Ip),
+            "cache" | "hit" => Ok(Field::Cache),
+            "rt" | "response_time" => Ok(Field::ResponseTime),
+            "time" => Ok(Field::Time),
+            "method" => Ok(Field::Method),
+            "url" => Ok(Field::Url),
+            "path" => Ok(Field::Path),
+            "protocol" => Ok(Field::Protocol),
+            "status" => Ok(Field::Status),
+            "bytes" | "size" => Ok(Field::Bytes),
+            "referer" => Ok(Field::Referer),
+            "ua" | "user_agent" => Ok(Field::UserAgent),
+            _ => Err(anyhow!(
+                "无效的字段: {}, 可选ip cache rt time method url path protocol status bytes referer ua",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Field::Ip => "ip",
+            Field::Cache => "cache",
+            Field::ResponseTime => "rt",
+            Field::Time => "time",
+            Field::Method => "method",
+            Field::Url => "url",
+            Field::Path => "path",
+            Field::Protocol => "protocol",
+            Field::Status => "status",
+            Field::Bytes => "bytes",
+            Field::Referer => "referer",
+            Field::UserAgent => "ua",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for LogRecord {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        split_fields(line)
+            .and_then(|fields| Self::from_fields(&fields))
+            .map_err(|e| anyhow!("日志解析失败({}): {}", e, line))
+    }
+}
+
+impl Display for LogRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let quote = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+        write!(
+            f,
+            "{} {} {} [{}] \"{} {} {}\" {} {} \"{}\" \"{}\"",
+            self.ip,
+            self.cache,
+            self.response_time,
+            self.time.format(TIME_FORMAT),
+            self.method,
+            self.url,
+            self.protocol,
+            self.status,
+            self.bytes,
+            quote(&self.referer),
+            quote(&self.user_agent),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_record_test() {
+        let line = r#"101.226.66.177 HIT 12 [07/Feb/2017:11:15:40 +0800] "GET http://www.example.com/a/favicon.ico?v=1 HTTP/1.1" 200 1150 "-" "Mozilla/5.0 (compatible; \"Bot\")""#;
+        let record: LogRecord = line.parse().unwrap();
+        assert_eq!(record.ip, "101.226.66.177".parse::<IpAddr>().unwrap());
+        assert_eq!(record.cache, CacheStatus::Hit);
+        assert_eq!(record.response_time, 12);
+        assert_eq!(record.time.to_rfc3339(), "2017-02-07T11:15:40+08:00");
+        assert_eq!(record.method, "GET");
+        assert_eq!(record.path(), "/a/favicon.ico");
+        assert_eq!(record.protocol, "HTTP/1.1");
+        assert_eq!(record.status, 200);
+        assert_eq!(record.bytes, 1150);
+        assert_eq!(record.referer, "-");
+        assert_eq!(record.user_agent, "Mozilla/5.0 (compatible; \"Bot\")");
+        assert_eq!(record.to_string(), line);
+
+        let record: LogRecord =
+            r#"2408:8207::1 MISS 3 [16/Jul/2024:00:00:01 +0800] "HEAD /x HTTP/2.0" 404 0 "https://a.com/" "curl/8.0""#
+                .parse()
+                .unwrap();
+        assert_eq!(record.cache, CacheStatus::Miss);
+        assert!(record.ip.is_ipv6());
+        assert_eq!(record.path(), "/x");
+    }
+
+    #[test]
+    fn parse_record_error_test() {
+        for line in [
+            "",
+            "1.2.3.4 HIT 0",
+            r#"1.2.3.45x HIT 0 [07/Feb/2017:11:15:40 +0800] "GET / HTTP/1.1" 200 1 "-" "-""#,
+            r#"1.2.3.4 HIT 0 [07/Feb/2017 11:15:40] "GET / HTTP/1.1" 200 1 "-" "-""#,
+            r#"1.2.3.4 HIT 0 [07/Feb/2017:11:15:40 +0800] "GET / HTTP/1.1" abc 1 "-" "-""#,
+            r#"1.2.3.4 HIT 0 [07/Feb/2017:11:15:40 +0800] "GET / HTTP/1.1" 200 1 "-" "-"#,
+        ] {
+            assert!(line.parse::<LogRecord>().is_err(), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,491 @@
+//! 推送日志到Elasticsearch/OpenSearch或Loki
+//!
+//! 日志按文件解析后分批推送，失败时按指数退避重试。检查点按推送目标(地址及索引/标签)
+//! 记录每个文件已推送的条数，重复运行时跳过推送完的文件及批次。
+//! Elasticsearch的文档id由文件md5及行号生成，重推不会产生重复数据；
+//! Loki没有文档id，某批推送成功但检查点未保存时(如进程被中断)该批会重复推送
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use colored::Colorize;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{
+    export::ExportRow,
+    stream::{LogFile, LogSource, ParseErrors},
+    LogData,
+};
+use crate::{
+    config::Config,
+    utils::{data_dir, print_err, write_json_atomic},
+    Client,
+};
+
+/// 默认每批推送的条数
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// 默认重试次数
+const DEFAULT_RETRIES: usize = 3;
+
+/// 默认索引模板
+const DEFAULT_INDEX: &str = "qiniu-cdn-{domain}-{date}";
+
+/// 替换模板中的`{domain}`及`{date}`
+pub fn render_template(template: &str, domain: &str, date: &str) -> String {
+    template.replace("{domain}", domain).replace("{date}", date)
+}
+
+/// 推送目标
+#[derive(Debug, Clone)]
+pub enum ShipSink {
+    Elasticsearch {
+        url: String,
+        index: String,
+    },
+    Loki {
+        url: String,
+        labels: BTreeMap<String, String>,
+    },
+}
+
+/// 一条待推送的日志
+#[derive(Debug, Clone)]
+pub struct ShipRow {
+    /// 文件md5及行号, 用作Elasticsearch文档id
+    pub id: String,
+    pub row: ExportRow,
+}
+
+impl ShipSink {
+    pub fn from_config(config: &Config) -> Result<Self, anyhow::Error> {
+        let ship = config.ship.clone().ok_or(anyhow!("未配置[ship]"))?;
+        let url = ship
+            .url
+            .ok_or(anyhow!("[ship]未配置url"))?
+            .trim_end_matches('/')
+            .to_string();
+        match ship.kind.as_deref().unwrap_or("elasticsearch") {
+            "elasticsearch" | "opensearch" => Ok(ShipSink::Elasticsearch {
+                url,
+                index: ship.index.unwrap_or(DEFAULT_INDEX.to_string()),
+            }),
+            "loki" => Ok(ShipSink::Loki {
+                url,
+                labels: ship.labels.unwrap_or(BTreeMap::from([
+                    ("job".to_string(), "qiniu-cdn".to_string()),
+                    ("domain".to_string(), "{domain}".to_string()),
+                ])),
+            }),
+            other => Err(anyhow!("不支持的推送类型: {}", other)),
+        }
+    }
+
+    /// 推送目标, 用于区分检查点, 如`http://127.0.0.1:9200/qiniu-cdn-{domain}-{date}`
+    pub fn target(&self) -> String {
+        match self {
+            ShipSink::Elasticsearch { url, index } => format!("{}/{}", url, index),
+            ShipSink::Loki { url, labels } => format!(
+                "{}{{{}}}",
+                url,
+                labels
+                    .iter()
+                    .map(|(k, v)| format!("{}={}", k, v))
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ),
+        }
+    }
+
+    fn document(row: &ExportRow, domain: &str) -> Result<Value, anyhow::Error> {
+        let mut doc = serde_json::to_value(row)?;
+        doc["domain"] = json!(domain);
+        Ok(doc)
+    }
+
+    /// 请求地址及请求体
+    pub fn render(
+        &self,
+        rows: &[ShipRow],
+        domain: &str,
+    ) -> Result<(String, String), anyhow::Error> {
+        match self {
+            ShipSink::Elasticsearch { url, index } => {
+                let mut body = String::new();
+                for x in rows {
+                    let action = json!({"index": {
+                        "_index": render_template(index, domain, &x.row.day),
+                        "_id": x.id,
+                    }});
+                    body.push_str(&action.to_string());
+                    body.push('\n');
+                    body.push_str(&Self::document(&x.row, domain)?.to_string());
+                    body.push('\n');
+                }
+                Ok((format!("{}/_bulk", url), body))
+            }
+            ShipSink::Loki { url, labels } => {
+                let mut streams: BTreeMap<BTreeMap<String, String>, Vec<Value>> = BTreeMap::new();
+                for x in rows {
+                    let stream = labels
+                        .iter()
+                        .map(|(k, v)| (k.clone(), render_template(v, domain, &x.row.day)))
+                        .collect();
+                    streams.entry(stream).or_default().push(json!([
+                        (x.row.timestamp_millis as i128 * 1_000_000).to_string(),
+                        Self::document(&x.row, domain)?.to_string(),
+                    ]));
+                }
+                let streams: Vec<Value> = streams
+                    .into_iter()
+                    .map(|(stream, values)| json!({"stream": stream, "values": values}))
+                    .collect();
+                Ok((
+                    format!("{}/loki/api/v1/push", url),
+                    json!({ "streams": streams }).to_string(),
+                ))
+            }
+        }
+    }
+
+    async fn send(&self, config: &Config, url: &str, body: String) -> Result<(), anyhow::Error> {
+        let ship = config.ship.clone().unwrap();
+        let content_type = match self {
+            ShipSink::Elasticsearch { .. } => "application/x-ndjson",
+            ShipSink::Loki { .. } => "application/json",
+        };
+        let mut request = reqwest::Client::new()
+            .post(url)
+            .header("Content-Type", content_type)
+            .body(body);
+        if let Some(username) = ship.username {
+            request = request.basic_auth(username, ship.password);
+        } else if let Some(token) = ship.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(anyhow!("推送失败: {} {}", status, text));
+        }
+        // bulk接口部分失败时仍返回200
+        if let ShipSink::Elasticsearch { .. } = self {
+            let result: Value = serde_json::from_str(&text)?;
+            if result["errors"].as_bool().unwrap_or(false) {
+                let error = result["items"]
+                    .as_array()
+                    .and_then(|items| items.iter().find_map(|x| x["index"].get("error")))
+                    .cloned()
+                    .unwrap_or_default();
+                return Err(anyhow!("推送失败: {}", error));
+            }
+        }
+        Ok(())
+    }
+
+    /// 推送一批, 失败时按1s、2s、4s...退避重试
+    pub async fn write(
+        &self,
+        config: &Config,
+        rows: &[ShipRow],
+        domain: &str,
+    ) -> Result<(), anyhow::Error> {
+        let (url, body) = self.render(rows, domain)?;
+        let retries = config
+            .ship
+            .as_ref()
+            .and_then(|x| x.retries)
+            .unwrap_or(DEFAULT_RETRIES);
+        let mut attempt = 0;
+        loop {
+            match self.send(config, &url, body.clone()).await {
+                Ok(_) => return Ok(()),
+                Err(e) if attempt < retries => {
+                    if config.debug.unwrap_or(false) {
+                        println!("[DEBUG] 第{}次重试: {}", attempt + 1, e);
+                    }
+                    tokio::time::sleep(std::time::Duration::from_secs(1 << attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// 一个文件的推送进度
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShipProgress {
+    pub md5: String,
+    /// 已推送的条数
+    pub rows: usize,
+    pub done: bool,
+}
+
+/// 各推送目标的推送进度, key为推送目标及`域名/文件名`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Checkpoint {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    target: String,
+    pub targets: BTreeMap<String, BTreeMap<String, ShipProgress>>,
+}
+
+impl Checkpoint {
+    pub fn load(path: PathBuf, target: String) -> Result<Self, anyhow::Error> {
+        let mut checkpoint: Self = match fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s)?,
+            Err(_) => Self::default(),
+        };
+        checkpoint.path = path;
+        checkpoint.target = target;
+        Ok(checkpoint)
+    }
+
+    /// 本地日志的name为绝对路径, 直接作为key
+    fn key(domain: &str, log: &LogData) -> String {
+        if Path::new(&log.name).is_absolute() {
+            return log.name.clone();
+        }
+        format!("{}/{}", domain, log.file_name())
+    }
+
+    fn progress(&self, domain: &str, log: &LogData) -> Option<&ShipProgress> {
+        self.targets
+            .get(&self.target)?
+            .get(&Self::key(domain, log))
+            .filter(|x| x.md5 == log.md5)
+    }
+
+    pub fn shipped(&self, domain: &str, log: &LogData) -> bool {
+        self.progress(domain, log).is_some_and(|x| x.done)
+    }
+
+    /// 未推送完的文件已推送的条数
+    pub fn shipped_rows(&self, domain: &str, log: &LogData) -> usize {
+        self.progress(domain, log).map(|x| x.rows).unwrap_or(0)
+    }
+
+    pub fn mark(&mut self, domain: &str, log: &LogData, rows: usize, done: bool) {
+        self.targets.entry(self.target.clone()).or_default().insert(
+            Self::key(domain, log),
+            ShipProgress {
+                md5: log.md5.clone(),
+                rows,
+                done,
+            },
+        );
+    }
+
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        write_json_atomic(&self.path, self)
+    }
+}
+
+impl Client {
+    /// 未推送完的日志, 本地日志按路径及md5记录
+    async fn pending_logs(
+        &self,
+        source: &LogSource,
+        start_date: &str,
+        end_date: &str,
+        domain: &str,
+        checkpoint: &Checkpoint,
+    ) -> Result<Vec<(LogFile, LogData)>, anyhow::Error> {
+        let mut logs = vec![];
+        for file in self.log_files(source, start_date, end_date, domain).await? {
+            let log = file.log_data()?;
+            if !checkpoint.shipped(domain, &log) {
+                logs.push((file, log));
+            }
+        }
+        Ok(logs)
+    }
+
+    /// 推送日期区间内各域名未推送过的日志
+    pub async fn log_ship(
+        &self,
+        start_date: &str,
+        end_date: &str,
+        source: &LogSource,
+        domains: Vec<String>,
+        dry_run: bool,
+    ) -> Result<(), anyhow::Error> {
+        let sink = ShipSink::from_config(&self.config)?;
+        let batch_size = self
+            .config
+            .ship
+            .as_ref()
+            .and_then(|x| x.batch_size)
+            .unwrap_or(DEFAULT_BATCH_SIZE)
+            .max(1);
+        let mut checkpoint =
+            Checkpoint::load(data_dir().join("ship_checkpoint.json"), sink.target())?;
+        let mut errors = ParseErrors::default();
+        let mut failed = 0;
+        for domain in domains.iter() {
+            let logs = match self
+                .pending_logs(source, start_date, end_date, domain, &checkpoint)
+                .await
+            {
+                Ok(logs) => logs,
+                Err(e) => {
+                    print_err(format!("{}: {}", domain, e).as_str(), false);
+                    failed += 1;
+                    continue;
+                }
+            };
+            let mut results = self
+                .log_stream(logs.iter().map(|x| x.0.clone()).collect(), |records| {
+                    records.map(ExportRow::from).collect::<Vec<ExportRow>>()
+                });
+            let (mut files, mut total) = (0, 0);
+            for (_, log) in logs.iter() {
+                let result = match results.next().await {
+                    Some(Ok(result)) => result,
+                    Some(Err(e)) => {
+                        print_err(format!("{}: {}", log.name, e).as_str(), false);
+                        failed += 1;
+                        continue;
+                    }
+                    None => break,
+                };
+                errors.add(&result);
+                // 跳过上次已推送的批次, 行号不变所以文档id不变
+                let offset = checkpoint.shipped_rows(domain, log);
+                let rows: Vec<ShipRow> = result
+                    .value
+                    .into_iter()
+                    .enumerate()
+                    .skip(offset)
+                    .map(|(i, row)| ShipRow {
+                        id: format!("{}-{}", log.md5, i),
+                        row,
+                    })
+                    .collect();
+                let mut shipped = offset;
+                let mut ok = true;
+                for batch in rows.chunks(batch_size) {
+                    if dry_run {
+                        continue;
+                    }
+                    if let Err(e) = sink.write(&self.config, batch, domain).await {
+                        print_err(format!("{}: {}", log.name, e).as_str(), false);
+                        ok = false;
+                        break;
+                    }
+                    shipped += batch.len();
+                    checkpoint.mark(domain, log, shipped, false);
+                    checkpoint.save()?;
+                }
+                total += shipped - offset;
+                if !ok {
+                    failed += 1;
+                    continue;
+                }
+                files += 1;
+                if dry_run {
+                    total += rows.len();
+                } else {
+                    checkpoint.mark(domain, log, shipped, true);
+                    checkpoint.save()?;
+                }
+            }
+            println!(
+                "域名 {} {} 推送日志{}个, 共{}条{}",
+                domain.bold().yellow(),
+                source.describe(start_date, end_date),
+                files,
+                total.to_string().green(),
+                if dry_run { "(dry-run)" } else { "" },
+            );
+        }
+        errors.report(self.config.debug.unwrap_or(false));
+        if failed > 0 {
+            return Err(anyhow!("{}个日志推送失败", failed));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::record::LogRecord;
+
+    #[test]
+    fn render_test() {
+        let record =
+            r#"1.2.3.4 HIT 5 [16/Jul/2024:23:59:59 +0800] "GET /a HTTP/1.1" 200 100 "-" "curl""#;
+        let rows = vec![ShipRow {
+            id: "abc-0".to_string(),
+            row: ExportRow::from(record.parse::<LogRecord>().unwrap()),
+        }];
+        let es = ShipSink::Elasticsearch {
+            url: "http://127.0.0.1:9200".to_string(),
+            index: DEFAULT_INDEX.to_string(),
+        };
+        let (url, body) = es.render(&rows, "a.com").unwrap();
+        assert_eq!(url, "http://127.0.0.1:9200/_bulk");
+        let lines: Vec<Value> = body
+            .lines()
+            .map(|x| serde_json::from_str(x).unwrap())
+            .collect();
+        assert_eq!(lines[0]["index"]["_index"], "qiniu-cdn-a.com-2024-07-16");
+        assert_eq!(lines[0]["index"]["_id"], "abc-0");
+        assert_eq!(lines[1]["domain"], "a.com");
+        assert_eq!(lines[1]["status"], 200);
+
+        let loki = ShipSink::Loki {
+            url: "http://127.0.0.1:3100".to_string(),
+            labels: BTreeMap::from([("domain".to_string(), "{domain}".to_string())]),
+        };
+        let (url, body) = loki.render(&rows, "a.com").unwrap();
+        assert_eq!(url, "http://127.0.0.1:3100/loki/api/v1/push");
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["streams"][0]["stream"]["domain"], "a.com");
+        assert_eq!(body["streams"][0]["values"][0][0], "1721145599000000000");
+    }
+
+    #[test]
+    fn checkpoint_test() {
+        let es = ShipSink::Elasticsearch {
+            url: "http://127.0.0.1:9200".to_string(),
+            index: DEFAULT_INDEX.to_string(),
+        };
+        let loki = ShipSink::Loki {
+            url: "http://127.0.0.1:3100".to_string(),
+            labels: BTreeMap::from([("job".to_string(), "cdn".to_string())]),
+        };
+        assert_eq!(loki.target(), "http://127.0.0.1:3100{job=cdn}");
+        let log = LogData::for_test("v2/a.com_2024-07-16-02_part-00000.gz", "abc");
+        let mut checkpoint = Checkpoint {
+            target: es.target(),
+            ..Default::default()
+        };
+        checkpoint.mark("a.com", &log, 1000, false);
+        assert!(!checkpoint.shipped("a.com", &log));
+        assert_eq!(checkpoint.shipped_rows("a.com", &log), 1000);
+        checkpoint.mark("a.com", &log, 1500, true);
+        assert!(checkpoint.shipped("a.com", &log));
+        // 换推送目标或文件变化后重新推送
+        checkpoint.target = loki.target();
+        assert!(!checkpoint.shipped("a.com", &log));
+        assert_eq!(checkpoint.shipped_rows("a.com", &log), 0);
+        checkpoint.target = es.target();
+        let changed = LogData::for_test(&log.name, "def");
+        assert!(!checkpoint.shipped("a.com", &changed));
+        // 本地日志按绝对路径记录
+        let local = LogData::for_test("/data/a/a.com_2024-07-16-02_part-00000.gz", "abc");
+        assert_eq!(Checkpoint::key("a.com", &local), local.name);
+        assert!(!checkpoint.shipped("a.com", &local));
+    }
+}
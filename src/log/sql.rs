@@ -0,0 +1,430 @@
+//! 用SQL查询日志
+//!
+//! 解析后的日志导入SQLite(默认缓存目录下的`log-sql.db`, 可用`[cache]sql_db`配置)，按文件名(本地日志为路径)及md5记录已导入的文件，
+//! 再次查询时只导入新文件。查询时`logs`视图只包含所选域名及日期区间的日志，
+//! 超过7天未查询的文件会从数据库中删除。只允许执行只读的SQL
+
+#![allow(clippy::too_many_arguments)]
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+use chrono::{Duration, Local};
+use colored::Colorize;
+use futures::StreamExt;
+use rusqlite::{params, types::ValueRef, Connection, OptionalExtension};
+use serde_json::{json, Map, Value};
+use unicode_width::UnicodeWidthStr;
+
+use super::{
+    cache::cache_dir,
+    export::{csv_field, ExportRow},
+    stream::{LogSource, ParseErrors},
+    LogData,
+};
+use crate::{
+    utils::{pad, print_err, truncate, WaitBlinker},
+    Client,
+};
+
+/// 超过该天数未查询的文件从数据库删除
+const EXPIRE_DAYS: i64 = 7;
+
+/// 表格中单元格的最大宽度, 超出部分截断
+const MAX_CELL_WIDTH: usize = 80;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS files (
+    id INTEGER PRIMARY KEY,
+    domain TEXT NOT NULL,
+    name TEXT NOT NULL,
+    md5 TEXT NOT NULL,
+    rows INTEGER NOT NULL,
+    accessed_at INTEGER NOT NULL,
+    UNIQUE (domain, name)
+);
+CREATE TABLE IF NOT EXISTS records (
+    file_id INTEGER NOT NULL,
+    domain TEXT NOT NULL,
+    ip TEXT NOT NULL,
+    timestamp TEXT NOT NULL,
+    ts INTEGER NOT NULL,
+    day TEXT NOT NULL,
+    hour INTEGER NOT NULL,
+    method TEXT NOT NULL,
+    url TEXT NOT NULL,
+    path TEXT NOT NULL,
+    query TEXT NOT NULL,
+    status INTEGER NOT NULL,
+    bytes INTEGER NOT NULL,
+    response_time INTEGER NOT NULL,
+    referer TEXT NOT NULL,
+    user_agent TEXT NOT NULL,
+    hit INTEGER
+);
+CREATE INDEX IF NOT EXISTS records_file ON records (file_id);
+";
+
+/// 输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqlFormat {
+    Table,
+    Csv,
+    Jsonl,
+}
+
+impl FromStr for SqlFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(Self::Table),
+            "csv" => Ok(Self::Csv),
+            "jsonl" | "json" => Ok(Self::Jsonl),
+            _ => Err(anyhow!("不支持的输出格式: {}, 可选table csv jsonl", s)),
+        }
+    }
+}
+
+/// 查询结果
+#[derive(Debug, Default, PartialEq)]
+pub struct SqlResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl SqlResult {
+    fn cell(v: &Value) -> String {
+        match v {
+            Value::Null => "NULL".to_string(),
+            Value::String(s) => s.clone(),
+            v => v.to_string(),
+        }
+    }
+
+    pub fn print(&self, format: SqlFormat, out: &mut dyn Write) -> Result<(), anyhow::Error> {
+        match format {
+            SqlFormat::Table => {
+                let cells: Vec<Vec<String>> = self
+                    .rows
+                    .iter()
+                    .map(|row| {
+                        row.iter()
+                            .map(|v| truncate(&Self::cell(v), MAX_CELL_WIDTH))
+                            .collect()
+                    })
+                    .collect();
+                let widths: Vec<usize> = self
+                    .columns
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| {
+                        cells
+                            .iter()
+                            .map(|row| row[i].width_cjk())
+                            .chain([c.width_cjk()])
+                            .max()
+                            .unwrap_or(0)
+                    })
+                    .collect();
+                let line = |row: &[String]| -> String {
+                    row.iter()
+                        .zip(widths.iter())
+                        .map(|(x, w)| pad(x, *w))
+                        .collect::<Vec<String>>()
+                        .join("  ")
+                };
+                writeln!(out, "{}", line(&self.columns).bold().green())?;
+                for row in cells.iter() {
+                    writeln!(out, "{}", line(row))?;
+                }
+                writeln!(out, "{}{}", "Total: ".cyan().bold(), self.rows.len())?;
+            }
+            SqlFormat::Csv => {
+                let header: Vec<String> = self.columns.iter().map(|x| csv_field(x)).collect();
+                writeln!(out, "{}", header.join(","))?;
+                for row in self.rows.iter() {
+                    let row: Vec<String> = row
+                        .iter()
+                        .map(|v| match v {
+                            Value::Null => "".to_string(),
+                            v => csv_field(&Self::cell(v)),
+                        })
+                        .collect();
+                    writeln!(out, "{}", row.join(","))?;
+                }
+            }
+            SqlFormat::Jsonl => {
+                for row in self.rows.iter() {
+                    let object: Map<String, Value> = self
+                        .columns
+                        .iter()
+                        .cloned()
+                        .zip(row.iter().cloned())
+                        .collect();
+                    writeln!(out, "{}", Value::Object(object))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct LogDb {
+    conn: Connection,
+}
+
+impl LogDb {
+    pub fn open(path: &Path) -> Result<Self, anyhow::Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// 删除过期的文件
+    pub fn expire(&mut self) -> Result<usize, anyhow::Error> {
+        let expire = (Local::now() - Duration::days(EXPIRE_DAYS)).timestamp();
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM records WHERE file_id IN (SELECT id FROM files WHERE accessed_at < ?1)",
+            params![expire],
+        )?;
+        let n = tx.execute("DELETE FROM files WHERE accessed_at < ?1", params![expire])?;
+        tx.commit()?;
+        Ok(n)
+    }
+
+    /// 已导入且md5一致时返回文件id, 并更新查询时间
+    pub fn imported(&self, domain: &str, log: &LogData) -> Result<Option<i64>, anyhow::Error> {
+        let id: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT id FROM files WHERE domain = ?1 AND name = ?2 AND md5 = ?3",
+                params![domain, log.name, log.md5],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(id) = id {
+            self.conn.execute(
+                "UPDATE files SET accessed_at = ?1 WHERE id = ?2",
+                params![Local::now().timestamp(), id],
+            )?;
+        }
+        Ok(id)
+    }
+
+    /// 导入一个文件的记录, 同名文件的旧记录会被替换
+    pub fn import(
+        &mut self,
+        domain: &str,
+        log: &LogData,
+        rows: &[ExportRow],
+    ) -> Result<i64, anyhow::Error> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM records WHERE file_id IN (SELECT id FROM files WHERE domain = ?1 AND name = ?2)",
+            params![domain, log.name],
+        )?;
+        tx.execute(
+            "DELETE FROM files WHERE domain = ?1 AND name = ?2",
+            params![domain, log.name],
+        )?;
+        tx.execute(
+            "INSERT INTO files (domain, name, md5, rows, accessed_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                domain,
+                log.name,
+                log.md5,
+                rows.len(),
+                Local::now().timestamp()
+            ],
+        )?;
+        let id = tx.last_insert_rowid();
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO records VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+            )?;
+            for row in rows {
+                stmt.execute(params![
+                    id,
+                    domain,
+                    row.ip,
+                    row.timestamp,
+                    row.timestamp_millis / 1000,
+                    row.day,
+                    row.timestamp
+                        .get(11..13)
+                        .and_then(|x| x.parse::<i64>().ok()),
+                    row.method,
+                    row.url,
+                    row.path,
+                    row.query,
+                    row.status,
+                    row.bytes as i64,
+                    row.response_time as i64,
+                    row.referer,
+                    row.user_agent,
+                    row.hit,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(id)
+    }
+
+    /// 在只包含`file_ids`的`logs`视图上执行SQL
+    pub fn query(&self, file_ids: &[i64], sql: &str) -> Result<SqlResult, anyhow::Error> {
+        self.conn.execute_batch(&format!(
+            "DROP VIEW IF EXISTS temp.logs;
+            CREATE TEMP VIEW logs AS SELECT domain, ip, timestamp, ts, day, hour, method, url, path, query,
+                status, bytes, response_time, referer, user_agent, hit
+            FROM records WHERE file_id IN ({});",
+            file_ids
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<String>>()
+                .join(",")
+        ))?;
+        let mut stmt = self.conn.prepare(sql)?;
+        // 数据库是导入的缓存, 不允许修改
+        if !stmt.readonly() {
+            return Err(anyhow!("只支持只读的SQL, 如SELECT"));
+        }
+        let columns: Vec<String> = stmt.column_names().iter().map(|x| x.to_string()).collect();
+        let mut rows = stmt.query([])?;
+        let mut result = SqlResult {
+            columns,
+            rows: vec![],
+        };
+        while let Some(row) = rows.next()? {
+            let mut values = vec![];
+            for i in 0..result.columns.len() {
+                values.push(match row.get_ref(i)? {
+                    ValueRef::Null => Value::Null,
+                    ValueRef::Integer(x) => json!(x),
+                    ValueRef::Real(x) => json!(x),
+                    ValueRef::Text(x) => json!(String::from_utf8_lossy(x)),
+                    ValueRef::Blob(x) => json!(format!("<blob {} bytes>", x.len())),
+                });
+            }
+            result.rows.push(values);
+        }
+        Ok(result)
+    }
+}
+
+impl Client {
+    /// 导入各域名日期区间内的日志并执行SQL
+    pub async fn log_sql(
+        &self,
+        sql: &str,
+        format: SqlFormat,
+        start_date: &str,
+        end_date: &str,
+        source: &LogSource,
+        domains: Vec<String>,
+        wait_blink: Option<WaitBlinker>,
+    ) -> Result<(), anyhow::Error> {
+        let path = self
+            .config
+            .cache
+            .as_ref()
+            .and_then(|x| x.sql_db.clone())
+            .map(PathBuf::from)
+            .unwrap_or(cache_dir().join("log-sql.db"));
+        let mut db = LogDb::open(&path)?;
+        db.expire()?;
+        let mut file_ids = vec![];
+        let mut errors = ParseErrors::default();
+        for domain in domains.iter() {
+            let mut pending = vec![];
+            for file in self.log_files(source, start_date, end_date, domain).await? {
+                // 本地日志按路径及md5记录
+                let log = file.log_data()?;
+                match db.imported(domain, &log)? {
+                    Some(id) => file_ids.push(id),
+                    None => pending.push((file, log)),
+                }
+            }
+            let mut results = self
+                .log_stream(pending.iter().map(|x| x.0.clone()).collect(), |records| {
+                    records.map(ExportRow::from).collect::<Vec<ExportRow>>()
+                });
+            for (_, log) in pending.iter() {
+                let result = match results.next().await {
+                    Some(result) => result?,
+                    None => break,
+                };
+                errors.add(&result);
+                file_ids.push(db.import(domain, log, &result.value)?);
+            }
+        }
+        if let Some(blinker) = wait_blink {
+            blinker.sender.send(true).unwrap();
+            blinker.handle.await?;
+        }
+        errors.report(self.config.debug.unwrap_or(false));
+        if file_ids.is_empty() {
+            print_err(crate::NOT_FOUND_MSG, false);
+        }
+        let result = db.query(&file_ids, sql)?;
+        result.print(format, &mut std::io::stdout().lock())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::{record::LogRecord, TempDir};
+
+    #[test]
+    fn log_db_test() {
+        let dir = TempDir::new("log-sql");
+        let mut db = LogDb::open(&dir.join("log-sql.db")).unwrap();
+        let rows = |lines: &[&str]| -> Vec<ExportRow> {
+            lines
+                .iter()
+                .map(|x| ExportRow::from(x.parse::<LogRecord>().unwrap()))
+                .collect()
+        };
+        let log = LogData::for_test;
+        let a = log("v2/a.com_2024-07-16-02_part-00000.gz", "a");
+        let b = log("v2/a.com_2024-07-16-03_part-00000.gz", "b");
+        let line = r#"1.2.3.4 HIT 5 [16/Jul/2024:02:00:00 +0800] "GET /a HTTP/1.1" 403 100 "https://x.com/" "curl""#;
+        let other =
+            r#"1.2.3.5 - 5 [16/Jul/2024:03:00:00 +0800] "GET /b HTTP/1.1" 200 50 "-" "curl""#;
+        assert_eq!(db.imported("a.com", &a).unwrap(), None);
+        let id_a = db.import("a.com", &a, &rows(&[line, line])).unwrap();
+        let id_b = db.import("a.com", &b, &rows(&[other])).unwrap();
+        assert_eq!(db.imported("a.com", &a).unwrap(), Some(id_a));
+        // md5变化时需要重新导入
+        assert_eq!(db.imported("a.com", &log(&a.name, "c")).unwrap(), None);
+
+        let sql = "SELECT referer, count(*) AS n, sum(bytes) AS bytes FROM logs WHERE status = 403 GROUP BY referer";
+        let result = db.query(&[id_a, id_b], sql).unwrap();
+        assert_eq!(result.columns, vec!["referer", "n", "bytes"]);
+        assert_eq!(
+            result.rows,
+            vec![vec![json!("https://x.com/"), json!(2), json!(200)]]
+        );
+        let result = db.query(&[id_b], "SELECT hour, hit FROM logs").unwrap();
+        assert_eq!(result.rows, vec![vec![json!(3), Value::Null]]);
+
+        let mut out = vec![];
+        result.print(SqlFormat::Csv, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "hour,hit\n3,\n");
+        assert!(db.query(&[id_a], "SELECT * FROM nothing").is_err());
+        assert!(db.query(&[id_a], "DELETE FROM records").is_err());
+        assert!(db.query(&[id_a], "DROP TABLE files").is_err());
+        assert_eq!(db.imported("a.com", &a).unwrap(), Some(id_a));
+    }
+}
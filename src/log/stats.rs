@@ -0,0 +1,391 @@
+//! 日志统计
+
+#![allow(clippy::too_many_arguments)]
+
+use std::{collections::HashMap, fmt::Display, ops::Div, str::FromStr};
+
+use anyhow::anyhow;
+use colored::Colorize;
+use futures::StreamExt;
+
+use super::{
+    query::Expr,
+    record::LogRecord,
+    stream::{LogSource, ParseErrors},
+};
+use crate::{
+    utils::{ipdb::IpDb, max_length, pad, WaitBlinker},
+    Client,
+};
+
+/// 统计维度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dimension {
+    Ip,
+    Url,
+    Prefix,
+    Referer,
+    UserAgent,
+    Status,
+    Ext,
+    Cache,
+    /// 省份(海外为国家), 需要配置`[ipdb]`
+    Province,
+    /// 运营商, 需要配置`[ipdb]`
+    Isp,
+}
+
+impl Dimension {
+    pub const ALL: [Dimension; 8] = [
+        Dimension::Ip,
+        Dimension::Url,
+        Dimension::Prefix,
+        Dimension::Referer,
+        Dimension::UserAgent,
+        Dimension::Status,
+        Dimension::Ext,
+        Dimension::Cache,
+    ];
+
+    pub fn parse_list(s: &str) -> Result<Vec<Dimension>, anyhow::Error> {
+        s.split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(Dimension::from_str)
+            .collect()
+    }
+
+    /// 记录在该维度下的取值
+    pub fn key(&self, record: &LogRecord, prefix_depth: usize) -> String {
+        match self {
+            Dimension::Ip => record.ip.to_string(),
+            Dimension::Url => record.url.clone(),
+            Dimension::Prefix => path_prefix(record.path(), prefix_depth),
+            Dimension::Referer => referer_host(&record.referer),
+            Dimension::UserAgent => record.user_agent.clone(),
+            Dimension::Status => record.status.to_string(),
+            Dimension::Ext => extension(record.path()),
+            Dimension::Cache => record.cache.to_string(),
+            // 先按IP统计, 汇总后再通过IP库转换, 每个IP只查询一次
+            Dimension::Province | Dimension::Isp => record.ip.to_string(),
+        }
+    }
+
+    pub fn need_ipdb(&self) -> bool {
+        matches!(self, Dimension::Province | Dimension::Isp)
+    }
+}
+
+impl FromStr for Dimension {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ip" => Ok(Dimension::Ip),
+            "url" => Ok(Dimension::Url),
+            "prefix" => Ok(Dimension::Prefix),
+            "referer" => Ok(Dimension::Referer),
+            "ua" => Ok(Dimension::UserAgent),
+            "status" => Ok(Dimension::Status),
+            "ext" => Ok(Dimension::Ext),
+            "cache" => Ok(Dimension::Cache),
+            "province" => Ok(Dimension::Province),
+            "isp" => Ok(Dimension::Isp),
+            _ => Err(anyhow!(
+                "无效的统计维度: {}, 可选ip url prefix referer ua status ext cache province isp",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for Dimension {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Dimension::Ip => "IP",
+            Dimension::Url => "URL",
+            Dimension::Prefix => "Prefix",
+            Dimension::Referer => "Referer",
+            Dimension::UserAgent => "UserAgent",
+            Dimension::Status => "Status",
+            Dimension::Ext => "Ext",
+            Dimension::Cache => "Cache",
+            Dimension::Province => "Province",
+            Dimension::Isp => "ISP",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// 路径的前`depth`级目录, 如`/a/b/c.js`的1级为`/a/`
+fn path_prefix(path: &str, depth: usize) -> String {
+    // 空路径或不以`/`开头的原样返回
+    let rest = match path.strip_prefix('/') {
+        Some(k) => k,
+        None => return path.to_string(),
+    };
+    let mut end = 0;
+    for _ in 0..depth.max(1) {
+        match rest[end..].find('/') {
+            Some(i) => end += i + 1,
+            None => return path.to_string(),
+        }
+    }
+    path[..end + 1].to_string()
+}
+
+/// Referer的域名
+fn referer_host(referer: &str) -> String {
+    match referer.split_once("://") {
+        Some((_, rest)) => rest
+            .split(['/', '?', '#'])
+            .next()
+            .unwrap_or(rest)
+            .to_string(),
+        None => referer.to_string(),
+    }
+}
+
+/// 文件扩展名, 没有扩展名为`-`
+fn extension(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    match name.rsplit_once('.') {
+        Some((_, ext)) if !ext.is_empty() => ext.to_lowercase(),
+        _ => "-".to_string(),
+    }
+}
+
+/// 各维度的请求次数及字节数
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub count: u64,
+    pub bytes: u64,
+    pub tables: HashMap<Dimension, HashMap<String, (u64, u64)>>,
+}
+
+impl Stats {
+    pub fn add(&mut self, record: &LogRecord, dimensions: &[Dimension], prefix_depth: usize) {
+        self.count += 1;
+        self.bytes += record.bytes;
+        for d in dimensions {
+            let v = self
+                .tables
+                .entry(*d)
+                .or_default()
+                .entry(d.key(record, prefix_depth))
+                .or_default();
+            v.0 += 1;
+            v.1 += record.bytes;
+        }
+    }
+
+    pub fn merge(&mut self, other: Stats) {
+        self.count += other.count;
+        self.bytes += other.bytes;
+        for (d, table) in other.tables {
+            let t = self.tables.entry(d).or_default();
+            for (k, (count, bytes)) in table {
+                let v = t.entry(k).or_default();
+                v.0 += count;
+                v.1 += bytes;
+            }
+        }
+    }
+
+    /// 把省份及运营商维度中的IP转换成IP库中的名称, 没有查到的IP归为未知
+    pub fn resolve_ip(&mut self, ipdb: &IpDb) {
+        for d in [Dimension::Province, Dimension::Isp] {
+            let table = match self.tables.remove(&d) {
+                Some(t) => t,
+                None => continue,
+            };
+            let mut resolved: HashMap<String, (u64, u64)> = HashMap::new();
+            for (ip, (count, bytes)) in table {
+                let info = ipdb.lookup_str(&ip);
+                let key = match (d, info) {
+                    (Dimension::Province, Some(info)) => info.region_name(),
+                    (_, Some(info)) => info.isp_name().to_string(),
+                    (Dimension::Province, None) => "未知地区".to_string(),
+                    (_, None) => "其他".to_string(),
+                };
+                let v = resolved.entry(key).or_default();
+                v.0 += count;
+                v.1 += bytes;
+            }
+            self.tables.insert(d, resolved);
+        }
+    }
+
+    /// 按请求次数或字节数倒序的前`limit`条
+    pub fn top(
+        &self,
+        dimension: Dimension,
+        limit: usize,
+        by_bytes: bool,
+    ) -> Vec<(String, u64, u64)> {
+        let mut rows: Vec<(String, u64, u64)> = self
+            .tables
+            .get(&dimension)
+            .map(|t| t.iter().map(|(k, v)| (k.clone(), v.0, v.1)).collect())
+            .unwrap_or_default();
+        if by_bytes {
+            rows.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        } else {
+            rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        }
+        rows.truncate(limit);
+        rows
+    }
+}
+
+impl Client {
+    /// 统计日志中各维度的TOP请求
+    pub async fn log_stats(
+        self,
+        query: Option<Expr>,
+        dimensions: Vec<Dimension>,
+        limit: Option<i32>,
+        by_bytes: bool,
+        prefix_depth: usize,
+        start_date: &str,
+        end_date: &str,
+        wait_blink: Option<WaitBlinker>,
+        source: &LogSource,
+        domain: &str,
+    ) -> Result<(), anyhow::Error> {
+        let ipdb = IpDb::from_config_or_warn(&self.config);
+        if ipdb.is_none() && dimensions.iter().any(|x| x.need_ipdb()) {
+            return Err(anyhow!("按province或isp统计需要配置[ipdb]"));
+        }
+        let logs = self.log_files(source, start_date, end_date, domain).await?;
+        let dims = dimensions.clone();
+        let mut results = self.log_stream(logs, move |records| {
+            let mut stats = Stats::default();
+            for record in records.filter(|x| query.as_ref().is_none_or(|e| e.matches(x))) {
+                stats.add(&record, &dims, prefix_depth);
+            }
+            stats
+        });
+        let mut stats = Stats::default();
+        let mut errors = ParseErrors::default();
+        while let Some(result) = results.next().await {
+            let result = result?;
+            errors.add(&result);
+            stats.merge(result.value);
+        }
+        if let Some(ipdb) = ipdb.as_ref() {
+            stats.resolve_ip(ipdb);
+        }
+        if let Some(blinker) = wait_blink {
+            blinker.sender.send(true).unwrap();
+            blinker.handle.await?;
+        }
+        errors.report(self.config.debug.unwrap_or(false));
+        println!(
+            "域名 {} {} 日志统计, 请求次数: {}, 流量: {}MB",
+            domain.bold().yellow(),
+            source.describe(start_date, end_date).bold(),
+            stats.count.to_string().bold(),
+            format!("{:.4}", (stats.bytes as f64).div(1024.0).div(1024.0)).bold(),
+        );
+        let limit = limit.unwrap_or(10).max(0) as usize;
+        for d in dimensions {
+            let rows = stats.top(d, limit, by_bytes);
+            if rows.is_empty() {
+                continue;
+            }
+            let width = max_length(&rows.iter().map(|x| x.0.clone()), limit as i32).clamp(20, 100);
+            println!();
+            // IP维度在最后一列附加归属地
+            let location = match (d, ipdb.as_ref()) {
+                (Dimension::Ip, Some(_)) => format!(" {}", "Location".bold().green()),
+                _ => "".to_string(),
+            };
+            println!(
+                "{} {:^10} {:^10} {:^16} {:^10}{}",
+                pad(&d.to_string(), width).bold().green(),
+                "Count".bold().green(),
+                "Percent".bold().green(),
+                "Traffic(MB)".bold().green(),
+                "Percent".bold().green(),
+                location,
+            );
+            for (k, count, bytes) in rows {
+                let location = match (d, ipdb.as_ref()) {
+                    (Dimension::Ip, Some(ipdb)) => format!(
+                        " {}",
+                        ipdb.lookup_str(&k)
+                            .map(|x| x.to_string())
+                            .unwrap_or("未知".to_string())
+                    ),
+                    _ => "".to_string(),
+                };
+                println!(
+                    "{} {:^10} {:^10} {:^16} {:^10}{}",
+                    pad(&k, width),
+                    count,
+                    format!("{:.2}%", count as f64 / stats.count.max(1) as f64 * 100.0),
+                    format!("{:.4}", (bytes as f64).div(1024.0).div(1024.0)),
+                    format!("{:.2}%", bytes as f64 / stats.bytes.max(1) as f64 * 100.0),
+                    location,
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_test() {
+        let lines = [
+            r#"1.2.3.4 HIT 0 [16/Jul/2024:00:00:01 +0800] "GET http://a.com/static/js/a.JS?v=1 HTTP/1.1" 200 100 "https://b.com/x?y" "curl""#,
+            r#"1.2.3.4 MISS 0 [16/Jul/2024:00:00:02 +0800] "GET http://a.com/static/b.png HTTP/1.1" 200 300 "-" "curl""#,
+            r#"1.2.3.5 MISS 0 [16/Jul/2024:00:00:03 +0800] "GET http://a.com/index HTTP/1.1" 404 1000 "-" "Mozilla""#,
+        ];
+        let mut stats = Stats::default();
+        for (i, line) in lines.iter().enumerate() {
+            let mut s = Stats::default();
+            s.add(&line.parse().unwrap(), &Dimension::ALL, 1);
+            if i == 0 {
+                stats = s;
+            } else {
+                stats.merge(s);
+            }
+        }
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.bytes, 1400);
+        assert_eq!(
+            stats.top(Dimension::Ip, 10, false)[0],
+            ("1.2.3.4".to_string(), 2, 400)
+        );
+        assert_eq!(stats.top(Dimension::Ip, 10, true)[0].0, "1.2.3.5");
+        assert_eq!(stats.top(Dimension::Prefix, 1, false)[0].0, "/static/");
+        assert_eq!(stats.top(Dimension::Referer, 10, false)[1].0, "b.com");
+        let exts: Vec<String> = stats
+            .top(Dimension::Ext, 10, false)
+            .into_iter()
+            .map(|x| x.0)
+            .collect();
+        assert_eq!(exts, vec!["-", "js", "png"]);
+        assert_eq!(path_prefix("/a/b/c.js", 2), "/a/b/");
+        assert_eq!(path_prefix("/c.js", 2), "/c.js");
+    }
+
+    #[test]
+    fn path_prefix_test() {
+        assert_eq!(path_prefix("", 1), "");
+        assert_eq!(path_prefix("a/b.js", 1), "a/b.js");
+        assert_eq!(path_prefix("中/b.js", 1), "中/b.js");
+        assert_eq!(path_prefix("/中文/b.js", 1), "/中文/");
+        assert_eq!(path_prefix("/a//b.js", 2), "/a//");
+        let record =
+            r#"1.2.3.4 HIT 0 [16/Jul/2024:00:00:01 +0800] "GET ?x HTTP/1.1" 200 100 "-" "curl""#
+                .parse()
+                .unwrap();
+        assert_eq!(Dimension::Prefix.key(&record, 1), "");
+    }
+}
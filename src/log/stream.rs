@@ -0,0 +1,377 @@
+//! 日志流式处理
+//!
+//! 日志文件逐个下载到本地缓存，在阻塞线程中边解压边解析，只保留每个文件的处理结果，
+//! 结果按文件顺序输出，同时处理的文件数有上限，内存占用不随日志总量增长
+
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::anyhow;
+use flate2::read::GzDecoder;
+use futures::{stream, Stream, StreamExt};
+
+use super::{cache::LogCache, record::LogRecord, sync::file_md5, LogData};
+use crate::{utils::print_err, Client};
+
+/// 默认同时处理的日志文件数
+pub const LOG_CONCURRENCY: usize = 8;
+
+/// 最多保留的解析错误示例数
+const MAX_ERROR_SAMPLES: usize = 5;
+
+/// 单个文件的解析结果迭代器，解析失败的行计入错误
+pub struct Records {
+    lines: std::io::Lines<Box<dyn BufRead>>,
+    pub error_count: usize,
+    pub errors: Vec<String>,
+}
+
+impl Records {
+    pub fn new(reader: Box<dyn BufRead>) -> Self {
+        Self {
+            lines: reader.lines(),
+            error_count: 0,
+            errors: vec![],
+        }
+    }
+
+    fn error(&mut self, e: String) {
+        self.error_count += 1;
+        if self.errors.len() < MAX_ERROR_SAMPLES {
+            self.errors.push(e);
+        }
+    }
+}
+
+impl Iterator for Records {
+    type Item = LogRecord;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.lines.next()? {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => match line.parse::<LogRecord>() {
+                    Ok(record) => return Some(record),
+                    Err(e) => self.error(e.to_string()),
+                },
+                Err(e) => {
+                    // 文件损坏时后面的内容也无法读取
+                    self.error(format!("读取日志失败: {}", e));
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// 日志来源
+#[derive(Debug, Clone, Default)]
+pub enum LogSource {
+    /// 通过接口下载
+    #[default]
+    Api,
+    /// 本地目录、文件或glob
+    Local(Vec<String>),
+}
+
+impl LogSource {
+    pub fn new(from_dir: Option<String>, files: Vec<String>) -> Self {
+        let mut patterns = files;
+        if let Some(dir) = from_dir {
+            patterns.push(format!("{}/**/*", dir.trim_end_matches('/')));
+        }
+        if patterns.is_empty() {
+            LogSource::Api
+        } else {
+            LogSource::Local(patterns)
+        }
+    }
+}
+
+impl LogSource {
+    /// 输出时显示的日志范围
+    pub fn describe(&self, start_date: &str, end_date: &str) -> String {
+        match self {
+            LogSource::Api => format!("{}～{}", start_date, end_date),
+            LogSource::Local(_) => "本地日志".to_string(),
+        }
+    }
+}
+
+/// 待处理的日志文件
+#[derive(Debug, Clone)]
+pub enum LogFile {
+    Remote(LogData),
+    Local(PathBuf),
+}
+
+impl LogFile {
+    /// 用于记录已处理文件的信息, 本地日志的name为绝对路径, md5为文件内容的md5
+    pub fn log_data(&self) -> Result<LogData, anyhow::Error> {
+        match self {
+            LogFile::Remote(log) => Ok(log.clone()),
+            LogFile::Local(path) => Ok(LogData {
+                name: fs::canonicalize(path)?.display().to_string(),
+                size: 0,
+                mtime: 0,
+                url: String::new(),
+                md5: file_md5(path)?,
+            }),
+        }
+    }
+}
+
+/// 展开本地日志的路径及glob, 按路径排序
+pub fn local_files(patterns: &[String]) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut files = vec![];
+    for pattern in patterns {
+        let mut matched = false;
+        for path in glob::glob(pattern)? {
+            let path = path?;
+            // 跳过目录及未下载完成的文件
+            if !path.is_file() || path.extension().is_some_and(|x| x == "part") {
+                continue;
+            }
+            matched = true;
+            files.push(path);
+        }
+        if !matched {
+            return Err(anyhow!("没有找到日志文件: {}", pattern));
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// 打开日志文件, 根据文件头判断是否为gzip
+pub fn open_log(path: &Path) -> Result<Box<dyn BufRead>, anyhow::Error> {
+    let mut file = File::open(path)?;
+    let mut magic = [0u8; 2];
+    let n = file.read(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+    if n == 2 && magic == [0x1f, 0x8b] {
+        Ok(Box::new(BufReader::new(GzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// 单个文件的处理结果
+pub struct FileResult<T> {
+    pub value: T,
+    pub error_count: usize,
+    pub errors: Vec<String>,
+}
+
+/// 汇总解析错误
+#[derive(Debug, Default)]
+pub struct ParseErrors {
+    pub count: usize,
+    pub samples: Vec<String>,
+}
+
+impl ParseErrors {
+    pub fn add<T>(&mut self, result: &FileResult<T>) {
+        self.count += result.error_count;
+        for e in result.errors.iter() {
+            if self.samples.len() < MAX_ERROR_SAMPLES {
+                self.samples.push(e.clone());
+            }
+        }
+    }
+
+    pub fn report(&self, debug: bool) {
+        if self.count == 0 {
+            return;
+        }
+        if debug {
+            for e in self.samples.iter() {
+                println!("[DEBUG] {}", e);
+            }
+        }
+        print_err(
+            format!("{}行日志解析失败, 如: {}", self.count, self.samples[0]).as_str(),
+            false,
+        );
+    }
+}
+
+impl Client {
+    /// 同时下载或处理的日志文件数, 配置文件的`download_concurrency`, 默认8
+    pub fn log_concurrency(&self) -> usize {
+        self.config
+            .download_concurrency
+            .unwrap_or(LOG_CONCURRENCY)
+            .max(1)
+    }
+
+    /// 下载日志到本地缓存, 已下载的直接返回缓存路径
+    pub async fn fetch_log(&self, log: &LogData) -> Result<PathBuf, anyhow::Error> {
+        self.fetch_log_with_progress(log, &|_| {}).await
+    }
+
+    /// 同`fetch_log`, 每写入一块数据调用一次`on_progress`, 命中缓存时按文件大小调用一次
+    pub async fn fetch_log_with_progress(
+        &self,
+        log: &LogData,
+        on_progress: &(dyn Fn(u64) + Send + Sync),
+    ) -> Result<PathBuf, anyhow::Error> {
+        let cache = LogCache::new(&self.config);
+        if let Some(path) = cache.get(&log.md5, &log.name) {
+            on_progress(fs::metadata(&path)?.len());
+            return Ok(path);
+        }
+        if self.config.debug.unwrap_or(false) {
+            println!("[DEBUG] Log url: {}", log.url);
+        }
+        let path = cache.path(&log.md5, &log.name);
+        fs::create_dir_all(path.parent().unwrap())?;
+        // 边下载边写入临时文件，完成后再重命名，避免中断或并发运行时读到不完整的缓存
+        let part = cache.part_path(&path);
+        let result = async {
+            let mut response = reqwest::get(&log.url).await?.error_for_status()?;
+            let mut f = File::create(&part)?;
+            while let Some(chunk) = response.chunk().await? {
+                f.write_all(&chunk)?;
+                on_progress(chunk.len() as u64);
+            }
+            f.flush()?;
+            fs::rename(&part, &path)?;
+            Ok::<(), anyhow::Error>(())
+        }
+        .await;
+        if let Err(e) = result {
+            let _ = fs::remove_file(&part);
+            let _ = fs::remove_dir(path.parent().unwrap());
+            return Err(e);
+        }
+        cache.prune(cache.max_size, Some(&path))?;
+        Ok(path)
+    }
+
+    /// 待处理的日志文件, 本地来源不调用接口也不按日期筛选
+    pub async fn log_files(
+        &self,
+        source: &LogSource,
+        start_date: &str,
+        end_date: &str,
+        domain: &str,
+    ) -> Result<Vec<LogFile>, anyhow::Error> {
+        match source {
+            LogSource::Api => Ok(self
+                .list_logs(start_date, end_date, domain)
+                .await?
+                .into_iter()
+                .map(LogFile::Remote)
+                .collect()),
+            LogSource::Local(patterns) => Ok(local_files(patterns)?
+                .into_iter()
+                .map(LogFile::Local)
+                .collect()),
+        }
+    }
+
+    /// 按文件流式处理日志，`f`在阻塞线程中处理单个文件的记录，结果按`logs`的顺序输出
+    pub fn log_stream<T, F>(
+        &self,
+        logs: Vec<LogFile>,
+        f: F,
+    ) -> impl Stream<Item = Result<FileResult<T>, anyhow::Error>>
+    where
+        T: Send + 'static,
+        F: Fn(&mut Records) -> T + Send + Sync + 'static,
+    {
+        let f = Arc::new(f);
+        let this = self.clone();
+        stream::iter(logs)
+            .map(move |log| {
+                let this = this.clone();
+                let f = f.clone();
+                async move {
+                    let path = match log {
+                        LogFile::Remote(log) => this.fetch_log(&log).await?,
+                        LogFile::Local(path) => path,
+                    };
+                    tokio::task::spawn_blocking(move || {
+                        let mut records = Records::new(open_log(&path)?);
+                        let value = f(&mut records);
+                        Ok(FileResult {
+                            value,
+                            error_count: records.error_count,
+                            errors: records.errors,
+                        })
+                    })
+                    .await?
+                }
+            })
+            .buffered(self.log_concurrency())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+    use crate::{config::Config, log::TempDir, SubFunctionEnum};
+
+    #[tokio::test]
+    async fn local_log_stream_test() {
+        let dir = TempDir::new("log-test");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let line = |i: i32| {
+            format!(
+                r#"1.2.3.4 HIT 0 [16/Jul/2024:00:00:0{} +0800] "GET /{} HTTP/1.1" 200 10 "-" "-""#,
+                i, i
+            )
+        };
+        let mut gz = GzEncoder::new(
+            File::create(dir.join("a.gz")).unwrap(),
+            Compression::default(),
+        );
+        writeln!(gz, "{}\n{}", line(1), line(2)).unwrap();
+        gz.finish().unwrap();
+        fs::write(dir.join("sub/b.log"), format!("{}\nbad\n", line(3))).unwrap();
+        fs::write(dir.join("sub/c.log.part"), line(4)).unwrap();
+
+        let source = LogSource::new(Some(dir.path().display().to_string()), vec![]);
+        let config: Config = toml::from_str(
+            "[cdn]\naccess_key=\"a\"\nsecret_key=\"b\"\ndomain=\"a.com\"\n[monitor]\n[blackip]",
+        )
+        .unwrap();
+        let client = Client::new(&config, SubFunctionEnum::Log);
+        let files = client.log_files(&source, "", "", "a.com").await.unwrap();
+        assert_eq!(files.len(), 2);
+        let results: Vec<FileResult<Vec<String>>> = client
+            .log_stream(files, |records| records.map(|x| x.url).collect())
+            .map(|x| x.unwrap())
+            .collect()
+            .await;
+        assert_eq!(results[0].value, vec!["/1", "/2"]);
+        assert_eq!(results[1].value, vec!["/3"]);
+        assert_eq!(results[1].error_count, 1);
+        assert!(local_files(&[dir.join("*.txt").display().to_string()]).is_err());
+    }
+
+    #[test]
+    fn records_test() {
+        let lines = [
+            r#"1.2.3.4 HIT 0 [16/Jul/2024:00:00:01 +0800] "GET /a HTTP/1.1" 200 10 "-" "-""#,
+            "",
+            "broken line",
+            r#"1.2.3.5 MISS 0 [16/Jul/2024:00:00:02 +0800] "GET /b HTTP/1.1" 404 0 "-" "-""#,
+        ];
+        let mut records = Records::new(Box::new(Cursor::new(lines.join("\n"))));
+        let urls: Vec<String> = records.by_ref().map(|x| x.url).collect();
+        assert_eq!(urls, vec!["/a", "/b"]);
+        assert_eq!(records.error_count, 1);
+        assert!(records.errors[0].contains("broken line"));
+    }
+}
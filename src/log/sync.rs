@@ -0,0 +1,313 @@
+//! 日志镜像
+//!
+//! 按`目录/域名/日期/文件名`保存日志，`manifest.json`记录已校验的文件，
+//! 已存在且md5一致的文件跳过，未下载完的`.part`文件断点续传，md5不一致时重新下载
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use chrono::{Duration, Local, NaiveDate};
+use colored::Colorize;
+use futures::{stream, StreamExt};
+use reqwest::{header::RANGE, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use super::LogData;
+use crate::{
+    utils::{print_err, write_json_atomic},
+    Client,
+};
+
+/// 下载失败或校验不通过时的最大尝试次数
+const MAX_ATTEMPTS: usize = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ManifestEntry {
+    pub domain: String,
+    pub day: String,
+    pub md5: String,
+    pub size: u64,
+    pub mtime: i64,
+    pub synced_at: String,
+}
+
+/// 已同步文件清单, key为相对路径
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join("manifest.json")
+    }
+
+    pub fn load(dir: &Path) -> Result<Self, anyhow::Error> {
+        let path = Self::path(dir);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        serde_json::from_str(&fs::read_to_string(&path)?)
+            .map_err(|e| anyhow!("{}解析失败: {}", path.display(), e))
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), anyhow::Error> {
+        write_json_atomic(&Self::path(dir), self)
+    }
+
+    /// 文件已存在且和清单一致
+    pub fn verified(&self, key: &str, path: &Path, md5: &str) -> bool {
+        match (self.files.get(key), fs::metadata(path)) {
+            (Some(entry), Ok(meta)) => entry.md5 == md5 && entry.size == meta.len(),
+            _ => false,
+        }
+    }
+}
+
+/// 文件的md5
+pub fn file_md5(path: &Path) -> Result<String, anyhow::Error> {
+    let mut file = File::open(path)?;
+    let mut context = md5::Context::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        context.consume(&buf[..n]);
+    }
+    Ok(format!("{:x}", context.finalize()))
+}
+
+/// 单个文件的同步结果
+enum Outcome {
+    Skipped,
+    Downloaded,
+}
+
+/// 续传下载到`part`，返回文件大小
+async fn download_part(url: &str, part: &Path) -> Result<u64, anyhow::Error> {
+    let offset = fs::metadata(part).map(|x| x.len()).unwrap_or(0);
+    let mut request = reqwest::Client::new().get(url);
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={}-", offset));
+    }
+    let mut response = request.send().await?;
+    // 服务端不支持Range或者已经下载完时从头下载
+    let append = match response.status() {
+        StatusCode::PARTIAL_CONTENT => true,
+        StatusCode::RANGE_NOT_SATISFIABLE => {
+            fs::remove_file(part)?;
+            response = reqwest::get(url).await?.error_for_status()?;
+            false
+        }
+        _ => {
+            response = response.error_for_status()?;
+            false
+        }
+    };
+    let mut f = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(part)?;
+    while let Some(chunk) = response.chunk().await? {
+        f.write_all(&chunk)?;
+    }
+    f.flush()?;
+    Ok(fs::metadata(part)?.len())
+}
+
+/// 下载并校验md5，通过后重命名为`dest`
+async fn download_verified(log: &LogData, dest: &Path) -> Result<(), anyhow::Error> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let part = PathBuf::from(format!("{}.part", dest.display()));
+    let mut last_error = anyhow!("下载失败");
+    for _ in 0..MAX_ATTEMPTS {
+        if let Err(e) = download_part(&log.url, &part).await {
+            last_error = e;
+            continue;
+        }
+        let md5 = file_md5(&part)?;
+        if log.md5.is_empty() || md5 == log.md5 {
+            fs::rename(&part, dest)?;
+            return Ok(());
+        }
+        fs::remove_file(&part)?;
+        last_error = anyhow!("md5校验失败, 期望{}, 实际{}", log.md5, md5);
+    }
+    Err(last_error)
+}
+
+impl Client {
+    /// 同步一个域名一天的日志，返回(下载数, 跳过数, 失败列表)
+    async fn log_sync_day(
+        &self,
+        dir: &Path,
+        manifest: &mut Manifest,
+        day: &str,
+        domain: &str,
+    ) -> Result<(usize, usize, Vec<(String, String)>), anyhow::Error> {
+        let response = self
+            .clone()
+            .download(day, None, None, false, false, domain)
+            .await?;
+        response.check()?;
+        let logs = response
+            .data
+            .and_then(|mut x| x.remove(domain))
+            .unwrap_or_default();
+        let mut tasks = vec![];
+        let mut skipped = 0;
+        for log in logs {
+            let key = format!("{}/{}/{}", domain, day, log.file_name());
+            let dest = dir.join(&key);
+            if manifest.verified(&key, &dest, &log.md5) {
+                skipped += 1;
+                continue;
+            }
+            tasks.push((key, dest, log));
+        }
+        let results: Vec<(String, LogData, Result<Outcome, anyhow::Error>)> = stream::iter(tasks)
+            .map(|(key, dest, log)| async move {
+                let result = async {
+                    // 已存在但不在清单中的文件, md5一致则直接记录
+                    if dest.exists() {
+                        let md5 = file_md5(&dest)?;
+                        if log.md5.is_empty() || md5 == log.md5 {
+                            return Ok(Outcome::Skipped);
+                        }
+                        fs::remove_file(&dest)?;
+                    }
+                    download_verified(&log, &dest).await?;
+                    Ok(Outcome::Downloaded)
+                }
+                .await;
+                (key, log, result)
+            })
+            .buffer_unordered(self.log_concurrency())
+            .collect()
+            .await;
+        let (mut downloaded, mut failed) = (0, vec![]);
+        for (key, log, result) in results {
+            match result {
+                Ok(outcome) => {
+                    match outcome {
+                        Outcome::Skipped => skipped += 1,
+                        Outcome::Downloaded => downloaded += 1,
+                    }
+                    manifest.files.insert(
+                        key.clone(),
+                        ManifestEntry {
+                            domain: domain.to_string(),
+                            day: day.to_string(),
+                            md5: log.md5,
+                            size: fs::metadata(dir.join(&key))?.len(),
+                            mtime: log.mtime,
+                            synced_at: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                        },
+                    );
+                }
+                Err(e) => failed.push((key, e.to_string())),
+            }
+        }
+        Ok((downloaded, skipped, failed))
+    }
+
+    /// 增量同步日期区间内各域名的日志到`dir`
+    pub async fn log_sync(
+        &self,
+        start_date: &str,
+        end_date: &str,
+        dir: &str,
+        domains: Vec<String>,
+    ) -> Result<(), anyhow::Error> {
+        let start = NaiveDate::parse_from_str(start_date, "%Y-%m-%d")?;
+        let end = NaiveDate::parse_from_str(end_date, "%Y-%m-%d")?;
+        if start > end {
+            return Err(anyhow!("开始日期不能大于结束日期！"));
+        }
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir)?;
+        let mut manifest = Manifest::load(&dir)?;
+        let mut failed = vec![];
+        for domain in domains.iter() {
+            let mut day = start;
+            while day <= end {
+                let d = day.format("%Y-%m-%d").to_string();
+                day += Duration::days(1);
+                match self.log_sync_day(&dir, &mut manifest, &d, domain).await {
+                    Ok((downloaded, skipped, errors)) => {
+                        println!(
+                            "域名 {} {} 日志同步: 下载{}个, 已存在{}个, 失败{}个",
+                            domain.bold().yellow(),
+                            d.bold(),
+                            downloaded.to_string().green(),
+                            skipped,
+                            errors.len().to_string().red(),
+                        );
+                        failed.extend(errors);
+                    }
+                    Err(e) => failed.push((format!("{}/{}", domain, d), e.to_string())),
+                }
+                // 每天保存一次, 中断后已完成的不用重新校验
+                manifest.save(&dir)?;
+            }
+        }
+        if !failed.is_empty() {
+            for (key, e) in failed.iter() {
+                print_err(format!("{}: {}", key, e).as_str(), false);
+            }
+            return Err(anyhow!("{}个日志同步失败", failed.len()));
+        }
+        println!("{}", "日志同步完成 ✅".green());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::TempDir;
+
+    #[test]
+    fn manifest_test() {
+        let tmp = TempDir::new("log-sync-test");
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("a.com/2024-07-16")).unwrap();
+        let path = dir.join("a.com/2024-07-16/a.gz");
+        fs::write(&path, "hello").unwrap();
+        let md5 = file_md5(&path).unwrap();
+        assert_eq!(md5, "5d41402abc4b2a76b9719d911017c592");
+
+        let mut manifest = Manifest::load(dir).unwrap();
+        let key = "a.com/2024-07-16/a.gz";
+        assert!(!manifest.verified(key, &path, &md5));
+        manifest.files.insert(
+            key.to_string(),
+            ManifestEntry {
+                domain: "a.com".to_string(),
+                day: "2024-07-16".to_string(),
+                md5: md5.clone(),
+                size: 5,
+                mtime: 0,
+                synced_at: "".to_string(),
+            },
+        );
+        manifest.save(dir).unwrap();
+        let manifest = Manifest::load(dir).unwrap();
+        assert!(manifest.verified(key, &path, &md5));
+        // 文件被截断或者七牛的md5变了都需要重新下载
+        assert!(!manifest.verified(key, &path, "x"));
+        fs::write(&path, "hell").unwrap();
+        assert!(!manifest.verified(key, &path, &md5));
+    }
+}
@@ -0,0 +1,226 @@
+//! 日志跟踪
+//!
+//! 定时查询前一天及当天的日志列表，文件名或md5没有输出过的视为新发布的日志，
+//! 下载解析后按文件顺序输出符合条件的记录。每输出完一个文件就写入状态文件，
+//! 重启后不会重复输出
+
+#![allow(clippy::too_many_arguments)]
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{Duration, Local};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    format_record,
+    query::Expr,
+    record::Field,
+    stream::{LogFile, ParseErrors},
+    LogData,
+};
+use crate::{
+    utils::{print_err, write_json_atomic},
+    Client,
+};
+
+/// 状态文件中保留的天数
+const STATE_KEEP_DAYS: i64 = 3;
+
+/// 已输出的日志文件
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TailEntry {
+    pub md5: String,
+    pub emitted_at: i64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TailState {
+    /// 已跟踪过的域名
+    pub domains: BTreeSet<String>,
+    /// key为`域名/文件名`
+    pub files: BTreeMap<String, TailEntry>,
+}
+
+fn key(domain: &str, log: &LogData) -> String {
+    format!("{}/{}", domain, log.file_name())
+}
+
+impl TailState {
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// 清理过期的记录后保存
+    pub fn save(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        let expire = (Local::now() - Duration::days(STATE_KEEP_DAYS)).timestamp();
+        self.files.retain(|_, v| v.emitted_at >= expire);
+        write_json_atomic(path, self)
+    }
+
+    pub fn is_new(&self, domain: &str, log: &LogData) -> bool {
+        self.files
+            .get(&key(domain, log))
+            .is_none_or(|x| x.md5 != log.md5)
+    }
+
+    pub fn mark(&mut self, domain: &str, log: &LogData) {
+        self.files.insert(
+            key(domain, log),
+            TailEntry {
+                md5: log.md5.clone(),
+                emitted_at: Local::now().timestamp(),
+            },
+        );
+    }
+}
+
+impl Client {
+    /// 输出一个域名新发布的日志，返回输出的行数
+    async fn log_tail_domain(
+        &self,
+        expr: Arc<Option<Expr>>,
+        fields: Arc<Option<Vec<Field>>>,
+        state: &mut TailState,
+        state_path: &Path,
+        backfill: bool,
+        domain: &str,
+    ) -> Result<usize, anyhow::Error> {
+        let now = Local::now();
+        let logs = self
+            .list_logs(
+                &(now - Duration::days(1)).format("%Y-%m-%d").to_string(),
+                &now.format("%Y-%m-%d").to_string(),
+                domain,
+            )
+            .await?;
+        let logs: Vec<LogData> = logs
+            .into_iter()
+            .filter(|x| state.is_new(domain, x))
+            .collect();
+        // 首次跟踪时只记录已有的日志，从下一个新文件开始输出
+        if !backfill && !state.domains.contains(domain) {
+            for log in logs.iter() {
+                state.mark(domain, log);
+            }
+            state.domains.insert(domain.to_string());
+            state.save(state_path)?;
+            return Ok(0);
+        }
+        state.domains.insert(domain.to_string());
+        if self.config.debug.unwrap_or(false) {
+            eprintln!("[DEBUG] 域名 {} 新日志{}个", domain, logs.len());
+        }
+        let mut results = self.log_stream(
+            logs.iter().cloned().map(LogFile::Remote).collect(),
+            move |records| {
+                records
+                    .filter(|x| expr.as_ref().as_ref().is_none_or(|e| e.matches(x)))
+                    .map(|x| format_record(&x, fields.as_deref()))
+                    .collect::<Vec<String>>()
+            },
+        );
+        let mut total = 0;
+        let mut errors = ParseErrors::default();
+        let mut logs = logs.iter();
+        while let Some(result) = results.next().await {
+            let result = result?;
+            errors.add(&result);
+            let mut stdout = std::io::stdout().lock();
+            for line in result.value {
+                writeln!(stdout, "{}", line)?;
+                total += 1;
+            }
+            stdout.flush()?;
+            // 结果和logs的顺序一致
+            if let Some(log) = logs.next() {
+                state.mark(domain, log);
+            }
+            state.save(state_path)?;
+        }
+        errors.report(self.config.debug.unwrap_or(false));
+        Ok(total)
+    }
+
+    /// 持续输出各域名新发布的日志中符合条件的记录, 直到Ctrl-C
+    pub async fn log_tail(
+        &self,
+        expr: Option<Expr>,
+        fields: Option<Vec<Field>>,
+        domains: Vec<String>,
+        interval: u64,
+        state_path: PathBuf,
+        backfill: bool,
+        once: bool,
+    ) -> Result<(), anyhow::Error> {
+        let expr = Arc::new(expr);
+        let fields = Arc::new(fields);
+        let mut state = TailState::load(&state_path)?;
+        loop {
+            for domain in domains.iter() {
+                if let Err(e) = self
+                    .log_tail_domain(
+                        expr.clone(),
+                        fields.clone(),
+                        &mut state,
+                        &state_path,
+                        backfill,
+                        domain,
+                    )
+                    .await
+                {
+                    print_err(
+                        format!("域名 {} 日志跟踪失败: {}", domain, e).as_str(),
+                        false,
+                    );
+                }
+            }
+            if once {
+                return Ok(());
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(std::time::Duration::from_secs(interval.max(1))) => {}
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::TempDir;
+
+    #[test]
+    fn tail_state_test() {
+        let dir = TempDir::new("log-tail");
+        let path = dir.join("tail.json");
+        let a = LogData::for_test("v2/a.com_2024-07-16-00_part-00000.gz", "x");
+        let mut state = TailState::load(&path).unwrap();
+        assert!(state.is_new("a.com", &a));
+        state.mark("a.com", &a);
+        state.files.insert(
+            "a.com/old.gz".to_string(),
+            TailEntry {
+                md5: "y".to_string(),
+                emitted_at: 0,
+            },
+        );
+        state.save(&path).unwrap();
+        let state = TailState::load(&path).unwrap();
+        assert!(!state.is_new("a.com", &a));
+        assert!(state.is_new("b.com", &a));
+        // 重新发布的文件md5变化
+        assert!(state.is_new("a.com", &LogData::for_test(&a.name, "z")));
+        assert_eq!(state.files.len(), 1);
+    }
+}
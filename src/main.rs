@@ -7,7 +7,25 @@ use clap_complete::{generate, Shell};
 use qiniu_cdn_manager::{
     analysis::top::FilterType,
     config::Config,
-    utils::{get_domains, print_err, prompt, qy_robot::QyRobot, wait_blink},
+    daemon::{list_jobs, run_daemon},
+    dashboard::run_dashboard,
+    exporter::serve_exporter,
+    log::{
+        cache::LogCache,
+        export::{ExportFormat, Partition},
+        filter_expr,
+        query::Expr,
+        record::Field,
+        sql::SqlFormat,
+        stats::Dimension,
+        stream::LogSource,
+        Hours,
+    },
+    push::push,
+    serve::serve,
+    utils::{
+        chart::ChartKind, data_dir, get_domains, print_err, prompt, qy_robot::QyRobot, wait_blink,
+    },
     Client, SubFunctionEnum, QUERYING,
 };
 
@@ -43,15 +61,27 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
+    /// 日志缓存
+    Cache(CacheArgs),
+
     /// 查询请求次数
     Count(CountArgs),
 
+    /// 常驻进程，按计划执行配置的任务
+    Daemon(DaemonArgs),
+
+    /// 终端仪表盘
+    Dashboard(DashboardArgs),
+
     /// 诊断疑似IP
     Diagnostic(DiagnosticArgs),
 
     /// 查询命中率
     Hitmiss(HitissArgs),
 
+    /// Prometheus指标
+    Exporter(ExporterArgs),
+
     /// 查询域名信息
     Info(InfoArgs),
 
@@ -61,6 +91,9 @@ enum Commands {
     /// 查询IP的URL请求次数
     Ipurl(IPUrlArgs),
 
+    /// 查询请求URL的IP及次数、流量
+    UrlIp(UrlIPArgs),
+
     /// 查询运营商请求次数
     ISPCount(ISPCountArgs),
 
@@ -73,18 +106,51 @@ enum Commands {
     /// 下载请求日志
     LogDownload(LogDownloadArgs),
 
+    /// 导出请求日志为JSONL、CSV或Parquet
+    LogExport(LogExportArgs),
+
     /// 过滤请求日志
     LogFilter(LogFilterArgs),
 
+    /// 统计请求日志的响应时间
+    LogLatency(LogLatencyArgs),
+
+    /// 按UserAgent识别爬虫及采集工具
+    LogBot(LogBotArgs),
+
+    /// 推送请求日志到Elasticsearch/Loki
+    LogShip(LogShipArgs),
+
+    /// 用SQL查询请求日志
+    LogSql(LogSqlArgs),
+
+    /// 统计请求日志
+    LogStats(LogStatsArgs),
+
+    /// 增量同步请求日志到本地目录
+    LogSync(LogSyncArgs),
+
+    /// 持续输出新发布的请求日志
+    LogTail(LogTailArgs),
+
     /// 文件预取
     Prefetch(PrefetchArgs),
 
+    /// 推送时间序列到InfluxDB/Graphite
+    Push(PushArgs),
+
     /// 刷新CDN缓存
     Refresh(RefreshArgs),
 
+    /// REST API服务
+    Serve(ServeArgs),
+
     /// 查询状态码
     Status(StatusArgs),
 
+    /// 同步历史数据到本地
+    Sync(SyncArgs),
+
     /// 查询TOP请求
     Top(TopArgs),
 
@@ -95,9 +161,21 @@ enum Commands {
 #[derive(Args)]
 struct LogDownloadArgs {
     /// 日期, 例如 2016-07-01, 默认当天
-    #[arg(short, long)]
+    #[arg(short, long, conflicts_with_all = ["start_date", "end_date"])]
     day: Option<String>,
 
+    /// 开始日期, 例如：2016-07-01, 默认当天
+    #[arg(short, long)]
+    start_date: Option<String>,
+
+    /// 结束日期, 例如：2016-07-03, 默认当天
+    #[arg(short, long)]
+    end_date: Option<String>,
+
+    /// 按小时筛选, 如02-05、2,20-23, 起始大于结束时跨过0点
+    #[arg(long)]
+    hours: Option<String>,
+
     /// 下载条数, 默认全部
     #[arg(short, long)]
     limit: Option<i32>,
@@ -117,6 +195,22 @@ struct LogDownloadArgs {
     /// 解压缩日志不保留源压缩文件
     #[clap(long, action, conflicts_with = "unzip_keep")]
     unzip_not_keep: bool,
+
+    /// 同时下载的文件数, 会覆盖配置文件的download_concurrency字段, 默认8
+    #[arg(short = 'j', long)]
+    concurrency: Option<usize>,
+
+    /// 包含所有域名
+    #[clap(long, action, conflicts_with = "domains")]
+    all_domain: bool,
+
+    /// 排除域名，多个以英文逗号隔开
+    #[arg(long)]
+    domain_exclude: Option<String>,
+
+    /// 域名，多个以英文逗号隔开
+    #[arg(long, conflicts_with = "all_domain")]
+    domains: Option<String>,
 }
 
 #[derive(Args)]
@@ -174,6 +268,14 @@ struct TrafficArgs {
     /// 域名，多个以英文逗号隔开
     #[arg(long, conflicts_with = "all_domain")]
     domains: Option<String>,
+
+    /// 从本地历史数据查询(需先执行sync)
+    #[clap(long, action)]
+    offline: bool,
+
+    /// 以图表显示, 可选bar(默认, 多个序列堆叠)和line
+    #[arg(long, num_args = 0..=1, default_missing_value = "bar")]
+    chart: Option<String>,
 }
 
 #[derive(Args)]
@@ -260,6 +362,10 @@ struct StatusArgs {
     /// 域名，多个以英文逗号隔开
     #[arg(long, conflicts_with = "all_domain")]
     domains: Option<String>,
+
+    /// 从本地历史数据查询(需先执行sync)
+    #[clap(long, action)]
+    offline: bool,
 }
 
 #[derive(Args)]
@@ -314,6 +420,14 @@ struct CountArgs {
     /// 域名，多个以英文逗号隔开
     #[arg(long, conflicts_with = "all_domain")]
     domains: Option<String>,
+
+    /// 从本地历史数据查询(需先执行sync)
+    #[clap(long, action)]
+    offline: bool,
+
+    /// 以图表显示, 可选bar(默认, 多个序列堆叠)和line
+    #[arg(long, num_args = 0..=1, default_missing_value = "bar")]
+    chart: Option<String>,
 }
 
 #[derive(Args)]
@@ -345,6 +459,30 @@ struct HitissArgs {
     /// 域名，多个以英文逗号隔开
     #[arg(long, conflicts_with = "all_domain")]
     domains: Option<String>,
+
+    /// 不要发送命中率告警
+    #[clap(long, action)]
+    no_warn: bool,
+
+    /// 按域名输出命中率日报，并和近7天基线对比
+    #[clap(long, action)]
+    report: bool,
+
+    /// 日报日期, 默认当天, 如2016-07-01
+    #[arg(long, requires = "report")]
+    day: Option<String>,
+
+    /// 日报不发送企业微信通知
+    #[clap(long, action, requires = "report")]
+    no_qy_notify: bool,
+
+    /// 从本地历史数据查询(需先执行sync)
+    #[clap(long, action)]
+    offline: bool,
+
+    /// 以图表显示, 可选bar(默认, 多个序列堆叠)和line
+    #[arg(long, num_args = 0..=1, default_missing_value = "bar")]
+    chart: Option<String>,
 }
 
 #[derive(Args)]
@@ -392,6 +530,14 @@ struct ISPTrafficArgs {
     /// 按运营商排序，会查询所有运营商并按流量从大到小排序
     #[clap(long, action, conflicts_with_all = vec!["isp", "region_sort"])]
     isp_sort: bool,
+
+    /// 从本地历史数据查询(需先执行sync)
+    #[clap(long, action)]
+    offline: bool,
+
+    /// 以图表显示, 可选bar(默认, 多个序列堆叠)和line
+    #[arg(long, num_args = 0..=1, default_missing_value = "bar")]
+    chart: Option<String>,
 }
 
 #[derive(Args)]
@@ -526,9 +672,151 @@ struct DiagnosticArgs {
     day: Option<String>,
 }
 
+#[derive(Args)]
+struct DashboardArgs {
+    /// 自动刷新间隔(秒), 默认60
+    #[arg(short, long)]
+    interval: Option<u64>,
+
+    /// 包含所有域名
+    #[clap(long, action, conflicts_with = "domains")]
+    all_domain: bool,
+
+    /// 排除域名，多个以英文逗号隔开
+    #[arg(long)]
+    domain_exclude: Option<String>,
+
+    /// 域名，多个以英文逗号隔开
+    #[arg(long, conflicts_with = "all_domain")]
+    domains: Option<String>,
+}
+
+#[derive(Args)]
+struct CacheArgs {
+    /// stats: 查看缓存, prune: 按上限淘汰最久未使用的日志, clear: 清空缓存
+    #[arg(value_parser = ["stats", "prune", "clear"], default_value = "stats")]
+    action: String,
+
+    /// prune时的缓存上限(MB), 会覆盖配置文件的[cache]max_size字段
+    #[arg(long)]
+    max_size: Option<u64>,
+}
+
+#[derive(Args)]
+struct DaemonArgs {
+    /// 列出配置的任务及下次执行时间
+    #[clap(long, short, action)]
+    list: bool,
+}
+
+#[derive(Args)]
+struct ExporterArgs {
+    /// 监听地址, 会覆盖配置文件的[exporter]listen字段, 默认0.0.0.0:9527
+    #[arg(short, long)]
+    listen: Option<String>,
+
+    /// 排除域名，多个以英文逗号隔开
+    #[arg(long)]
+    domain_exclude: Option<String>,
+
+    /// 域名，多个以英文逗号隔开, 默认所有域名
+    #[arg(long)]
+    domains: Option<String>,
+}
+
+#[derive(Args)]
+struct PushArgs {
+    /// 只打印要推送的数据, 不推送也不更新水位线
+    #[clap(long, action)]
+    dry_run: bool,
+
+    /// 排除域名，多个以英文逗号隔开
+    #[arg(long)]
+    domain_exclude: Option<String>,
+
+    /// 域名，多个以英文逗号隔开, 默认[push]domains或所有域名
+    #[arg(long)]
+    domains: Option<String>,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// 监听地址, 会覆盖配置文件的[serve]listen字段, 默认127.0.0.1:9528
+    #[arg(short, long)]
+    listen: Option<String>,
+}
+
+#[derive(Args)]
+struct SyncArgs {
+    /// 开始日期, 例如：2016-07-01, 默认当天
+    #[arg(short, long)]
+    start_date: Option<String>,
+
+    /// 结束日期, 例如：2016-07-03, 默认当天
+    #[arg(short, long)]
+    end_date: Option<String>,
+
+    /// 包含所有域名
+    #[clap(long, action, conflicts_with = "domains")]
+    all_domain: bool,
+
+    /// 排除域名，多个以英文逗号隔开
+    #[arg(long)]
+    domain_exclude: Option<String>,
+
+    /// 域名，多个以英文逗号隔开
+    #[arg(long, conflicts_with = "all_domain")]
+    domains: Option<String>,
+}
+
+#[derive(Args)]
+struct LogSyncArgs {
+    /// 开始日期, 例如：2016-07-01, 默认前一天
+    #[arg(short, long)]
+    start_date: Option<String>,
+
+    /// 结束日期, 例如：2016-07-03, 默认当天
+    #[arg(short, long)]
+    end_date: Option<String>,
+
+    /// 同步目录, 默认当前目录的`./logs`目录, 按`域名/日期/文件名`保存
+    #[arg(long)]
+    dir: Option<String>,
+
+    /// 包含所有域名
+    #[clap(long, action, conflicts_with = "domains")]
+    all_domain: bool,
+
+    /// 排除域名，多个以英文逗号隔开
+    #[arg(long)]
+    domain_exclude: Option<String>,
+
+    /// 域名，多个以英文逗号隔开
+    #[arg(long, conflicts_with = "all_domain")]
+    domains: Option<String>,
+}
+
+/// 本地日志来源, 各日志子命令共用
+#[derive(Args)]
+struct LogSourceArgs {
+    /// 从本地目录读取日志(.gz或文本)，会查找目录下所有文件，不调用接口也不按日期筛选
+    #[arg(long)]
+    from_dir: Option<String>,
+
+    /// 本地日志文件，支持glob，多个以英文逗号隔开，不调用接口也不按日期筛选
+    #[arg(long, value_delimiter = ',')]
+    files: Vec<String>,
+}
+
+impl LogSourceArgs {
+    fn source(&self) -> LogSource {
+        LogSource::new(self.from_dir.clone(), self.files.clone())
+    }
+}
+
 #[derive(Args)]
 struct IPUrlArgs {
-    /// 要查询的IP, 请求日志一般滞后6个小时左右
+    /// 要查询的IP, 多个以英文逗号隔开, 支持网段(如10.0.0.0/8)及IPv6, 请求日志一般滞后6个小时左右
     #[arg(short, long)]
     ip: String,
 
@@ -543,6 +831,39 @@ struct IPUrlArgs {
     /// 输出条数, 默认全部
     #[arg(short, long)]
     limit: Option<i32>,
+
+    #[command(flatten)]
+    source: LogSourceArgs,
+}
+
+#[derive(Args)]
+struct UrlIPArgs {
+    /// 要查询的URL, 以/开头时匹配路径(不含参数), 否则匹配完整URL
+    #[arg(short, long)]
+    url: String,
+
+    /// 按前缀匹配URL
+    #[clap(long, action)]
+    prefix: bool,
+
+    /// 按流量排序, 默认按请求次数
+    #[clap(long, action)]
+    sort_traffic: bool,
+
+    /// 开始日期, 例如：2016-07-01, 默认当天
+    #[arg(short, long)]
+    start_date: Option<String>,
+
+    /// 结束日期, 例如：2016-07-03, 默认当天
+    #[arg(short, long)]
+    end_date: Option<String>,
+
+    /// 输出条数, 默认全部
+    #[arg(short, long)]
+    limit: Option<i32>,
+
+    #[command(flatten)]
+    source: LogSourceArgs,
 }
 
 #[derive(Args)]
@@ -551,6 +872,14 @@ struct LogFilterArgs {
     #[arg(short, long)]
     filter_string: Vec<String>,
 
+    /// 查询条件, 如: status>=500 and ip in 10.0.0.0/8 and ua ~ /bot/i and bytes>1MB and time between 02:00 and 03:00
+    #[arg(short, long)]
+    query: Option<String>,
+
+    /// 输出的字段，多个以英文逗号隔开, 可选ip cache rt time method url path protocol status bytes referer ua, 默认输出整行
+    #[arg(long)]
+    fields: Option<String>,
+
     /// 开始日期, 例如：2016-07-01, 默认当天
     #[arg(short, long)]
     start_date: Option<String>,
@@ -562,13 +891,250 @@ struct LogFilterArgs {
     /// 输出到文件
     #[clap(long, action)]
     output_file: bool,
+
+    #[command(flatten)]
+    source: LogSourceArgs,
+}
+
+#[derive(Args)]
+struct LogExportArgs {
+    /// 导出格式, 可选jsonl csv parquet, 默认jsonl
+    #[arg(long)]
+    format: Option<String>,
+
+    /// 输出文件, 分区时为输出目录, 默认./export(不分区时为./export.<格式>)
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// 分区字段，多个以英文逗号隔开, 可选day domain, 目录为day=2024-07-16/domain=a.com, 多个域名时必须包含domain
+    #[arg(short, long)]
+    partition: Option<String>,
+
+    /// 查询条件, 语法同log-filter的--query
+    #[arg(short, long)]
+    query: Option<String>,
+
+    /// 开始日期, 例如：2016-07-01, 默认当天
+    #[arg(short, long)]
+    start_date: Option<String>,
+
+    /// 结束日期, 例如：2016-07-03, 默认当天
+    #[arg(short, long)]
+    end_date: Option<String>,
+
+    /// 包含所有域名
+    #[clap(long, action, conflicts_with = "domains")]
+    all_domain: bool,
+
+    /// 排除域名，多个以英文逗号隔开
+    #[arg(long)]
+    domain_exclude: Option<String>,
+
+    /// 域名，多个以英文逗号隔开
+    #[arg(long, conflicts_with = "all_domain")]
+    domains: Option<String>,
+
+    #[command(flatten)]
+    source: LogSourceArgs,
+}
+
+#[derive(Args)]
+struct LogLatencyArgs {
+    /// 查询条件, 语法同log-filter的--query
+    #[arg(short, long)]
+    query: Option<String>,
+
+    /// 开始日期, 例如：2016-07-01, 默认当天
+    #[arg(short, long)]
+    start_date: Option<String>,
+
+    /// 结束日期, 例如：2016-07-03, 默认当天
+    #[arg(short, long)]
+    end_date: Option<String>,
+
+    /// URL前缀输出条数, 默认10
+    #[arg(short, long)]
+    limit: Option<i32>,
+
+    /// URL前缀的目录层级, 默认1
+    #[arg(long)]
+    prefix_depth: Option<usize>,
+
+    /// 不发送企业微信通知
+    #[clap(long, action)]
+    no_qy_notify: bool,
+
+    #[command(flatten)]
+    source: LogSourceArgs,
+}
+
+#[derive(Args)]
+struct LogBotArgs {
+    /// 查询条件, 语法同log-filter的--query
+    #[arg(short, long)]
+    query: Option<String>,
+
+    /// 开始日期, 例如：2016-07-01, 默认当天
+    #[arg(short, long)]
+    start_date: Option<String>,
+
+    /// 结束日期, 例如：2016-07-03, 默认当天
+    #[arg(short, long)]
+    end_date: Option<String>,
+
+    /// 每个分类输出的IP条数, 默认10
+    #[arg(short, long)]
+    limit: Option<i32>,
+
+    /// 按流量排序, 默认按请求次数
+    #[clap(long, action)]
+    sort_traffic: bool,
+
+    #[command(flatten)]
+    source: LogSourceArgs,
+}
+
+#[derive(Args)]
+struct LogShipArgs {
+    /// 开始日期, 例如：2016-07-01, 默认前一天
+    #[arg(short, long)]
+    start_date: Option<String>,
+
+    /// 结束日期, 例如：2016-07-03, 默认当天
+    #[arg(short, long)]
+    end_date: Option<String>,
+
+    /// 只解析不推送, 也不更新检查点
+    #[clap(long, action)]
+    dry_run: bool,
+
+    /// 排除域名，多个以英文逗号隔开
+    #[arg(long)]
+    domain_exclude: Option<String>,
+
+    /// 域名，多个以英文逗号隔开, 默认[ship]domains或所有域名
+    #[arg(long)]
+    domains: Option<String>,
+
+    #[command(flatten)]
+    source: LogSourceArgs,
+}
+
+#[derive(Args)]
+struct LogSqlArgs {
+    /// SQL, 表名为logs, 字段有domain ip timestamp ts day hour method url path query status bytes response_time referer user_agent hit
+    sql: String,
+
+    /// 输出格式, 可选table csv jsonl, 默认table
+    #[arg(long)]
+    format: Option<String>,
+
+    /// 开始日期, 例如：2016-07-01, 默认当天
+    #[arg(short, long)]
+    start_date: Option<String>,
+
+    /// 结束日期, 例如：2016-07-03, 默认当天
+    #[arg(short, long)]
+    end_date: Option<String>,
+
+    /// 包含所有域名
+    #[clap(long, action, conflicts_with = "domains")]
+    all_domain: bool,
+
+    /// 排除域名，多个以英文逗号隔开
+    #[arg(long)]
+    domain_exclude: Option<String>,
+
+    /// 域名，多个以英文逗号隔开
+    #[arg(long, conflicts_with = "all_domain")]
+    domains: Option<String>,
+
+    #[command(flatten)]
+    source: LogSourceArgs,
+}
+
+#[derive(Args)]
+struct LogTailArgs {
+    /// 要过滤的字符串，支持传递多次, 以!!开头表示不包含
+    #[arg(short, long)]
+    filter_string: Vec<String>,
+
+    /// 查询条件, 语法同log-filter的--query
+    #[arg(short, long)]
+    query: Option<String>,
+
+    /// 输出的字段，多个以英文逗号隔开, 可选ip cache rt time method url path protocol status bytes referer ua, 默认输出整行
+    #[arg(long)]
+    fields: Option<String>,
+
+    /// 查询日志列表的间隔(秒), 默认300
+    #[arg(short, long)]
+    interval: Option<u64>,
+
+    /// 状态文件, 记录已输出的日志, 默认$XDG_DATA_HOME/qiniu-cdn-manager/log_tail_state.json
+    #[arg(long)]
+    state: Option<String>,
+
+    /// 首次跟踪时输出前一天及当天已有的日志, 默认只输出之后发布的日志
+    #[clap(long, action)]
+    backfill: bool,
+
+    /// 只查询一次
+    #[clap(long, action)]
+    once: bool,
+
+    /// 包含所有域名
+    #[clap(long, action, conflicts_with = "domains")]
+    all_domain: bool,
+
+    /// 排除域名，多个以英文逗号隔开
+    #[arg(long)]
+    domain_exclude: Option<String>,
+
+    /// 域名，多个以英文逗号隔开
+    #[arg(long, conflicts_with = "all_domain")]
+    domains: Option<String>,
+}
+
+#[derive(Args)]
+struct LogStatsArgs {
+    /// 统计维度，多个以英文逗号隔开, 可选ip url prefix referer ua status ext cache province isp, 默认除province isp外的全部, province isp需要配置[ipdb]
+    #[arg(long)]
+    by: Option<String>,
+
+    /// 查询条件, 语法同log-filter的--query
+    #[arg(short, long)]
+    query: Option<String>,
+
+    /// 开始日期, 例如：2016-07-01, 默认当天
+    #[arg(short, long)]
+    start_date: Option<String>,
+
+    /// 结束日期, 例如：2016-07-03, 默认当天
+    #[arg(short, long)]
+    end_date: Option<String>,
+
+    /// 每个维度输出条数, 默认10
+    #[arg(short, long)]
+    limit: Option<i32>,
+
+    /// 按流量排序, 默认按请求次数
+    #[clap(long, action)]
+    sort_traffic: bool,
+
+    /// URL前缀的目录层级, 默认1
+    #[arg(long)]
+    prefix_depth: Option<usize>,
+
+    #[command(flatten)]
+    source: LogSourceArgs,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     let cli = Cli::parse();
-    let config_path = cli.config.map(PathBuf::from);
-    let mut config = Config::parse(config_path);
+    let config_path = Config::resolve_path(cli.config.map(PathBuf::from));
+    let mut config = Config::parse(Some(config_path.clone()));
     if let Some(domain) = cli.domain {
         config.cdn.domain = domain;
     }
@@ -581,21 +1147,157 @@ async fn main() -> Result<(), anyhow::Error> {
         Some(command) => match command {
             // 下载日志
             Commands::LogDownload(args) => {
-                let day = args.day.clone().unwrap_or(today.clone());
+                let start_date = args
+                    .day
+                    .clone()
+                    .or(args.start_date.clone())
+                    .unwrap_or(today.clone());
+                let end_date = args
+                    .day
+                    .clone()
+                    .or(args.end_date.clone())
+                    .unwrap_or(today.clone());
+                let hours = match &args.hours {
+                    Some(hours) => Some(Hours::from_str(hours)?),
+                    None => None,
+                };
                 if args.no_domain_dir {
                     config.download_log_domain_dir = Some(false);
                 }
+                if args.concurrency.is_some() {
+                    config.download_concurrency = args.concurrency;
+                }
+                let domains = if args.all_domain || args.domains.is_some() {
+                    get_domains(&config, &args.domain_exclude, &args.domains).await?
+                } else {
+                    vec![config.cdn.domain.clone()]
+                };
                 let client = Client::new(&config, SubFunctionEnum::Log);
                 let dir = args.dir.clone().unwrap_or("./logs".to_string());
+                let mut failed = vec![];
+                for domain in domains.iter() {
+                    if let Err(e) = client
+                        .download_range(
+                            &start_date,
+                            &end_date,
+                            hours.as_ref(),
+                            &dir,
+                            args.limit,
+                            args.unzip_keep,
+                            args.unzip_not_keep,
+                            domain,
+                        )
+                        .await
+                    {
+                        print_err(format!("域名 {}: {}", domain, e).as_str(), false);
+                        failed.push(domain.clone());
+                    }
+                }
+                if !failed.is_empty() {
+                    return Err(anyhow::anyhow!("域名 {} 日志下载失败", failed.join(",")));
+                }
+            }
+            // 日志缓存
+            Commands::Cache(args) => {
+                let cache = LogCache::new(&config);
+                let (count, size) = match args.action.as_str() {
+                    "prune" => cache.prune(
+                        args.max_size
+                            .map(|x| x * 1024 * 1024)
+                            .unwrap_or(cache.max_size),
+                        None,
+                    )?,
+                    "clear" => cache.clear()?,
+                    _ => cache.stats()?,
+                };
+                let size = format!("{:.2}MB", size as f64 / 1024.0 / 1024.0);
+                match args.action.as_str() {
+                    "prune" | "clear" => println!(
+                        "已删除 {} 个文件, 共 {}",
+                        count.to_string().bold(),
+                        size.bold().green()
+                    ),
+                    _ => println!(
+                        "缓存目录: {}\n文件数: {}\n大小: {} / {:.0}MB",
+                        cache.dir.display().to_string().bold(),
+                        count.to_string().bold(),
+                        size.bold().green(),
+                        cache.max_size as f64 / 1024.0 / 1024.0,
+                    ),
+                }
+            }
+            // 终端仪表盘
+            Commands::Dashboard(args) => {
+                let domains = if args.all_domain || args.domains.is_some() {
+                    get_domains(&config, &args.domain_exclude, &args.domains).await?
+                } else {
+                    vec![config.cdn.domain.clone()]
+                };
+                if domains.is_empty() {
+                    print_err("域名列表为空！", true);
+                }
+                run_dashboard(config, domains, args.interval.unwrap_or(60)).await?;
+            }
+            // 常驻进程
+            Commands::Daemon(args) => {
+                if args.list {
+                    list_jobs(&config)?;
+                } else {
+                    run_daemon(config_path, cli.debug).await?;
+                }
+            }
+            // Prometheus指标
+            Commands::Exporter(args) => {
+                serve_exporter(
+                    config,
+                    args.listen.clone(),
+                    args.domains.clone(),
+                    args.domain_exclude.clone(),
+                )
+                .await?;
+            }
+            // REST API服务
+            Commands::Serve(args) => {
+                serve(config, args.listen.clone()).await?;
+            }
+            // 推送时间序列
+            Commands::Push(args) => {
+                push(
+                    &config,
+                    args.domains.clone(),
+                    args.domain_exclude.clone(),
+                    args.dry_run,
+                )
+                .await?;
+            }
+            // 同步历史数据
+            Commands::Sync(args) => {
+                let start_date = args.start_date.clone().unwrap_or(today.clone());
+                let end_date = args.end_date.clone().unwrap_or(today.clone());
+                let domains = if args.all_domain || args.domains.is_some() {
+                    get_domains(&config, &args.domain_exclude, &args.domains).await?
+                } else {
+                    vec![config.cdn.domain.clone()]
+                };
+                let client = Client::new(&config, SubFunctionEnum::Traffic);
+                client.sync(&start_date, &end_date, domains).await?;
+            }
+            // 同步日志
+            Commands::LogSync(args) => {
+                let yesterday = (Local::now() - chrono::Duration::days(1))
+                    .format("%Y-%m-%d")
+                    .to_string();
+                let start_date = args.start_date.clone().unwrap_or(yesterday);
+                let end_date = args.end_date.clone().unwrap_or(today.clone());
+                let dir = args.dir.clone().unwrap_or("./logs".to_string());
+                let domains = if args.all_domain || args.domains.is_some() {
+                    get_domains(&config, &args.domain_exclude, &args.domains).await?
+                } else {
+                    vec![config.cdn.domain.clone()]
+                };
+                let client = Client::new(&config, SubFunctionEnum::Log);
                 client
-                    .download(
-                        &day,
-                        Some(&dir),
-                        args.limit,
-                        args.unzip_keep,
-                        args.unzip_not_keep,
-                        &config.cdn.domain,
-                    )
+                    .log_sync(&start_date, &end_date, &dir, domains)
                     .await?;
             }
             // 刷新缓存
@@ -611,6 +1313,9 @@ async fn main() -> Result<(), anyhow::Error> {
             }
             // 计费流量查询
             Commands::Traffic(args) => {
+                if args.offline {
+                    config.offline = Some(true);
+                }
                 let blinker = if !config.debug.unwrap_or(false) {
                     Some(wait_blink(QUERYING.into(), 3))
                 } else {
@@ -622,6 +1327,7 @@ async fn main() -> Result<(), anyhow::Error> {
                 if let Some(five_minute_traffic) = args.five_minute_traffic {
                     config.five_minute_traffic = Some(five_minute_traffic);
                 }
+                let chart = args.chart.as_deref().map(ChartKind::from_str).transpose()?;
                 let mut client = Client::new(&config, SubFunctionEnum::Traffic);
                 // 所有域名的流量
                 if args.all_domain || args.domains.is_some() {
@@ -630,12 +1336,33 @@ async fn main() -> Result<(), anyhow::Error> {
                         blinker.handle.await?;
                     }
                     let domains = get_domains(&config, &args.domain_exclude, &args.domains).await?;
+                    if let Some(kind) = chart {
+                        // 图表模式每个域名单独画一张图
+                        for d in domains {
+                            let response = client
+                                .charge_traffic(&start_date, &end_date, &granularity, &d)
+                                .await?;
+                            client.chart_traffic(&response, kind, &start_date, &end_date, &d);
+                            client
+                                .print_traffic_result(
+                                    &response,
+                                    true,
+                                    &start_date,
+                                    &end_date,
+                                    &granularity,
+                                    args.no_warn || args.offline,
+                                    &d,
+                                )
+                                .await?;
+                        }
+                        return Ok(());
+                    }
                     client
                         .all_domain_charge_traffic(
                             &start_date,
                             &end_date,
                             &granularity,
-                            args.no_warn,
+                            args.no_warn || args.offline,
                             domains,
                         )
                         .await?;
@@ -647,14 +1374,23 @@ async fn main() -> Result<(), anyhow::Error> {
                         blinker.sender.send(true).unwrap();
                         blinker.handle.await?;
                     }
+                    if let Some(kind) = chart {
+                        client.chart_traffic(
+                            &response,
+                            kind,
+                            &start_date,
+                            &end_date,
+                            &config.cdn.domain,
+                        );
+                    }
                     client
                         .print_traffic_result(
                             &response,
-                            args.no_print,
+                            args.no_print || chart.is_some(),
                             &start_date,
                             &end_date,
                             &granularity,
-                            args.no_warn,
+                            args.no_warn || args.offline,
                             &config.cdn.domain,
                         )
                         .await?;
@@ -772,6 +1508,9 @@ async fn main() -> Result<(), anyhow::Error> {
             }
             // 状态码查询
             Commands::Status(args) => {
+                if args.offline {
+                    config.offline = Some(true);
+                }
                 let blinker = if !config.debug.unwrap_or(false) {
                     Some(wait_blink(QUERYING.into(), 3))
                 } else {
@@ -814,6 +1553,9 @@ async fn main() -> Result<(), anyhow::Error> {
             }
             // 请求次数查询
             Commands::Count(args) => {
+                if args.offline {
+                    config.offline = Some(true);
+                }
                 let blinker = if !config.debug.unwrap_or(false) {
                     Some(wait_blink(QUERYING.into(), 3))
                 } else {
@@ -826,8 +1568,53 @@ async fn main() -> Result<(), anyhow::Error> {
                 if let Some(five_minute_count) = args.five_minute_count {
                     config.five_minute_count = Some(five_minute_count);
                 }
+                let chart = args.chart.as_deref().map(ChartKind::from_str).transpose()?;
                 let mut client = Client::new(&config, SubFunctionEnum::AnalysisCount);
-                if args.all_domain || args.domains.is_some() {
+                if let Some(kind) = chart {
+                    let domains = if args.all_domain || args.domains.is_some() {
+                        get_domains(&config, &args.domain_exclude, &args.domains).await?
+                    } else {
+                        vec![config.cdn.domain.clone()]
+                    };
+                    if let Some(blinker) = blinker {
+                        blinker.sender.send(true).unwrap();
+                        blinker.handle.await?;
+                    }
+                    // 图表模式每个域名单独画一张图, 并按域名告警
+                    let multi = domains.len() > 1;
+                    let mut total = 0;
+                    for d in domains {
+                        let response = client
+                            .req_count(
+                                freq.clone().into(),
+                                &region,
+                                &start_date,
+                                &end_date,
+                                vec![d.clone()],
+                            )
+                            .await?;
+                        match client
+                            .print_count(
+                                response.clone(),
+                                Some(0),
+                                &region,
+                                &start_date,
+                                &end_date,
+                                freq.clone().into(),
+                                args.no_warn || args.offline,
+                                vec![d],
+                            )
+                            .await
+                        {
+                            Ok(t) => total += t,
+                            Err(e) => print_err(e.to_string().as_str(), false),
+                        }
+                        client.chart_count(&response, kind);
+                    }
+                    if multi {
+                        println!("\n{}{}", "所有域名请求次数总计: ".red().bold(), total);
+                    }
+                } else if args.all_domain || args.domains.is_some() {
                     if let Some(blinker) = blinker {
                         blinker.sender.send(true).unwrap();
                         blinker.handle.await?;
@@ -839,7 +1626,7 @@ async fn main() -> Result<(), anyhow::Error> {
                             &region,
                             &start_date,
                             &end_date,
-                            args.no_warn,
+                            args.no_warn || args.offline,
                             domains.clone(),
                         )
                         .await?;
@@ -866,7 +1653,7 @@ async fn main() -> Result<(), anyhow::Error> {
                             &start_date,
                             &end_date,
                             freq.into(),
-                            args.no_warn,
+                            args.no_warn || args.offline,
                             domains,
                         )
                         .await?;
@@ -874,6 +1661,9 @@ async fn main() -> Result<(), anyhow::Error> {
             }
             // 命中率查询
             Commands::Hitmiss(args) => {
+                if args.offline {
+                    config.offline = Some(true);
+                }
                 let blinker = if !config.debug.unwrap_or(false) {
                     Some(wait_blink(QUERYING.into(), 3))
                 } else {
@@ -887,18 +1677,57 @@ async fn main() -> Result<(), anyhow::Error> {
                 } else {
                     vec![config.cdn.domain.clone()]
                 };
+                let chart = args.chart.as_deref().map(ChartKind::from_str).transpose()?;
                 let client = Client::new(&config, SubFunctionEnum::AnalysisHitmiss);
-                let response = client
-                    .hit_miss(freq.into(), &start_date, &end_date, domains.clone())
-                    .await?;
-                if let Some(blinker) = blinker {
-                    blinker.sender.send(true).unwrap();
-                    blinker.handle.await?;
+                if args.report {
+                    if let Some(blinker) = blinker {
+                        blinker.sender.send(true).unwrap();
+                        blinker.handle.await?;
+                    }
+                    let day = args.day.clone().unwrap_or(today.clone());
+                    client
+                        .hitmiss_report(&day, domains, args.no_qy_notify)
+                        .await?;
+                } else {
+                    let response = client
+                        .hit_miss(freq.clone().into(), &start_date, &end_date, domains.clone())
+                        .await?;
+                    if let Some(blinker) = blinker {
+                        blinker.sender.send(true).unwrap();
+                        blinker.handle.await?;
+                    }
+                    if let Some(kind) = chart {
+                        client.chart_hitmiss(
+                            response,
+                            kind,
+                            &start_date,
+                            &end_date,
+                            domains.clone(),
+                        );
+                    } else {
+                        client.print_hitmiss(
+                            response,
+                            args.limit,
+                            &start_date,
+                            &end_date,
+                            domains.clone(),
+                        );
+                    }
+                    // 5分钟粒度检查命中率告警
+                    if freq == "5min" && !args.no_warn && !args.offline {
+                        for d in domains {
+                            if let Err(e) = client.check_hitmiss(&start_date, &end_date, &d).await {
+                                print_err(e.to_string().as_str(), false);
+                            }
+                        }
+                    }
                 }
-                client.print_hitmiss(response, args.limit, &start_date, &end_date, domains);
             }
             // 运营商流量查询
             Commands::ISPTraffic(args) => {
+                if args.offline {
+                    config.offline = Some(true);
+                }
                 let blinker = if !config.debug.unwrap_or(false) {
                     Some(wait_blink(QUERYING.into(), 3))
                 } else {
@@ -914,6 +1743,7 @@ async fn main() -> Result<(), anyhow::Error> {
                 } else {
                     vec![config.cdn.domain.clone()]
                 };
+                let chart = args.chart.as_deref().map(ChartKind::from_str).transpose()?;
                 let client = Client::new(&config, SubFunctionEnum::AnalysisIsp);
                 if args.region_sort || args.isp_sort {
                     client
@@ -944,15 +1774,27 @@ async fn main() -> Result<(), anyhow::Error> {
                         blinker.sender.send(true).unwrap();
                         blinker.handle.await?;
                     }
-                    client.print_isp_traffic(
-                        response,
-                        args.limit,
-                        &isp,
-                        &regions,
-                        &start_date,
-                        &end_date,
-                        domains,
-                    );
+                    if let Some(kind) = chart {
+                        client.chart_isp_traffic(
+                            response,
+                            kind,
+                            &isp,
+                            &regions,
+                            &start_date,
+                            &end_date,
+                            domains,
+                        );
+                    } else {
+                        client.print_isp_traffic(
+                            response,
+                            args.limit,
+                            &isp,
+                            &regions,
+                            &start_date,
+                            &end_date,
+                            domains,
+                        );
+                    }
                 }
             }
             // ISP请求次数
@@ -1164,6 +2006,31 @@ async fn main() -> Result<(), anyhow::Error> {
                         &end_date,
                         args.limit,
                         blinker,
+                        &args.source.source(),
+                        &config.cdn.domain,
+                    )
+                    .await?;
+            }
+            // 请求URL的IP
+            Commands::UrlIp(args) => {
+                let blinker = if !config.debug.unwrap_or(false) {
+                    Some(wait_blink(QUERYING.into(), 3))
+                } else {
+                    None
+                };
+                let client = Client::new(&config, SubFunctionEnum::Log);
+                let start_date = args.start_date.clone().unwrap_or(today.clone());
+                let end_date = args.end_date.clone().unwrap_or(today.clone());
+                client
+                    .url_ip(
+                        &args.url,
+                        args.prefix,
+                        args.sort_traffic,
+                        &start_date,
+                        &end_date,
+                        args.limit,
+                        blinker,
+                        &args.source.source(),
                         &config.cdn.domain,
                     )
                     .await?;
@@ -1185,9 +2052,11 @@ async fn main() -> Result<(), anyhow::Error> {
             }
             // 日志过滤
             Commands::LogFilter(args) => {
-                if args.filter_string.is_empty() {
-                    print_err("请输入待过滤的字符串！", true);
+                if args.filter_string.is_empty() && args.query.is_none() {
+                    print_err("请输入待过滤的字符串或查询条件！", true);
                 }
+                let query = args.query.as_deref().map(Expr::from_str).transpose()?;
+                let fields = args.fields.as_deref().map(Field::parse_list).transpose()?;
                 let blinker = if !config.debug.unwrap_or(false) {
                     Some(wait_blink(QUERYING.into(), 3))
                 } else {
@@ -1199,10 +2068,226 @@ async fn main() -> Result<(), anyhow::Error> {
                 client
                     .filter_log(
                         args.filter_string.clone(),
+                        query,
+                        fields,
                         &start_date,
                         &end_date,
                         args.output_file,
                         blinker,
+                        &args.source.source(),
+                        &config.cdn.domain,
+                    )
+                    .await?;
+            }
+            // 导出日志
+            Commands::LogExport(args) => {
+                let query = args.query.as_deref().map(Expr::from_str).transpose()?;
+                let format = match args.format.as_deref() {
+                    Some(format) => ExportFormat::from_str(format)?,
+                    None => ExportFormat::Jsonl,
+                };
+                let partitions = match args.partition.as_deref() {
+                    Some(partition) => Partition::parse_list(partition)?,
+                    None => vec![],
+                };
+                let output = args.output.clone().unwrap_or(if partitions.is_empty() {
+                    format!("./export.{}", format)
+                } else {
+                    "./export".to_string()
+                });
+                let source = args.source.source();
+                // 本地日志不区分域名
+                let domains = if matches!(source, LogSource::Api)
+                    && (args.all_domain || args.domains.is_some())
+                {
+                    get_domains(&config, &args.domain_exclude, &args.domains).await?
+                } else {
+                    vec![config.cdn.domain.clone()]
+                };
+                let start_date = args.start_date.clone().unwrap_or(today.clone());
+                let end_date = args.end_date.clone().unwrap_or(today.clone());
+                let client = Client::new(&config, SubFunctionEnum::Log);
+                client
+                    .log_export(
+                        query,
+                        format,
+                        partitions,
+                        &output,
+                        &start_date,
+                        &end_date,
+                        &source,
+                        domains,
+                    )
+                    .await?;
+            }
+            // 响应时间
+            Commands::LogLatency(args) => {
+                let query = args.query.as_deref().map(Expr::from_str).transpose()?;
+                let blinker = if !config.debug.unwrap_or(false) {
+                    Some(wait_blink(QUERYING.into(), 3))
+                } else {
+                    None
+                };
+                let start_date = args.start_date.clone().unwrap_or(today.clone());
+                let end_date = args.end_date.clone().unwrap_or(today.clone());
+                let client = Client::new(&config, SubFunctionEnum::Log);
+                client
+                    .log_latency(
+                        query,
+                        args.limit,
+                        args.prefix_depth.unwrap_or(1),
+                        &start_date,
+                        &end_date,
+                        args.no_qy_notify,
+                        blinker,
+                        &args.source.source(),
+                        &config.cdn.domain,
+                    )
+                    .await?;
+            }
+            // 爬虫识别
+            Commands::LogBot(args) => {
+                let query = args.query.as_deref().map(Expr::from_str).transpose()?;
+                let blinker = if !config.debug.unwrap_or(false) {
+                    Some(wait_blink(QUERYING.into(), 3))
+                } else {
+                    None
+                };
+                let start_date = args.start_date.clone().unwrap_or(today.clone());
+                let end_date = args.end_date.clone().unwrap_or(today.clone());
+                let client = Client::new(&config, SubFunctionEnum::Log);
+                client
+                    .log_bot(
+                        query,
+                        args.limit,
+                        args.sort_traffic,
+                        &start_date,
+                        &end_date,
+                        blinker,
+                        &args.source.source(),
+                        &config.cdn.domain,
+                    )
+                    .await?;
+            }
+            // 推送日志
+            Commands::LogShip(args) => {
+                let yesterday = (Local::now() - chrono::Duration::days(1))
+                    .format("%Y-%m-%d")
+                    .to_string();
+                let start_date = args.start_date.clone().unwrap_or(yesterday);
+                let end_date = args.end_date.clone().unwrap_or(today.clone());
+                let ship = config.ship.clone();
+                let source = args.source.source();
+                // 本地日志不区分域名
+                let domains = if matches!(source, LogSource::Api) {
+                    get_domains(
+                        &config,
+                        &args
+                            .domain_exclude
+                            .clone()
+                            .or(ship.as_ref().and_then(|x| x.domain_exclude.clone())),
+                        &args
+                            .domains
+                            .clone()
+                            .or(ship.as_ref().and_then(|x| x.domains.clone())),
+                    )
+                    .await?
+                } else {
+                    vec![config.cdn.domain.clone()]
+                };
+                let client = Client::new(&config, SubFunctionEnum::Log);
+                client
+                    .log_ship(&start_date, &end_date, &source, domains, args.dry_run)
+                    .await?;
+            }
+            // SQL查询日志
+            Commands::LogSql(args) => {
+                let format = match args.format.as_deref() {
+                    Some(format) => SqlFormat::from_str(format)?,
+                    None => SqlFormat::Table,
+                };
+                let source = args.source.source();
+                // 本地日志不区分域名
+                let domains = if matches!(source, LogSource::Api)
+                    && (args.all_domain || args.domains.is_some())
+                {
+                    get_domains(&config, &args.domain_exclude, &args.domains).await?
+                } else {
+                    vec![config.cdn.domain.clone()]
+                };
+                let blinker = if !config.debug.unwrap_or(false) && format == SqlFormat::Table {
+                    Some(wait_blink(QUERYING.into(), 3))
+                } else {
+                    None
+                };
+                let start_date = args.start_date.clone().unwrap_or(today.clone());
+                let end_date = args.end_date.clone().unwrap_or(today.clone());
+                let client = Client::new(&config, SubFunctionEnum::Log);
+                client
+                    .log_sql(
+                        &args.sql,
+                        format,
+                        &start_date,
+                        &end_date,
+                        &source,
+                        domains,
+                        blinker,
+                    )
+                    .await?;
+            }
+            // 跟踪日志
+            Commands::LogTail(args) => {
+                let query = args.query.as_deref().map(Expr::from_str).transpose()?;
+                let fields = args.fields.as_deref().map(Field::parse_list).transpose()?;
+                let domains = if args.all_domain || args.domains.is_some() {
+                    get_domains(&config, &args.domain_exclude, &args.domains).await?
+                } else {
+                    vec![config.cdn.domain.clone()]
+                };
+                let state = args
+                    .state
+                    .clone()
+                    .map(PathBuf::from)
+                    .unwrap_or(data_dir().join("log_tail_state.json"));
+                let client = Client::new(&config, SubFunctionEnum::Log);
+                client
+                    .log_tail(
+                        filter_expr(query, &args.filter_string),
+                        fields,
+                        domains,
+                        args.interval.unwrap_or(300),
+                        state,
+                        args.backfill,
+                        args.once,
+                    )
+                    .await?;
+            }
+            // 日志统计
+            Commands::LogStats(args) => {
+                let query = args.query.as_deref().map(Expr::from_str).transpose()?;
+                let dimensions = match args.by.as_deref() {
+                    Some(by) => Dimension::parse_list(by)?,
+                    None => Dimension::ALL.to_vec(),
+                };
+                let blinker = if !config.debug.unwrap_or(false) {
+                    Some(wait_blink(QUERYING.into(), 3))
+                } else {
+                    None
+                };
+                let start_date = args.start_date.clone().unwrap_or(today.clone());
+                let end_date = args.end_date.clone().unwrap_or(today.clone());
+                let client = Client::new(&config, SubFunctionEnum::Log);
+                client
+                    .log_stats(
+                        query,
+                        dimensions,
+                        args.limit,
+                        args.sort_traffic,
+                        args.prefix_depth.unwrap_or(1),
+                        &start_date,
+                        &end_date,
+                        blinker,
+                        &args.source.source(),
                         &config.cdn.domain,
                     )
                     .await?;
@@ -1,13 +1,13 @@
 //! 文件预取
 use std::collections::HashMap;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::Client;
 
 use colored::Colorize;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct PrefetchResponse {
     pub code: i32,
     pub error: String,
@@ -0,0 +1,433 @@
+//! 推送时间序列到InfluxDB/VictoriaMetrics(line protocol)或Graphite
+
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use anyhow::anyhow;
+use chrono::{Duration, FixedOffset, TimeZone};
+use colored::Colorize;
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+use crate::{
+    analysis::Freq,
+    config::Config,
+    utils::{
+        data_dir, get_domains, parse_point_time, print_err, qiniu_now, write_json_atomic,
+        QINIU_OFFSET,
+    },
+    Client, SubFunctionEnum,
+};
+
+/// 默认等待10分钟后再推送，七牛最近的点可能还会更新
+const DEFAULT_SETTLE: i64 = 10;
+
+/// 一个时间点的数据
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub measurement: &'static str,
+    pub tags: Vec<(&'static str, String)>,
+    pub fields: Vec<(&'static str, i64)>,
+    /// 秒级时间戳
+    pub timestamp: i64,
+}
+
+/// 推送目标
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sink {
+    /// HTTP写入地址, 如 http://127.0.0.1:8086/write?db=cdn
+    Influx { url: String, token: Option<String> },
+    /// TCP地址, 如 127.0.0.1:2003
+    Graphite { address: String, prefix: String },
+}
+
+fn escape_tag(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+fn graphite_node(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+impl Point {
+    /// 输出Influx line protocol, 时间精度为纳秒
+    pub fn to_line_protocol(&self) -> String {
+        let mut line = self.measurement.to_string();
+        for (k, v) in self.tags.iter() {
+            line.push_str(&format!(",{}={}", k, escape_tag(v)));
+        }
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(k, v)| format!("{}={}i", k, v))
+            .collect();
+        format!(
+            "{} {} {}",
+            line,
+            fields.join(","),
+            self.timestamp * 1_000_000_000
+        )
+    }
+
+    /// 输出Graphite plaintext, 每个字段一行
+    pub fn to_graphite(&self, prefix: &str) -> Vec<String> {
+        let mut path = vec![prefix.to_string(), self.measurement.to_string()];
+        path.extend(self.tags.iter().map(|(_, v)| graphite_node(v)));
+        self.fields
+            .iter()
+            .map(|(k, v)| format!("{}.{} {} {}", path.join("."), k, v, self.timestamp))
+            .collect()
+    }
+
+    /// 水位线的key
+    fn series(&self) -> String {
+        let domain = self
+            .tags
+            .iter()
+            .find(|(k, _)| *k == "domain")
+            .map(|(_, v)| v.as_str())
+            .unwrap_or("");
+        format!("{}/{}", domain, self.measurement)
+    }
+}
+
+/// 七牛时间点转成时间戳
+pub fn point_timestamp(point: &str) -> Option<i64> {
+    let naive = parse_point_time(point)?;
+    FixedOffset::east_opt(QINIU_OFFSET)?
+        .from_local_datetime(&naive)
+        .single()
+        .map(|t| t.timestamp())
+}
+
+/// 已推送的最后时间点，按`域名/指标`记录
+#[derive(Debug, Default)]
+pub struct Watermark {
+    path: PathBuf,
+    marks: HashMap<String, i64>,
+}
+
+impl Watermark {
+    /// 文件不存在时为空, 文件损坏时返回错误, 避免重复推送全部的点
+    pub fn load(path: PathBuf) -> Result<Self, anyhow::Error> {
+        let marks = match fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s)
+                .map_err(|e| anyhow!("水位文件{}损坏: {}", path.display(), e))?,
+            Err(_) => HashMap::new(),
+        };
+        Ok(Self { path, marks })
+    }
+
+    /// 过滤掉已推送及还未稳定的点
+    pub fn pending(&self, points: Vec<Point>, until: i64) -> Vec<Point> {
+        points
+            .into_iter()
+            .filter(|p| p.timestamp <= until)
+            .filter(|p| self.marks.get(&p.series()).is_none_or(|t| p.timestamp > *t))
+            .collect()
+    }
+
+    pub fn advance(&mut self, points: &[Point]) {
+        for p in points {
+            let mark = self.marks.entry(p.series()).or_insert(p.timestamp);
+            if *mark < p.timestamp {
+                *mark = p.timestamp;
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        write_json_atomic(&self.path, &self.marks)
+    }
+}
+
+impl Sink {
+    pub fn from_config(config: &Config) -> Result<Self, anyhow::Error> {
+        let push = config.push.clone().ok_or(anyhow!("未配置[push]"))?;
+        match push.kind.as_deref().unwrap_or("influx") {
+            "influx" => Ok(Sink::Influx {
+                url: push.url.ok_or(anyhow!("[push]未配置url"))?,
+                token: push.token,
+            }),
+            "graphite" => Ok(Sink::Graphite {
+                address: push.address.ok_or(anyhow!("[push]未配置address"))?,
+                prefix: push.prefix.unwrap_or("qiniu.cdn".to_string()),
+            }),
+            other => Err(anyhow!("不支持的推送类型: {}", other)),
+        }
+    }
+
+    pub fn render(&self, points: &[Point]) -> String {
+        let lines: Vec<String> = match self {
+            Sink::Influx { .. } => points.iter().map(|p| p.to_line_protocol()).collect(),
+            Sink::Graphite { prefix, .. } => {
+                points.iter().flat_map(|p| p.to_graphite(prefix)).collect()
+            }
+        };
+        let mut body = lines.join("\n");
+        body.push('\n');
+        body
+    }
+
+    pub async fn write(&self, points: &[Point]) -> Result<(), anyhow::Error> {
+        let body = self.render(points);
+        match self {
+            Sink::Influx { url, token } => {
+                let mut request = reqwest::Client::new().post(url).body(body);
+                if let Some(token) = token {
+                    request = request.header("Authorization", format!("Token {}", token));
+                }
+                let response = request.send().await?;
+                if !response.status().is_success() {
+                    return Err(anyhow!(
+                        "写入失败: {} {}",
+                        response.status(),
+                        response.text().await.unwrap_or_default()
+                    ));
+                }
+            }
+            Sink::Graphite { address, .. } => {
+                let mut stream = TcpStream::connect(address).await?;
+                stream.write_all(body.as_bytes()).await?;
+                stream.shutdown().await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 拉取单个域名某一天的5分钟数据
+async fn fetch_domain(
+    config: &Config,
+    day: &str,
+    domain: &str,
+) -> Result<Vec<Point>, anyhow::Error> {
+    let domains = vec![domain.to_string()];
+    let domain_tag = || vec![("domain", domain.to_string())];
+    let mut points = vec![];
+
+    // 计费流量
+    let response = Client::new(config, SubFunctionEnum::Traffic)
+        .charge_traffic(day, day, "5min", domain)
+        .await?;
+    let time = response.time.unwrap_or_default();
+    if let Some(data) = response.data.and_then(|x| x.get(domain).cloned()) {
+        let china = data.china.unwrap_or_default();
+        let oversea = data.oversea.unwrap_or_default();
+        for (i, p) in time.iter().enumerate() {
+            if let Some(timestamp) = point_timestamp(p) {
+                points.push(Point {
+                    measurement: "qiniu_cdn_traffic",
+                    tags: domain_tag(),
+                    fields: vec![
+                        ("china", *china.get(i).unwrap_or(&0)),
+                        ("oversea", *oversea.get(i).unwrap_or(&0)),
+                    ],
+                    timestamp,
+                });
+            }
+        }
+    }
+
+    // 请求次数
+    let response = Client::new(config, SubFunctionEnum::AnalysisCount)
+        .req_count(Freq::FiveMin, "global", day, day, domains.clone())
+        .await?;
+    if let Some(data) = response.data {
+        for (i, p) in data.points.iter().enumerate() {
+            if let Some(timestamp) = point_timestamp(p) {
+                points.push(Point {
+                    measurement: "qiniu_cdn_requests",
+                    tags: domain_tag(),
+                    fields: vec![("count", *data.req_count.get(i).unwrap_or(&0))],
+                    timestamp,
+                });
+            }
+        }
+    }
+
+    // 状态码
+    let response = Client::new(config, SubFunctionEnum::AnalysisStatus)
+        .status_code(Freq::FiveMin, "global", "all", day, day, domains.clone())
+        .await?;
+    if let Some(data) = response.data {
+        for (i, p) in data.points.iter().enumerate() {
+            if let Some(timestamp) = point_timestamp(p) {
+                let mut codes: Vec<(&String, &Vec<i64>)> = data.codes.iter().collect();
+                codes.sort_by_key(|x| x.0);
+                for (code, values) in codes {
+                    let mut tags = domain_tag();
+                    tags.push(("code", code.clone()));
+                    points.push(Point {
+                        measurement: "qiniu_cdn_status",
+                        tags,
+                        fields: vec![("count", *values.get(i).unwrap_or(&0))],
+                        timestamp,
+                    });
+                }
+            }
+        }
+    }
+
+    // 命中率
+    let response = Client::new(config, SubFunctionEnum::AnalysisHitmiss)
+        .hit_miss(Freq::FiveMin, day, day, domains)
+        .await?;
+    if let Some(data) = response.data {
+        for (i, p) in data.points.iter().enumerate() {
+            if let Some(timestamp) = point_timestamp(p) {
+                let r = data.ratio_at(i);
+                points.push(Point {
+                    measurement: "qiniu_cdn_hitmiss",
+                    tags: domain_tag(),
+                    fields: vec![
+                        ("hit", r.hit),
+                        ("miss", r.miss),
+                        ("traffic_hit", r.traffic_hit),
+                        ("traffic_miss", r.traffic_miss),
+                    ],
+                    timestamp,
+                });
+            }
+        }
+    }
+    Ok(points)
+}
+
+/// 推送前一天和当天未推送过的点
+pub async fn push(
+    config: &Config,
+    domains: Option<String>,
+    domain_exclude: Option<String>,
+    dry_run: bool,
+) -> Result<(), anyhow::Error> {
+    let sink = Sink::from_config(config)?;
+    let push_conf = config.push.clone().unwrap();
+    let domains = get_domains(
+        config,
+        &domain_exclude.or(push_conf.domain_exclude),
+        &domains.or(push_conf.domains),
+    )
+    .await?;
+    let settle = push_conf.settle.unwrap_or(DEFAULT_SETTLE);
+    let until = (qiniu_now() - Duration::minutes(settle)).timestamp();
+    let mut watermark = Watermark::load(data_dir().join("push_watermark.json"))?;
+    let now = qiniu_now();
+    let days = [
+        (now - Duration::days(1)).format("%Y-%m-%d").to_string(),
+        now.format("%Y-%m-%d").to_string(),
+    ];
+    let mut failed = 0;
+    for domain in domains.iter() {
+        let mut points = vec![];
+        for day in days.iter() {
+            match fetch_domain(config, day, domain).await {
+                Ok(k) => points.extend(k),
+                Err(e) => {
+                    print_err(
+                        format!("{} {} 数据拉取失败: {}", domain, day, e).as_str(),
+                        false,
+                    );
+                    failed += 1;
+                    // 数据不完整时不推送，避免水位线越过缺失的点
+                    points.clear();
+                    break;
+                }
+            }
+        }
+        let points = watermark.pending(points, until);
+        if points.is_empty() {
+            continue;
+        }
+        if dry_run {
+            print!("{}", sink.render(&points));
+            continue;
+        }
+        match sink.write(&points).await {
+            Ok(_) => {
+                watermark.advance(&points);
+                println!(
+                    "域名 {} 推送完成 ✅ {}个点",
+                    domain.bold().yellow(),
+                    points.len()
+                );
+            }
+            Err(e) => {
+                print_err(format!("{} 推送失败: {}", domain, e).as_str(), false);
+                failed += 1;
+            }
+        }
+    }
+    if !dry_run {
+        watermark.save()?;
+    }
+    if failed > 0 {
+        return Err(anyhow!("{}次推送或拉取失败", failed));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(timestamp: i64) -> Point {
+        Point {
+            measurement: "qiniu_cdn_status",
+            tags: vec![
+                ("domain", "a.com".to_string()),
+                ("code", "2 00".to_string()),
+            ],
+            fields: vec![("count", 12)],
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn push_format_test() {
+        let ts = point_timestamp("2024-07-16-10-05-00").unwrap();
+        assert_eq!(ts, point_timestamp("2024-07-16 10:05:00").unwrap());
+        assert_eq!(ts, 1721095500);
+        assert_eq!(
+            point(ts).to_line_protocol(),
+            "qiniu_cdn_status,domain=a.com,code=2\\ 00 count=12i 1721095500000000000"
+        );
+        assert_eq!(
+            point(ts).to_graphite("qiniu.cdn"),
+            vec!["qiniu.cdn.qiniu_cdn_status.a_com.2_00.count 12 1721095500"]
+        );
+    }
+
+    #[test]
+    fn push_watermark_test() {
+        let mut watermark = Watermark::default();
+        let points = vec![point(100), point(200), point(300)];
+        let pending = watermark.pending(points.clone(), 250);
+        assert_eq!(pending.len(), 2);
+        watermark.advance(&pending);
+        assert_eq!(watermark.pending(points.clone(), 400), vec![point(300)]);
+
+        let dir = crate::log::TempDir::new("watermark-test");
+        watermark.path = dir.join("push_watermark.json");
+        watermark.save().unwrap();
+        let loaded = Watermark::load(watermark.path.clone()).unwrap();
+        assert_eq!(loaded.pending(points, 400), vec![point(300)]);
+        fs::write(&watermark.path, "{").unwrap();
+        assert!(Watermark::load(watermark.path.clone()).is_err());
+        assert!(Watermark::load(dir.join("none.json"))
+            .unwrap()
+            .marks
+            .is_empty());
+    }
+}
@@ -3,12 +3,12 @@
 use std::collections::HashMap;
 
 use anyhow::Ok;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::Client;
 use colored::Colorize;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct RefreshResponse {
     pub code: i32,
     pub error: String,
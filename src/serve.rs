@@ -0,0 +1,598 @@
+//! REST API服务，内部工具无需持有AK/SK即可查询数据和刷新缓存
+
+use std::{fs::OpenOptions, future::Future, io::Write, path::PathBuf, sync::Arc};
+
+use anyhow::anyhow;
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::Local;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, sync::Mutex};
+
+use crate::{
+    analysis::{top::FilterType, Freq},
+    config::{Config, ServeToken},
+    domain::IpAclChange,
+    utils::{data_dir, print_err},
+    Client, SubFunctionEnum,
+};
+
+/// 查询接口
+pub const SCOPE_READ: &str = "read";
+/// 刷新缓存
+pub const SCOPE_REFRESH: &str = "refresh";
+/// 文件预取
+pub const SCOPE_PREFETCH: &str = "prefetch";
+/// 修改IP黑白名单
+pub const SCOPE_IPACL: &str = "ipacl";
+
+struct AppState {
+    config: Config,
+    tokens: Vec<ServeToken>,
+    audit_log: PathBuf,
+    audit_lock: Mutex<()>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct TrafficQuery {
+    start_date: Option<String>,
+    end_date: Option<String>,
+    /// 5min/hour/day, 默认day
+    granularity: Option<String>,
+    domain: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct AnalysisQuery {
+    start_date: Option<String>,
+    end_date: Option<String>,
+    /// 5min/1hour/1day, 默认1day
+    freq: Option<String>,
+    /// 区域, 状态码查询时可用英文逗号隔开多个
+    region: Option<String>,
+    isp: Option<String>,
+    /// 域名，多个以英文逗号隔开
+    domains: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct TopQuery {
+    start_date: Option<String>,
+    end_date: Option<String>,
+    region: Option<String>,
+    /// traffic(默认)/count
+    by: Option<String>,
+    domains: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct RefreshBody {
+    urls: Option<Vec<String>>,
+    dirs: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct PrefetchBody {
+    urls: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct IpAclBody {
+    /// black/white/close
+    #[serde(rename = "type")]
+    acl_type: String,
+    ips: Option<Vec<String>>,
+    /// 覆盖线上配置，默认追加
+    rewrite: Option<bool>,
+}
+
+fn error_response(status: StatusCode, msg: &str) -> Response {
+    (status, Json(json!({ "error": msg }))).into_response()
+}
+
+/// 不因提前返回泄露token的长度以外的信息
+fn constant_eq(a: &str, b: &str) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.bytes()
+        .zip(b.bytes())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+/// 校验token、权限及域名
+pub fn authorize<'a>(
+    tokens: &'a [ServeToken],
+    authorization: Option<&str>,
+    scope: &str,
+    domains: &[String],
+) -> Result<&'a ServeToken, (StatusCode, String)> {
+    let bearer = authorization
+        .and_then(|x| x.strip_prefix("Bearer "))
+        .map(|x| x.trim())
+        .ok_or((StatusCode::UNAUTHORIZED, "缺少Bearer token".to_string()))?;
+    let token = tokens
+        .iter()
+        .find(|x| constant_eq(&x.token, bearer))
+        .ok_or((StatusCode::UNAUTHORIZED, "无效的token".to_string()))?;
+    if !token.scopes.iter().any(|x| x == scope) {
+        return Err((StatusCode::FORBIDDEN, format!("token没有{}权限", scope)));
+    }
+    if let Some(allowed) = token.domains.as_ref() {
+        let allowed: Vec<&str> = allowed.split(',').map(|x| x.trim()).collect();
+        if let Some(d) = domains.iter().find(|d| !allowed.contains(&d.as_str())) {
+            return Err((StatusCode::FORBIDDEN, format!("token无权访问域名{}", d)));
+        }
+    }
+    Ok(token)
+}
+
+/// 刷新/预取的链接所属的域名
+fn url_domains(urls: &[String]) -> Result<Vec<String>, anyhow::Error> {
+    let mut domains = vec![];
+    for url in urls {
+        let host = reqwest::Url::parse(url)?
+            .host_str()
+            .ok_or(anyhow!("链接缺少域名: {}", url))?
+            .to_string();
+        if !domains.contains(&host) {
+            domains.push(host);
+        }
+    }
+    Ok(domains)
+}
+
+/// Freq::from遇到无效值会panic，这里先校验
+fn parse_freq(freq: Option<String>) -> Result<Freq, anyhow::Error> {
+    let freq = freq.unwrap_or("1day".to_string());
+    if !["5min", "1hour", "1day"].contains(&freq.as_str()) {
+        return Err(anyhow!("无效的freq: {}", freq));
+    }
+    Ok(freq.into())
+}
+
+fn split_domains(domains: &Option<String>, default: &str) -> Vec<String> {
+    match domains {
+        Some(k) => k.split(',').map(|x| x.trim().to_string()).collect(),
+        None => vec![default.to_string()],
+    }
+}
+
+impl AppState {
+    /// 追加一行审计日志
+    async fn audit(&self, entry: Value) {
+        let _guard = self.audit_lock.lock().await;
+        let line = format!("{}\n", entry);
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.audit_log)
+            .and_then(|mut f| f.write_all(line.as_bytes()));
+        if let Err(e) = result {
+            print_err(format!("审计日志写入失败: {}", e).as_str(), false);
+        }
+    }
+
+    /// 鉴权后执行请求并记录审计日志
+    async fn handle<T: Serialize, F: Future<Output = Result<T, anyhow::Error>>>(
+        &self,
+        headers: &HeaderMap,
+        scope: &str,
+        action: &str,
+        domains: Vec<String>,
+        params: Value,
+        fut: F,
+    ) -> Response {
+        let authorization = headers.get("Authorization").and_then(|x| x.to_str().ok());
+        let mut entry = json!({
+            "time": Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            "action": action,
+            "domains": domains,
+            "params": params,
+        });
+        let token = match authorize(&self.tokens, authorization, scope, &domains) {
+            Ok(k) => k,
+            Err((status, msg)) => {
+                entry["status"] = json!(status.as_u16());
+                entry["error"] = json!(msg);
+                self.audit(entry).await;
+                return error_response(status, &msg);
+            }
+        };
+        entry["token"] = json!(token.name);
+        let (status, response) = match fut.await {
+            Ok(k) => (StatusCode::OK, Json(json!(k)).into_response()),
+            Err(e) => {
+                entry["error"] = json!(e.to_string());
+                (
+                    StatusCode::BAD_GATEWAY,
+                    error_response(StatusCode::BAD_GATEWAY, &e.to_string()),
+                )
+            }
+        };
+        entry["status"] = json!(status.as_u16());
+        self.audit(entry).await;
+        response
+    }
+
+    fn today(&self) -> String {
+        Local::now().format("%Y-%m-%d").to_string()
+    }
+}
+
+async fn traffic(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(q): Query<TrafficQuery>,
+) -> Response {
+    let today = state.today();
+    let domain = q.domain.clone().unwrap_or(state.config.cdn.domain.clone());
+    let client = Client::new(&state.config, SubFunctionEnum::Traffic);
+    state
+        .handle(
+            &headers,
+            SCOPE_READ,
+            "traffic",
+            vec![domain.clone()],
+            json!(q),
+            async move {
+                client
+                    .charge_traffic(
+                        &q.start_date.unwrap_or(today.clone()),
+                        &q.end_date.unwrap_or(today),
+                        &q.granularity.unwrap_or("day".to_string()),
+                        &domain,
+                    )
+                    .await
+            },
+        )
+        .await
+}
+
+async fn count(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(q): Query<AnalysisQuery>,
+) -> Response {
+    let today = state.today();
+    let domains = split_domains(&q.domains, &state.config.cdn.domain);
+    let client = Client::new(&state.config, SubFunctionEnum::AnalysisCount);
+    state
+        .handle(
+            &headers,
+            SCOPE_READ,
+            "count",
+            domains.clone(),
+            json!(q),
+            async move {
+                client
+                    .req_count(
+                        parse_freq(q.freq)?,
+                        &q.region.unwrap_or("global".to_string()),
+                        &q.start_date.unwrap_or(today.clone()),
+                        &q.end_date.unwrap_or(today),
+                        domains,
+                    )
+                    .await
+            },
+        )
+        .await
+}
+
+async fn status(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(q): Query<AnalysisQuery>,
+) -> Response {
+    let today = state.today();
+    let domains = split_domains(&q.domains, &state.config.cdn.domain);
+    let client = Client::new(&state.config, SubFunctionEnum::AnalysisStatus);
+    state
+        .handle(
+            &headers,
+            SCOPE_READ,
+            "status",
+            domains.clone(),
+            json!(q),
+            async move {
+                client
+                    .status_code(
+                        parse_freq(q.freq)?,
+                        &q.region.unwrap_or("global".to_string()),
+                        &q.isp.unwrap_or("all".to_string()),
+                        &q.start_date.unwrap_or(today.clone()),
+                        &q.end_date.unwrap_or(today),
+                        domains,
+                    )
+                    .await
+            },
+        )
+        .await
+}
+
+async fn top(
+    State(state): State<Arc<AppState>>,
+    Path(kind): Path<String>,
+    headers: HeaderMap,
+    Query(q): Query<TopQuery>,
+) -> Response {
+    let today = state.today();
+    let domains = split_domains(&q.domains, &state.config.cdn.domain);
+    let client = Client::new(&state.config, SubFunctionEnum::AnalysisTop);
+    let filter_type = if q.by.as_deref() == Some("count") {
+        FilterType::ReqCount
+    } else {
+        FilterType::Traffic
+    };
+    let params = json!(q);
+    let region = q.region.unwrap_or("global".to_string());
+    let start_date = q.start_date.unwrap_or(today.clone());
+    let end_date = q.end_date.unwrap_or(today);
+    let action = format!("top-{}", kind);
+    match kind.as_str() {
+        "ip" => {
+            state
+                .handle(
+                    &headers,
+                    SCOPE_READ,
+                    &action,
+                    domains.clone(),
+                    params,
+                    async move {
+                        client
+                            .top_ip(&region, &start_date, &end_date, filter_type, domains)
+                            .await
+                    },
+                )
+                .await
+        }
+        "url" => {
+            state
+                .handle(
+                    &headers,
+                    SCOPE_READ,
+                    &action,
+                    domains.clone(),
+                    params,
+                    async move {
+                        client
+                            .top_url(&region, &start_date, &end_date, filter_type, domains)
+                            .await
+                    },
+                )
+                .await
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "仅支持ip和url"),
+    }
+}
+
+async fn domain_info(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let client = Client::new(&state.config, SubFunctionEnum::Domain);
+    state
+        .handle(
+            &headers,
+            SCOPE_READ,
+            "domain-info",
+            vec![domain.clone()],
+            json!({}),
+            async move { client.domain_info(&domain).await },
+        )
+        .await
+}
+
+async fn refresh(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(body): Json<RefreshBody>,
+) -> Response {
+    let urls = body.urls.clone().unwrap_or_default();
+    let dirs = body.dirs.clone().unwrap_or_default();
+    if urls.is_empty() && dirs.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "待刷新的链接或目录为空！");
+    }
+    let domains = match url_domains(&[urls.clone(), dirs.clone()].concat()) {
+        Ok(k) => k,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+    let client = Client::new(&state.config, SubFunctionEnum::Refresh);
+    state
+        .handle(
+            &headers,
+            SCOPE_REFRESH,
+            "refresh",
+            domains,
+            json!(body),
+            async move { client.refresh(&urls.join(","), &dirs.join(",")).await },
+        )
+        .await
+}
+
+async fn prefetch(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(body): Json<PrefetchBody>,
+) -> Response {
+    if body.urls.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "待预取的链接为空！");
+    }
+    let domains = match url_domains(&body.urls) {
+        Ok(k) => k,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+    let client = Client::new(&state.config, SubFunctionEnum::Prefetch);
+    let urls = body.urls.join(",");
+    state
+        .handle(
+            &headers,
+            SCOPE_PREFETCH,
+            "prefetch",
+            domains,
+            json!(body),
+            async move { client.prefetch(&urls).await },
+        )
+        .await
+}
+
+async fn ipacl(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+    headers: HeaderMap,
+    Json(body): Json<IpAclBody>,
+) -> Response {
+    // 先校验参数, 参数错误返回400
+    let (black, white, close) = match body.acl_type.as_str() {
+        "black" => (true, false, false),
+        "white" => (false, true, false),
+        "close" => (false, false, true),
+        _ => return error_response(StatusCode::BAD_REQUEST, "type仅支持black/white/close"),
+    };
+    let ips = body.ips.clone().unwrap_or_default();
+    if !close && ips.iter().all(|x| x.trim().is_empty()) {
+        return error_response(StatusCode::BAD_REQUEST, "ips参数错误");
+    }
+    let rewrite = body.rewrite.unwrap_or(false);
+    let client = Client::new(&state.config, SubFunctionEnum::Domain);
+    state
+        .handle(
+            &headers,
+            SCOPE_IPACL,
+            "ipacl",
+            vec![domain.clone()],
+            json!(body),
+            async move {
+                let change = client
+                    .update_ip_acl(black, white, close, &ips.join(","), rewrite, &domain)
+                    .await?;
+                Ok(json!({
+                    "count": change.count(),
+                    "unchanged": change == IpAclChange::Unchanged,
+                }))
+            },
+        )
+        .await
+}
+
+/// 启动REST API服务
+pub async fn serve(config: Config, listen: Option<String>) -> Result<(), anyhow::Error> {
+    let serve_config = config.serve.clone().ok_or(anyhow!("未配置[serve]"))?;
+    let tokens = serve_config.tokens.unwrap_or_default();
+    if tokens.is_empty() {
+        return Err(anyhow!("[serve]未配置tokens"));
+    }
+    let listen = listen
+        .or(serve_config.listen)
+        .unwrap_or("127.0.0.1:9528".to_string());
+    let audit_log = match serve_config.audit_log {
+        Some(k) => PathBuf::from(k),
+        None => data_dir().join("audit.jsonl"),
+    };
+    if let Some(parent) = audit_log.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let state = Arc::new(AppState {
+        config,
+        tokens,
+        audit_log: audit_log.clone(),
+        audit_lock: Mutex::new(()),
+    });
+    let app = Router::new()
+        .route("/api/traffic", get(traffic))
+        .route("/api/count", get(count))
+        .route("/api/status", get(status))
+        .route("/api/top/:kind", get(top))
+        .route("/api/domain/:domain", get(domain_info))
+        .route("/api/refresh", post(refresh))
+        .route("/api/prefetch", post(prefetch))
+        .route("/api/ipacl/:domain", post(ipacl))
+        .with_state(state);
+    let listener = TcpListener::bind(&listen).await?;
+    println!(
+        "{}{}",
+        "API地址: ".green().bold(),
+        format!("http://{}/api", listen).bold()
+    );
+    println!("{}{}", "审计日志: ".green().bold(), audit_log.display());
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens() -> Vec<ServeToken> {
+        vec![
+            ServeToken {
+                name: "grafana".to_string(),
+                token: "t1".to_string(),
+                scopes: vec!["read".to_string()],
+                domains: None,
+            },
+            ServeToken {
+                name: "deploy".to_string(),
+                token: "t2".to_string(),
+                scopes: vec!["read".to_string(), "refresh".to_string()],
+                domains: Some("a.com, b.com".to_string()),
+            },
+        ]
+    }
+
+    #[test]
+    fn serve_authorize_test() {
+        let tokens = tokens();
+        let a = vec!["a.com".to_string()];
+        let c = vec!["c.com".to_string()];
+        assert_eq!(
+            authorize(&tokens, None, SCOPE_READ, &a).unwrap_err().0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            authorize(&tokens, Some("Bearer t3"), SCOPE_READ, &a)
+                .unwrap_err()
+                .0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            authorize(&tokens, Some("Bearer t1"), SCOPE_READ, &c)
+                .unwrap()
+                .name,
+            "grafana"
+        );
+        assert_eq!(
+            authorize(&tokens, Some("Bearer t1"), SCOPE_REFRESH, &a)
+                .unwrap_err()
+                .0,
+            StatusCode::FORBIDDEN
+        );
+        assert!(authorize(&tokens, Some("Bearer t2"), SCOPE_REFRESH, &a).is_ok());
+        assert_eq!(
+            authorize(&tokens, Some("Bearer t2"), SCOPE_READ, &c)
+                .unwrap_err()
+                .0,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[test]
+    fn serve_url_domains_test() {
+        let urls = vec![
+            "https://a.com/x.js".to_string(),
+            "http://a.com/dir/".to_string(),
+            "https://b.com:8080/y".to_string(),
+        ];
+        assert_eq!(url_domains(&urls).unwrap(), vec!["a.com", "b.com"]);
+        assert!(url_domains(&["a.com/x".to_string()]).is_err());
+    }
+}
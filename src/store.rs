@@ -0,0 +1,731 @@
+//! 本地历史数据，将七牛接口返回的时间序列保存到SQLite
+
+#![allow(clippy::too_many_arguments)]
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use chrono::{Duration, NaiveDate, NaiveDateTime, Timelike};
+use colored::Colorize;
+use rusqlite::{params, params_from_iter, Connection};
+
+use crate::{
+    analysis::{
+        count::{ReqCountData, ReqCountResponse},
+        hitmiss::{HitMissData, HitMissResponse},
+        isp::{ISPTrafficData, ISPTrafficResponse},
+        status::{StatusData, StatusResponse},
+        Freq,
+    },
+    config::Config,
+    traffic::{ChargeTrafficResponse, DomainTraffic},
+    utils::{data_dir, parse_point_time, print_err, qiniu_now, region_isp::ISP_CODES},
+    Client, SubFunctionEnum,
+};
+
+const TS_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS traffic (
+    domain TEXT NOT NULL,
+    ts TEXT NOT NULL,
+    point TEXT NOT NULL,
+    china INTEGER NOT NULL,
+    oversea INTEGER NOT NULL,
+    PRIMARY KEY (domain, ts)
+);
+CREATE TABLE IF NOT EXISTS req_count (
+    domain TEXT NOT NULL,
+    region TEXT NOT NULL,
+    ts TEXT NOT NULL,
+    point TEXT NOT NULL,
+    count INTEGER NOT NULL,
+    PRIMARY KEY (domain, region, ts)
+);
+CREATE TABLE IF NOT EXISTS status_code (
+    domain TEXT NOT NULL,
+    region TEXT NOT NULL,
+    isp TEXT NOT NULL,
+    ts TEXT NOT NULL,
+    point TEXT NOT NULL,
+    code TEXT NOT NULL,
+    count INTEGER NOT NULL,
+    PRIMARY KEY (domain, region, isp, ts, code)
+);
+CREATE TABLE IF NOT EXISTS hitmiss (
+    domain TEXT NOT NULL,
+    ts TEXT NOT NULL,
+    point TEXT NOT NULL,
+    hit INTEGER NOT NULL,
+    miss INTEGER NOT NULL,
+    traffic_hit INTEGER NOT NULL,
+    traffic_miss INTEGER NOT NULL,
+    PRIMARY KEY (domain, ts)
+);
+CREATE TABLE IF NOT EXISTS isp_traffic (
+    domain TEXT NOT NULL,
+    region TEXT NOT NULL,
+    isp TEXT NOT NULL,
+    ts TEXT NOT NULL,
+    point TEXT NOT NULL,
+    value INTEGER NOT NULL,
+    PRIMARY KEY (domain, region, isp, ts)
+);
+";
+
+/// 本地历史数据库
+pub struct Store {
+    conn: Connection,
+}
+
+/// 计费流量的粒度转换成Freq
+pub fn granularity_freq(granularity: &str) -> Freq {
+    match granularity {
+        "5min" => Freq::FiveMin,
+        "hour" => Freq::OneHour,
+        _ => Freq::OneDay,
+    }
+}
+
+/// 时间点所在的粒度区间
+fn bucket(ts: NaiveDateTime, freq: Freq) -> NaiveDateTime {
+    match freq {
+        Freq::FiveMin => ts,
+        Freq::OneHour => ts.date().and_hms_opt(ts.hour(), 0, 0).unwrap(),
+        Freq::OneDay => ts.date().and_hms_opt(0, 0, 0).unwrap(),
+    }
+}
+
+/// 按原始时间点的格式输出聚合后的时间
+fn format_like(sample: &str, ts: NaiveDateTime) -> String {
+    if sample.contains(' ') {
+        ts.format(TS_FORMAT).to_string()
+    } else {
+        ts.format("%Y-%m-%d-%H-%M-%S").to_string()
+    }
+}
+
+/// 已发布的时间点，未来的时间点七牛会补0，不保存
+fn published_points(points: &[String]) -> Vec<(usize, String)> {
+    let now = qiniu_now().naive_local();
+    points
+        .iter()
+        .enumerate()
+        .filter_map(|(i, p)| {
+            parse_point_time(p)
+                .filter(|t| *t <= now)
+                .map(|t| (i, t.format(TS_FORMAT).to_string()))
+        })
+        .collect()
+}
+
+fn placeholders(n: usize) -> String {
+    vec!["?"; n].join(",")
+}
+
+/// sync只保存了global区域的数据, 其它区域离线查询不到
+fn check_region(regions: &str) -> Result<(), anyhow::Error> {
+    match regions
+        .split(',')
+        .map(|x| x.trim())
+        .find(|x| *x != "global")
+    {
+        Some(region) => Err(anyhow!(
+            "本地历史数据只保存了global区域, 离线模式不支持区域: {}",
+            region
+        )),
+        None => Ok(()),
+    }
+}
+
+/// 接口返回的code不为200时返回错误
+fn check_code(name: &str, code: i32, error: &str) -> Result<(), anyhow::Error> {
+    if code != 200 {
+        return Err(anyhow!(
+            "{}接口响应错误, code: {}, message: {}",
+            name,
+            code,
+            error
+        ));
+    }
+    Ok(())
+}
+
+/// 查询条件: 域名列表及日期区间
+fn range_params(domains: &[String], start_date: &str, end_date: &str) -> Vec<String> {
+    let mut params: Vec<String> = domains.to_vec();
+    params.push(start_date.to_string());
+    params.push(end_date.to_string());
+    params
+}
+
+impl Store {
+    /// 数据库路径，默认`$XDG_DATA_HOME/qiniu-cdn-manager/history.db`
+    pub fn path(config: &Config) -> PathBuf {
+        match config.store.clone().and_then(|x| x.path) {
+            Some(k) => PathBuf::from(k),
+            None => data_dir().join("history.db"),
+        }
+    }
+
+    pub fn open(path: &Path) -> Result<Self, anyhow::Error> {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() && !parent.exists() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    pub fn save_traffic(
+        &mut self,
+        domain: &str,
+        response: &ChargeTrafficResponse,
+    ) -> Result<usize, anyhow::Error> {
+        let points = response.time.clone().unwrap_or_default();
+        let data = match response.data.as_ref().and_then(|x| x.get(domain)) {
+            Some(k) => k.clone(),
+            None => return Ok(0),
+        };
+        let china = data.china.unwrap_or_default();
+        let oversea = data.oversea.unwrap_or_default();
+        let tx = self.conn.transaction()?;
+        let mut n = 0;
+        for (i, ts) in published_points(&points) {
+            n += tx.execute(
+                "INSERT OR REPLACE INTO traffic VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    domain,
+                    ts,
+                    points[i],
+                    china.get(i).unwrap_or(&0),
+                    oversea.get(i).unwrap_or(&0)
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(n)
+    }
+
+    pub fn save_req_count(
+        &mut self,
+        domain: &str,
+        region: &str,
+        response: &ReqCountResponse,
+    ) -> Result<usize, anyhow::Error> {
+        let data = match response.data.as_ref() {
+            Some(k) => k,
+            None => return Ok(0),
+        };
+        let tx = self.conn.transaction()?;
+        let mut n = 0;
+        for (i, ts) in published_points(&data.points) {
+            n += tx.execute(
+                "INSERT OR REPLACE INTO req_count VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    domain,
+                    region,
+                    ts,
+                    data.points[i],
+                    data.req_count.get(i).unwrap_or(&0)
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(n)
+    }
+
+    pub fn save_status(
+        &mut self,
+        domain: &str,
+        region: &str,
+        isp: &str,
+        response: &StatusResponse,
+    ) -> Result<usize, anyhow::Error> {
+        let data = match response.data.as_ref() {
+            Some(k) => k,
+            None => return Ok(0),
+        };
+        let tx = self.conn.transaction()?;
+        let mut n = 0;
+        for (i, ts) in published_points(&data.points) {
+            for (code, values) in data.codes.iter() {
+                n += tx.execute(
+                    "INSERT OR REPLACE INTO status_code VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        domain,
+                        region,
+                        isp,
+                        ts,
+                        data.points[i],
+                        code,
+                        values.get(i).unwrap_or(&0)
+                    ],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(n)
+    }
+
+    pub fn save_hitmiss(
+        &mut self,
+        domain: &str,
+        response: &HitMissResponse,
+    ) -> Result<usize, anyhow::Error> {
+        let data = match response.data.as_ref() {
+            Some(k) => k,
+            None => return Ok(0),
+        };
+        let tx = self.conn.transaction()?;
+        let mut n = 0;
+        for (i, ts) in published_points(&data.points) {
+            let r = data.ratio_at(i);
+            n += tx.execute(
+                "INSERT OR REPLACE INTO hitmiss VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    domain,
+                    ts,
+                    data.points[i],
+                    r.hit,
+                    r.miss,
+                    r.traffic_hit,
+                    r.traffic_miss
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(n)
+    }
+
+    pub fn save_isp_traffic(
+        &mut self,
+        domain: &str,
+        region: &str,
+        isp: &str,
+        response: &ISPTrafficResponse,
+    ) -> Result<usize, anyhow::Error> {
+        let (points, values) = match response.data.as_ref() {
+            Some(k) => (
+                k.points.clone().unwrap_or_default(),
+                k.value.clone().unwrap_or_default(),
+            ),
+            None => return Ok(0),
+        };
+        let tx = self.conn.transaction()?;
+        let mut n = 0;
+        for (i, ts) in published_points(&points) {
+            n += tx.execute(
+                "INSERT OR REPLACE INTO isp_traffic VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    domain,
+                    region,
+                    isp,
+                    ts,
+                    points[i],
+                    values.get(i).unwrap_or(&0)
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(n)
+    }
+
+    /// 查询并按粒度聚合，返回 (时间点格式样例, 聚合区间 -> 各列之和)
+    fn aggregate<const N: usize>(
+        &self,
+        sql: &str,
+        params: Vec<String>,
+        freq: Freq,
+    ) -> Result<(String, BTreeMap<NaiveDateTime, [i64; N]>), anyhow::Error> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let mut rows = stmt.query(params_from_iter(params.iter()))?;
+        let mut sample = String::new();
+        let mut result: BTreeMap<NaiveDateTime, [i64; N]> = BTreeMap::new();
+        while let Some(row) = rows.next()? {
+            let ts: String = row.get(0)?;
+            sample = row.get(1)?;
+            let ts = NaiveDateTime::parse_from_str(&ts, TS_FORMAT)?;
+            let entry = result.entry(bucket(ts, freq)).or_insert([0; N]);
+            for (i, v) in entry.iter_mut().enumerate() {
+                *v += row.get::<usize, i64>(i + 2)?;
+            }
+        }
+        Ok((sample, result))
+    }
+
+    pub fn traffic(
+        &self,
+        start_date: &str,
+        end_date: &str,
+        granularity: &str,
+        domain: &str,
+    ) -> Result<ChargeTrafficResponse, anyhow::Error> {
+        let (sample, rows) = self.aggregate::<2>(
+            "SELECT ts, point, china, oversea FROM traffic WHERE domain = ?1 AND substr(ts, 1, 10) BETWEEN ?2 AND ?3 ORDER BY ts",
+            range_params(&[domain.to_string()], start_date, end_date),
+            granularity_freq(granularity),
+        )?;
+        let time = rows.keys().map(|t| format_like(&sample, *t)).collect();
+        let mut data = HashMap::new();
+        data.insert(
+            domain.to_string(),
+            DomainTraffic {
+                china: Some(rows.values().map(|x| x[0]).collect()),
+                oversea: Some(rows.values().map(|x| x[1]).collect()),
+            },
+        );
+        Ok(ChargeTrafficResponse {
+            code: Some(200),
+            error: String::new(),
+            time: Some(time),
+            data: Some(data),
+        })
+    }
+
+    pub fn req_count(
+        &self,
+        freq: Freq,
+        region: &str,
+        start_date: &str,
+        end_date: &str,
+        domains: &[String],
+    ) -> Result<ReqCountResponse, anyhow::Error> {
+        check_region(region)?;
+        let mut params = range_params(domains, start_date, end_date);
+        params.push(region.to_string());
+        let sql = format!(
+            "SELECT ts, point, count FROM req_count WHERE domain IN ({}) AND substr(ts, 1, 10) BETWEEN ? AND ? AND region = ? ORDER BY ts",
+            placeholders(domains.len())
+        );
+        let (sample, rows) = self.aggregate::<1>(&sql, params, freq)?;
+        Ok(ReqCountResponse {
+            code: 200,
+            error: String::new(),
+            data: Some(ReqCountData {
+                points: rows.keys().map(|t| format_like(&sample, *t)).collect(),
+                req_count: rows.values().map(|x| x[0]).collect(),
+            }),
+        })
+    }
+
+    pub fn hit_miss(
+        &self,
+        freq: Freq,
+        start_date: &str,
+        end_date: &str,
+        domains: &[String],
+    ) -> Result<HitMissResponse, anyhow::Error> {
+        let sql = format!(
+            "SELECT ts, point, hit, miss, traffic_hit, traffic_miss FROM hitmiss WHERE domain IN ({}) AND substr(ts, 1, 10) BETWEEN ? AND ? ORDER BY ts",
+            placeholders(domains.len())
+        );
+        let (sample, rows) =
+            self.aggregate::<4>(&sql, range_params(domains, start_date, end_date), freq)?;
+        Ok(HitMissResponse {
+            code: 200,
+            error: String::new(),
+            data: Some(HitMissData {
+                points: rows.keys().map(|t| format_like(&sample, *t)).collect(),
+                hit: rows.values().map(|x| x[0]).collect(),
+                miss: rows.values().map(|x| x[1]).collect(),
+                traffic_hit: rows.values().map(|x| x[2]).collect(),
+                traffic_miss: rows.values().map(|x| x[3]).collect(),
+            }),
+        })
+    }
+
+    pub fn isp_traffic(
+        &self,
+        freq: Freq,
+        regions: &str,
+        isp: &str,
+        start_date: &str,
+        end_date: &str,
+        domains: &[String],
+    ) -> Result<ISPTrafficResponse, anyhow::Error> {
+        check_region(regions)?;
+        let regions: Vec<String> = regions.split(',').map(|x| x.to_string()).collect();
+        let mut params = range_params(domains, start_date, end_date);
+        params.push(isp.to_string());
+        params.extend(regions.clone());
+        let sql = format!(
+            "SELECT ts, point, value FROM isp_traffic WHERE domain IN ({}) AND substr(ts, 1, 10) BETWEEN ? AND ? AND isp = ? AND region IN ({}) ORDER BY ts",
+            placeholders(domains.len()),
+            placeholders(regions.len()),
+        );
+        let (sample, rows) = self.aggregate::<1>(&sql, params, freq)?;
+        Ok(ISPTrafficResponse {
+            code: 200,
+            error: String::new(),
+            data: Some(ISPTrafficData {
+                points: Some(rows.keys().map(|t| format_like(&sample, *t)).collect()),
+                value: Some(rows.values().map(|x| x[0]).collect()),
+            }),
+        })
+    }
+
+    pub fn status_code(
+        &self,
+        freq: Freq,
+        regions: &str,
+        isp: &str,
+        start_date: &str,
+        end_date: &str,
+        domains: &[String],
+    ) -> Result<StatusResponse, anyhow::Error> {
+        check_region(regions)?;
+        let mut params = range_params(domains, start_date, end_date);
+        params.push(regions.to_string());
+        params.push(isp.to_string());
+        let sql = format!(
+            "SELECT ts, point, code, count FROM status_code WHERE domain IN ({}) AND substr(ts, 1, 10) BETWEEN ? AND ? AND region = ? AND isp = ? ORDER BY ts",
+            placeholders(domains.len())
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut rows = stmt.query(params_from_iter(params.iter()))?;
+        let mut sample = String::new();
+        let mut buckets: BTreeMap<NaiveDateTime, HashMap<String, i64>> = BTreeMap::new();
+        while let Some(row) = rows.next()? {
+            let ts: String = row.get(0)?;
+            sample = row.get(1)?;
+            let ts = NaiveDateTime::parse_from_str(&ts, TS_FORMAT)?;
+            let code: String = row.get(2)?;
+            let count: i64 = row.get(3)?;
+            *buckets
+                .entry(bucket(ts, freq))
+                .or_default()
+                .entry(code)
+                .or_insert(0) += count;
+        }
+        let mut codes: HashMap<String, Vec<i64>> = HashMap::new();
+        for v in buckets.values() {
+            for code in v.keys() {
+                codes.entry(code.clone()).or_default();
+            }
+        }
+        for (code, values) in codes.iter_mut() {
+            for v in buckets.values() {
+                values.push(*v.get(code).unwrap_or(&0));
+            }
+        }
+        Ok(StatusResponse {
+            code: 200,
+            error: String::new(),
+            data: Some(StatusData {
+                points: buckets.keys().map(|t| format_like(&sample, *t)).collect(),
+                codes,
+            }),
+        })
+    }
+}
+
+impl Client {
+    /// 离线模式，从本地历史数据查询
+    pub fn offline(&self) -> bool {
+        self.config.offline.unwrap_or(false)
+    }
+
+    /// 打开本地历史数据库
+    pub fn store(&self) -> Result<Store, anyhow::Error> {
+        let path = Store::path(&self.config);
+        if self.offline() && !path.exists() {
+            return Err(anyhow!(
+                "本地历史数据不存在: {}, 请先执行sync",
+                path.display()
+            ));
+        }
+        Store::open(&path)
+    }
+
+    /// 同步单个域名某一天的5分钟数据到本地
+    pub async fn sync_day(
+        &self,
+        store: &mut Store,
+        day: &str,
+        domain: &str,
+    ) -> Result<usize, anyhow::Error> {
+        let config = &self.config;
+        let domains = vec![domain.to_string()];
+        let mut n = 0;
+        let response = Client::new(config, SubFunctionEnum::Traffic)
+            .charge_traffic(day, day, "5min", domain)
+            .await?;
+        check_code("计费流量", response.code.unwrap_or(-1), &response.error)?;
+        n += store.save_traffic(domain, &response)?;
+        let response = Client::new(config, SubFunctionEnum::AnalysisCount)
+            .req_count(Freq::FiveMin, "global", day, day, domains.clone())
+            .await?;
+        check_code("请求次数", response.code, &response.error)?;
+        n += store.save_req_count(domain, "global", &response)?;
+        let response = Client::new(config, SubFunctionEnum::AnalysisStatus)
+            .status_code(Freq::FiveMin, "global", "all", day, day, domains.clone())
+            .await?;
+        check_code("状态码", response.code, &response.error)?;
+        n += store.save_status(domain, "global", "all", &response)?;
+        let response = Client::new(config, SubFunctionEnum::AnalysisHitmiss)
+            .hit_miss(Freq::FiveMin, day, day, domains.clone())
+            .await?;
+        check_code("命中率", response.code, &response.error)?;
+        n += store.save_hitmiss(domain, &response)?;
+        let client = Client::new(config, SubFunctionEnum::AnalysisIsp);
+        for isp in ISP_CODES {
+            let response = client
+                .clone()
+                .isp_traffic(Freq::FiveMin, "global", isp, day, day, domains.clone())
+                .await?;
+            check_code("运营商流量", response.code, &response.error)?;
+            n += store.save_isp_traffic(domain, "global", isp, &response)?;
+        }
+        Ok(n)
+    }
+
+    /// 同步多个域名一段时间的5分钟数据到本地
+    pub async fn sync(
+        &self,
+        start_date: &str,
+        end_date: &str,
+        domains: Vec<String>,
+    ) -> Result<(), anyhow::Error> {
+        let start_dt = NaiveDate::parse_from_str(start_date, "%Y-%m-%d")?;
+        let end_dt = NaiveDate::parse_from_str(end_date, "%Y-%m-%d")?;
+        if start_dt > end_dt {
+            return Err(anyhow!("开始日期不能大于结束日期！"));
+        }
+        let path = Store::path(&self.config);
+        let mut store = Store::open(&path)?;
+        let mut failed = 0;
+        for domain in domains.iter() {
+            let mut day = start_dt;
+            while day <= end_dt {
+                let day_str = day.format("%Y-%m-%d").to_string();
+                match self.sync_day(&mut store, &day_str, domain).await {
+                    Ok(n) => println!(
+                        "域名 {} {} 同步完成 ✅ {}条",
+                        domain.bold().yellow(),
+                        day_str.bold(),
+                        n
+                    ),
+                    Err(e) => {
+                        failed += 1;
+                        print_err(
+                            format!("{} {} 同步失败: {}", domain, day_str, e).as_str(),
+                            false,
+                        );
+                    }
+                }
+                day += Duration::days(1);
+            }
+        }
+        println!("{}{}", "历史数据: ".green().bold(), path.display());
+        if failed > 0 {
+            return Err(anyhow!("{}个域名日期同步失败", failed));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_memory() -> Store {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        Store { conn }
+    }
+
+    #[test]
+    fn store_traffic_test() {
+        let mut store = open_memory();
+        let mut data = HashMap::new();
+        data.insert(
+            "a.com".to_string(),
+            DomainTraffic {
+                china: Some(vec![1, 2, 3]),
+                oversea: Some(vec![10, 20, 30]),
+            },
+        );
+        let response = ChargeTrafficResponse {
+            code: Some(200),
+            error: String::new(),
+            time: Some(vec![
+                "2024-07-16 10:00:00".to_string(),
+                "2024-07-16 10:05:00".to_string(),
+                "2024-07-16 11:00:00".to_string(),
+            ]),
+            data: Some(data),
+        };
+        assert_eq!(store.save_traffic("a.com", &response).unwrap(), 3);
+        // 重复同步不会重复计数
+        store.save_traffic("a.com", &response).unwrap();
+        let result = store
+            .traffic("2024-07-16", "2024-07-16", "hour", "a.com")
+            .unwrap();
+        assert_eq!(
+            result.time.unwrap(),
+            vec!["2024-07-16 10:00:00", "2024-07-16 11:00:00"]
+        );
+        let traffic = result.data.unwrap().get("a.com").unwrap().clone();
+        assert_eq!(traffic.china.unwrap(), vec![3, 3]);
+        assert_eq!(traffic.oversea.unwrap(), vec![30, 30]);
+    }
+
+    #[test]
+    fn store_status_test() {
+        let mut store = open_memory();
+        let mut codes = HashMap::new();
+        codes.insert("200".to_string(), vec![5, 6]);
+        codes.insert("404".to_string(), vec![1, 0]);
+        let response = StatusResponse {
+            code: 200,
+            error: String::new(),
+            data: Some(StatusData {
+                points: vec![
+                    "2024-07-16-10-00-00".to_string(),
+                    "2024-07-17-10-00-00".to_string(),
+                ],
+                codes,
+            }),
+        };
+        store
+            .save_status("a.com", "global", "all", &response)
+            .unwrap();
+        let result = store
+            .status_code(
+                Freq::OneDay,
+                "global",
+                "all",
+                "2024-07-16",
+                "2024-07-17",
+                &["a.com".to_string()],
+            )
+            .unwrap()
+            .data
+            .unwrap();
+        assert_eq!(
+            result.points,
+            vec!["2024-07-16-00-00-00", "2024-07-17-00-00-00"]
+        );
+        assert_eq!(result.codes.get("404").unwrap(), &vec![1, 0]);
+        assert_eq!(result.codes.get("200").unwrap(), &vec![5, 6]);
+        // 只保存了global区域
+        assert!(store
+            .status_code(
+                Freq::OneDay,
+                "beijing",
+                "all",
+                "2024-07-16",
+                "2024-07-17",
+                &["a.com".to_string()],
+            )
+            .is_err());
+        assert!(check_code("状态码", 401, "bad token").is_err());
+    }
+}
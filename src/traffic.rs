@@ -5,20 +5,24 @@
 use std::{collections::HashMap, ops::Div, path::PathBuf};
 
 use chrono::Local;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tokio::{
     fs::{self, File},
     io::AsyncWriteExt,
 };
 
 use crate::{
-    utils::{print_err, qy_robot::QyRobot},
+    utils::{
+        chart::{print_chart, ChartKind, Series},
+        print_err,
+        qy_robot::QyRobot,
+    },
     Client, NOT_FOUND_MSG,
 };
-use colored::Colorize;
+use colored::{Color, Colorize};
 
 /// 计费流量响应
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct ChargeTrafficResponse {
     pub code: Option<i32>,
     pub error: String,
@@ -26,7 +30,7 @@ pub struct ChargeTrafficResponse {
     pub data: Option<HashMap<String, DomainTraffic>>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct DomainTraffic {
     pub china: Option<Vec<i64>>,
     pub oversea: Option<Vec<i64>>,
@@ -41,6 +45,11 @@ impl Client {
         granularity: &str,
         domain: &str,
     ) -> Result<ChargeTrafficResponse, anyhow::Error> {
+        if self.offline() {
+            return self
+                .store()?
+                .traffic(start_date, end_date, granularity, domain);
+        }
         let url = format!("https://{}{}", self.host, "/v2/tune/flux");
         let mut data = HashMap::new();
         data.insert("startDate", start_date);
@@ -206,6 +215,46 @@ impl Client {
         Ok(china_total + oversea_total)
     }
 
+    /// 流量图表, 国内和海外堆叠显示
+    pub fn chart_traffic(
+        &self,
+        response: &ChargeTrafficResponse,
+        kind: ChartKind,
+        start_date: &str,
+        end_date: &str,
+        domain: &str,
+    ) {
+        let data = match response.data.as_ref().and_then(|x| x.get(domain)) {
+            Some(k) => k.clone(),
+            None => {
+                print_err(NOT_FOUND_MSG, false);
+                return;
+            }
+        };
+        println!(
+            "域名 {} {}～{} 流量如下: ",
+            domain.bold().yellow(),
+            start_date.bold(),
+            end_date.bold(),
+        );
+        let mb = |values: Option<Vec<i64>>| {
+            values
+                .unwrap_or_default()
+                .iter()
+                .map(|v| (*v as f64).div(1024.0).div(1024.0))
+                .collect()
+        };
+        print_chart(
+            &response.time.clone().unwrap_or_default(),
+            &[
+                Series::new("China", mb(data.china), Color::Green),
+                Series::new("Oversea", mb(data.oversea), Color::Blue),
+            ],
+            kind,
+            "MB",
+        );
+    }
+
     pub async fn all_domain_charge_traffic(
         &mut self,
         start_date: &str,
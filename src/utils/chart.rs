@@ -0,0 +1,366 @@
+//! 终端图表
+
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use colored::{Color, Colorize};
+use dialoguer::console::Term;
+
+use super::{parse_point_time, qiniu_now};
+
+/// 柱状图中不足一格的部分
+const EIGHTHS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// 图表类型, 柱状图多个序列堆叠显示, 折线图多个序列叠加显示
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChartKind {
+    Bar,
+    Line,
+}
+
+impl FromStr for ChartKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bar" => Ok(Self::Bar),
+            "line" => Ok(Self::Line),
+            _ => Err(anyhow!("图表类型仅支持bar和line: {}", s)),
+        }
+    }
+}
+
+/// 一个数据序列
+#[derive(Debug, Clone)]
+pub struct Series {
+    pub name: String,
+    pub values: Vec<f64>,
+    pub color: Color,
+}
+
+impl Series {
+    pub fn new(name: &str, values: Vec<f64>, color: Color) -> Self {
+        Self {
+            name: name.to_string(),
+            values,
+            color,
+        }
+    }
+}
+
+fn format_value(value: f64, unit: &str) -> String {
+    if unit.is_empty() {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.2}{}", value, unit)
+    }
+}
+
+/// 去掉末尾七牛补0的未来时间点
+fn published_len(points: &[String], series: &[Series]) -> usize {
+    let now = qiniu_now().naive_local();
+    (0..points.len())
+        .rev()
+        .find(|i| {
+            parse_point_time(&points[*i]).is_none_or(|t| t <= now)
+                || series
+                    .iter()
+                    .any(|s| s.values.get(*i).is_some_and(|v| *v != 0.0))
+        })
+        .map(|i| i + 1)
+        .unwrap_or(0)
+}
+
+/// 时间点的简短显示，同一天只显示时分
+fn short_label(point: &str, same_day: bool) -> String {
+    match parse_point_time(point) {
+        Some(t) if same_day => t.format("%H:%M").to_string(),
+        Some(t) => t.format("%m-%d %H:%M").to_string(),
+        None => point.to_string(),
+    }
+}
+
+/// 每一列对应的时间点区间
+fn columns(n: usize, plot_width: usize) -> (Vec<(usize, usize)>, usize) {
+    if n <= plot_width {
+        // 点数较少时加宽每个柱子
+        let col_width = (plot_width / n).clamp(1, 6);
+        ((0..n).map(|i| (i, i + 1)).collect(), col_width)
+    } else {
+        (
+            (0..plot_width)
+                .map(|i| {
+                    (
+                        i * n / plot_width,
+                        ((i + 1) * n / plot_width).max(i * n / plot_width + 1),
+                    )
+                })
+                .collect(),
+            1,
+        )
+    }
+}
+
+/// 生成图表的每一行
+pub fn render(
+    points: &[String],
+    series: &[Series],
+    kind: ChartKind,
+    unit: &str,
+    width: usize,
+    height: usize,
+) -> Vec<String> {
+    let n = points.len();
+    if n == 0 || series.is_empty() {
+        return vec![];
+    }
+    let height = height.max(3);
+    let probe_max = format_value(
+        (0..n)
+            .map(|i| {
+                series
+                    .iter()
+                    .map(|s| s.values.get(i).copied().unwrap_or(0.0).max(0.0))
+                    .sum::<f64>()
+            })
+            .fold(0f64, f64::max),
+        unit,
+    );
+    let label_width = probe_max.len().max(4);
+    let plot_width = width.saturating_sub(label_width + 2).max(10);
+    let (ranges, col_width) = columns(n, plot_width);
+    // 每列取区间内的平均值
+    let cols: Vec<Vec<f64>> = ranges
+        .iter()
+        .map(|(start, end)| {
+            series
+                .iter()
+                .map(|s| {
+                    let values: Vec<f64> = (*start..*end)
+                        .map(|i| s.values.get(i).copied().unwrap_or(0.0).max(0.0))
+                        .collect();
+                    values.iter().sum::<f64>() / values.len() as f64
+                })
+                .collect()
+        })
+        .collect();
+    let mut max = match kind {
+        ChartKind::Bar => cols
+            .iter()
+            .map(|c| c.iter().sum::<f64>())
+            .fold(0f64, f64::max),
+        ChartKind::Line => cols.iter().flatten().copied().fold(0f64, f64::max),
+    };
+    if max <= 0.0 {
+        max = 1.0;
+    }
+
+    // grid[row][col], row 0 为最上面一行
+    let mut grid: Vec<Vec<Option<(char, Color)>>> = vec![vec![None; cols.len()]; height];
+    match kind {
+        ChartKind::Bar => {
+            for (x, values) in cols.iter().enumerate() {
+                let total: f64 = values.iter().sum();
+                for r in 0..height {
+                    let lower = r as f64 / height as f64 * max;
+                    let upper = (r + 1) as f64 / height as f64 * max;
+                    if total <= lower {
+                        break;
+                    }
+                    let mid = (lower + upper) / 2.0;
+                    let mut cumulative = 0.0;
+                    let mut color = series[series.len() - 1].color;
+                    for (i, v) in values.iter().enumerate() {
+                        cumulative += v;
+                        if *v > 0.0 {
+                            color = series[i].color;
+                        }
+                        if cumulative >= mid.min(total) {
+                            break;
+                        }
+                    }
+                    let c = if total >= upper {
+                        '█'
+                    } else {
+                        EIGHTHS[((total - lower) / (upper - lower) * 8.0).round() as usize]
+                    };
+                    if c != ' ' {
+                        grid[height - 1 - r][x] = Some((c, color));
+                    }
+                }
+            }
+        }
+        ChartKind::Line => {
+            let row_of = |v: f64| ((v / max) * (height - 1) as f64).round() as usize;
+            for (i, s) in series.iter().enumerate() {
+                let mut prev: Option<usize> = None;
+                for (x, values) in cols.iter().enumerate() {
+                    let r = row_of(values[i]);
+                    if let Some(p) = prev {
+                        let (lo, hi) = if p < r { (p + 1, r) } else { (r + 1, p) };
+                        for y in lo..hi {
+                            grid[height - 1 - y][x] = Some(('│', s.color));
+                        }
+                    }
+                    grid[height - 1 - r][x] = Some(('•', s.color));
+                    prev = Some(r);
+                }
+            }
+        }
+    }
+
+    let mut lines = vec![];
+    for (r, row) in grid.iter().enumerate() {
+        let label = if r == 0 {
+            format_value(max, unit)
+        } else if r == height / 2 {
+            // 柱状图每行表示区间上限，折线图每行表示一个值
+            let value = match kind {
+                ChartKind::Bar => max * (height - r) as f64 / height as f64,
+                ChartKind::Line => max * (height - 1 - r) as f64 / (height - 1) as f64,
+            };
+            format_value(value, unit)
+        } else {
+            String::new()
+        };
+        let axis = if label.is_empty() { '│' } else { '┤' };
+        let mut line = format!("{:>width$} {}", label, axis, width = label_width);
+        for cell in row {
+            let (c, color) = cell.unwrap_or((' ', Color::White));
+            let cell = c.to_string().repeat(col_width);
+            if c == ' ' {
+                line.push_str(&cell);
+            } else if kind == ChartKind::Line {
+                line.push_str(&format!(
+                    "{}{}",
+                    c.to_string().color(color),
+                    " ".repeat(col_width - 1)
+                ));
+            } else if col_width > 2 {
+                // 柱子之间留空
+                line.push_str(
+                    &format!("{} ", &c.to_string().repeat(col_width - 1))
+                        .color(color)
+                        .to_string(),
+                );
+            } else {
+                line.push_str(&cell.color(color).to_string());
+            }
+        }
+        lines.push(line);
+    }
+    let plot_len = cols.len() * col_width;
+    lines.push(format!(
+        "{:>width$} └{}",
+        format_value(0.0, unit),
+        "─".repeat(plot_len),
+        width = label_width
+    ));
+
+    // x轴标签: 开始、中间、结束
+    let same_day = points.first().map(|x| &x[..10.min(x.len())])
+        == points.last().map(|x| &x[..10.min(x.len())]);
+    let mut axis: Vec<char> = vec![' '; plot_len];
+    let mut last_end = 0;
+    for x in [0, cols.len() / 2, cols.len() - 1] {
+        let label: Vec<char> = short_label(&points[ranges[x].0], same_day)
+            .chars()
+            .collect();
+        let start = (x * col_width).min(plot_len.saturating_sub(label.len()));
+        if start < last_end || start + label.len() > plot_len {
+            continue;
+        }
+        axis[start..start + label.len()].copy_from_slice(&label);
+        last_end = start + label.len() + 1;
+    }
+    lines.push(format!(
+        "{:>width$}  {}",
+        "",
+        axis.iter().collect::<String>().trim_end(),
+        width = label_width
+    ));
+
+    let legend: Vec<String> = series
+        .iter()
+        .map(|s| {
+            format!(
+                "{} {} {}",
+                "■".color(s.color),
+                s.name,
+                format_value(s.values.iter().take(n).sum(), unit).bold()
+            )
+        })
+        .collect();
+    lines.push(format!(
+        "{:>width$}  {}",
+        "",
+        legend.join("   "),
+        width = label_width
+    ));
+    lines
+}
+
+/// 按终端宽度打印图表
+pub fn print_chart(points: &[String], series: &[Series], kind: ChartKind, unit: &str) {
+    let len = published_len(points, series);
+    let series: Vec<Series> = series
+        .iter()
+        .map(|s| Series {
+            values: s.values.iter().take(len).copied().collect(),
+            ..s.clone()
+        })
+        .collect();
+    let (rows, cols) = Term::stdout().size();
+    let height = (rows as usize).saturating_sub(8).clamp(5, 16);
+    for line in render(&points[..len], &series, kind, unit, cols as usize, height) {
+        println!("{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chart_render_test() {
+        colored::control::set_override(false);
+        let points: Vec<String> = (0..4)
+            .map(|i| format!("2024-07-16-{:02}-00-00", i))
+            .collect();
+        let series = vec![
+            Series::new("China", vec![1.0, 2.0, 3.0, 4.0], Color::Green),
+            Series::new("Oversea", vec![0.0, 0.0, 1.0, 4.0], Color::Blue),
+        ];
+        let lines = render(&points, &series, ChartKind::Bar, "MB", 40, 4);
+        // 4行图 + x轴 + 标签 + 图例
+        assert_eq!(lines.len(), 7);
+        assert!(lines[0].starts_with("8.00MB ┤"));
+        assert!(lines[4].starts_with("0.00MB └"));
+        assert!(lines[5].contains("00:00"));
+        assert!(lines[5].contains("03:00"));
+        assert!(lines[6].contains("China 10.00MB"));
+        assert!(lines[6].contains("Oversea 5.00MB"));
+        // 最高的柱子画满
+        assert!(lines[0].contains('█'));
+
+        let lines = render(&points, &series[..1], ChartKind::Line, "", 40, 4);
+        assert!(lines[0].starts_with("   4 ┤"));
+        assert_eq!(lines[0].matches('•').count(), 1);
+        assert!(lines[2].contains('•'));
+    }
+
+    #[test]
+    fn chart_columns_test() {
+        // 288个点压缩到100列
+        let (ranges, col_width) = columns(288, 100);
+        assert_eq!(ranges.len(), 100);
+        assert_eq!(col_width, 1);
+        assert_eq!(ranges[0].0, 0);
+        assert_eq!(ranges[99].1, 288);
+        let (ranges, col_width) = columns(7, 100);
+        assert_eq!(ranges.len(), 7);
+        assert_eq!(col_width, 6);
+        assert_eq!("line".parse::<ChartKind>().unwrap(), ChartKind::Line);
+        assert!("pie".parse::<ChartKind>().is_err());
+    }
+}
@@ -0,0 +1,216 @@
+//! cron表达式
+
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+
+/// 5个字段的cron表达式: 分 时 日 月 周
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // 日和周都有限制时，两者满足其一即可
+    day_restricted: bool,
+    weekday_restricted: bool,
+}
+
+/// `min`到`max`全部置位的掩码
+fn full_mask(min: u32, max: u32) -> u64 {
+    (min..=max).fold(0, |mask, v| mask | (1 << v))
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, anyhow::Error> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((r, s)) => (r, s.parse::<u32>()?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(anyhow!("步长不能为0: {}", part));
+        }
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (a.parse::<u32>()?, b.parse::<u32>()?)
+        } else {
+            let v = range.parse::<u32>()?;
+            // a/n 表示从a开始到最大值
+            if part.contains('/') {
+                (v, max)
+            } else {
+                (v, v)
+            }
+        };
+        if start < min || end > max || start > end {
+            return Err(anyhow!("超出范围[{}-{}]: {}", min, max, part));
+        }
+        let mut v = start;
+        while v <= end {
+            mask |= 1 << v;
+            v += step;
+        }
+    }
+    Ok(mask)
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expr = match s.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(anyhow!("cron表达式需要5个字段: {}", s));
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // 7 和 0 都表示周日
+        if weekdays & (1 << 7) > 0 {
+            weekdays |= 1;
+        }
+        let days = parse_field(fields[2], 1, 31)?;
+        // 同cron, 按解析后的取值判断是否有限制, `*/1`、`1-31`都视为不限制
+        Ok(Schedule {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            day_restricted: days & full_mask(1, 31) != full_mask(1, 31),
+            weekday_restricted: weekdays & full_mask(0, 6) != full_mask(0, 6),
+        })
+    }
+}
+
+impl Schedule {
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) > 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) > 0;
+        match (self.day_restricted, self.weekday_restricted) {
+            (true, true) => day || weekday,
+            (true, false) => day,
+            (false, true) => weekday,
+            (false, false) => true,
+        }
+    }
+
+    /// 严格晚于`after`的下一个执行时间
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let naive = after.naive_local();
+        let mut t: NaiveDateTime =
+            naive.date().and_hms_opt(naive.hour(), naive.minute(), 0)? + Duration::minutes(1);
+        // 最多向后找5年
+        let limit = t + Duration::days(366 * 5);
+        while t < limit {
+            if self.months & (1 << t.month()) == 0 {
+                let (y, m) = if t.month() == 12 {
+                    (t.year() + 1, 1)
+                } else {
+                    (t.year(), t.month() + 1)
+                };
+                t = NaiveDate::from_ymd_opt(y, m, 1)?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !self.day_matches(t.date()) {
+                t = (t.date() + Duration::days(1)).and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if self.hours & (1 << t.hour()) == 0 {
+                t = t.date().and_hms_opt(t.hour(), 0, 0)? + Duration::hours(1);
+                continue;
+            }
+            if self.minutes & (1 << t.minute()) == 0 {
+                t += Duration::minutes(1);
+                continue;
+            }
+            if let Some(dt) = Local.from_local_datetime(&t).earliest() {
+                return Some(dt);
+            }
+            t += Duration::minutes(1);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(s: &str) -> DateTime<Local> {
+        let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+        Local.from_local_datetime(&naive).unwrap()
+    }
+
+    fn next(expr: &str, after: &str) -> String {
+        let schedule: Schedule = expr.parse().unwrap();
+        schedule
+            .next_after(local(after))
+            .unwrap()
+            .format("%Y-%m-%d %H:%M")
+            .to_string()
+    }
+
+    #[test]
+    fn schedule_next_test() {
+        assert_eq!(
+            next("*/5 * * * *", "2024-07-16 10:02:30"),
+            "2024-07-16 10:05"
+        );
+        assert_eq!(
+            next("*/5 * * * *", "2024-07-16 10:05:00"),
+            "2024-07-16 10:10"
+        );
+        assert_eq!(next("0 9 * * *", "2024-07-16 10:00:00"), "2024-07-17 09:00");
+        assert_eq!(
+            next("30 2 1 * *", "2024-12-15 00:00:00"),
+            "2025-01-01 02:30"
+        );
+        // 2024-07-21 是周日
+        assert_eq!(next("0 0 * * 7", "2024-07-16 00:00:00"), "2024-07-21 00:00");
+        assert_eq!(
+            next("15,45 1-3 * * *", "2024-07-16 03:50:00"),
+            "2024-07-17 01:15"
+        );
+        assert_eq!(next("@daily", "2024-07-16 00:00:00"), "2024-07-17 00:00");
+        // 日为全部取值时不限制, 只按周执行; 周为全部取值时只按日执行
+        assert_eq!(
+            next("0 0 */1 * 1", "2024-07-16 00:00:00"),
+            "2024-07-22 00:00"
+        );
+        assert_eq!(
+            next("0 0 1-31 * 1", "2024-07-16 00:00:00"),
+            "2024-07-22 00:00"
+        );
+        assert_eq!(
+            next("0 0 1 * 0-6", "2024-07-16 00:00:00"),
+            "2024-08-01 00:00"
+        );
+        // 日和周都有限制时满足其一即可
+        assert_eq!(
+            next("0 0 20 * 1", "2024-07-16 00:00:00"),
+            "2024-07-20 00:00"
+        );
+    }
+
+    #[test]
+    fn schedule_parse_error_test() {
+        assert!("* * * *".parse::<Schedule>().is_err());
+        assert!("60 * * * *".parse::<Schedule>().is_err());
+        assert!("*/0 * * * *".parse::<Schedule>().is_err());
+        assert!("0 0 31 2 *"
+            .parse::<Schedule>()
+            .unwrap()
+            .next_after(Local::now())
+            .is_none());
+    }
+}
@@ -0,0 +1,326 @@
+//! 离线IP库
+//!
+//! 支持ip2region的xdb及MaxMind的mmdb格式，查询IP的国家、省份及运营商，
+//! 并转换成region_isp中的代码，不发起网络请求
+
+use std::{collections::BTreeMap, fmt::Display, fs, net::IpAddr, path::Path};
+
+use anyhow::anyhow;
+use serde::Deserialize;
+
+use super::print_err;
+use super::region_isp::{
+    get_isp_code_from_name, get_isp_name_from_code, get_region_code_from_name,
+    get_region_name_from_code,
+};
+use crate::config::Config;
+
+/// xdb头部长度
+const XDB_HEADER_LEN: usize = 256;
+/// xdb向量索引的列数及每项长度
+const XDB_VECTOR_COLS: usize = 256;
+const XDB_VECTOR_SIZE: usize = 8;
+/// xdb二分索引每项长度
+const XDB_SEGMENT_SIZE: usize = 14;
+
+/// IP的归属信息
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IpInfo {
+    pub country: String,
+    pub province: String,
+    pub isp: String,
+    /// region_isp中的区域代码
+    pub region_code: &'static str,
+    /// region_isp中的运营商代码
+    pub isp_code: &'static str,
+}
+
+impl IpInfo {
+    fn new(country: &str, province: &str, isp: &str) -> Self {
+        // ip2region中未知的字段为0
+        let clean = |x: &str| {
+            if x == "0" {
+                String::new()
+            } else {
+                x.trim().to_string()
+            }
+        };
+        let (country, province, isp) = (clean(country), clean(province), clean(isp));
+        Self {
+            region_code: get_region_code_from_name(&country, &province),
+            isp_code: get_isp_code_from_name(&country, &isp),
+            country,
+            province,
+            isp,
+        }
+    }
+
+    /// 省份名称, 海外为国家名称
+    pub fn region_name(&self) -> String {
+        match self.region_code {
+            "oversea" | "unknown" | "china" if !self.country.is_empty() => self.country.clone(),
+            code => get_region_name_from_code(code).to_string(),
+        }
+    }
+
+    pub fn isp_name(&self) -> &str {
+        get_isp_name_from_code(self.isp_code)
+    }
+}
+
+impl Display for IpInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let country = if self.country.is_empty() {
+            "未知"
+        } else {
+            self.country.as_str()
+        };
+        let mut s = country.to_string();
+        let region = self.region_name();
+        if region != country {
+            s.push(' ');
+            s.push_str(&region);
+        }
+        s.push(' ');
+        s.push_str(self.isp_name());
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MmdbNames {
+    iso_code: Option<String>,
+    names: Option<BTreeMap<String, String>>,
+}
+
+impl MmdbNames {
+    fn name(&self) -> String {
+        let names = self.names.clone().unwrap_or_default();
+        names
+            .get("zh-CN")
+            .or(names.get("en"))
+            .cloned()
+            .or(self.iso_code.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// GeoIP2/GeoLite2 City及ISP/ASN库中用到的字段
+#[derive(Debug, Deserialize)]
+struct MmdbRecord {
+    country: Option<MmdbNames>,
+    subdivisions: Option<Vec<MmdbNames>>,
+    isp: Option<String>,
+    organization: Option<String>,
+    autonomous_system_organization: Option<String>,
+}
+
+pub enum IpDb {
+    /// ip2region xdb, 只支持IPv4
+    Xdb(Vec<u8>),
+    Mmdb(maxminddb::Reader<Vec<u8>>),
+}
+
+impl IpDb {
+    /// 按扩展名识别格式，`.xdb`为ip2region，其它按mmdb读取
+    pub fn open(path: &Path) -> Result<Self, anyhow::Error> {
+        let is_xdb = path
+            .extension()
+            .is_some_and(|x| x.eq_ignore_ascii_case("xdb"));
+        if is_xdb {
+            let buf =
+                fs::read(path).map_err(|e| anyhow!("读取IP库{}失败: {}", path.display(), e))?;
+            if buf.len() < XDB_HEADER_LEN + XDB_VECTOR_COLS * XDB_VECTOR_COLS * XDB_VECTOR_SIZE {
+                return Err(anyhow!("无效的xdb文件: {}", path.display()));
+            }
+            return Ok(IpDb::Xdb(buf));
+        }
+        let reader = maxminddb::Reader::open_readfile(path)
+            .map_err(|e| anyhow!("读取IP库{}失败: {}", path.display(), e))?;
+        Ok(IpDb::Mmdb(reader))
+    }
+
+    /// 读取`[ipdb]`配置的IP库，没有配置返回None
+    pub fn from_config(config: &Config) -> Result<Option<Self>, anyhow::Error> {
+        match config.ipdb.as_ref().and_then(|x| x.path.as_ref()) {
+            Some(path) => Ok(Some(Self::open(Path::new(path))?)),
+            None => Ok(None),
+        }
+    }
+
+    /// 同`from_config`, 读取失败时输出错误并返回None, 不影响查询
+    pub fn from_config_or_warn(config: &Config) -> Option<Self> {
+        Self::from_config(config).unwrap_or_else(|e| {
+            print_err(e.to_string().as_str(), false);
+            None
+        })
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Option<IpInfo> {
+        match self {
+            IpDb::Xdb(buf) => {
+                let ip = match ip {
+                    IpAddr::V4(v4) => u32::from(v4),
+                    IpAddr::V6(v6) => u32::from(v6.to_ipv4_mapped()?),
+                };
+                let region = xdb_search(buf, ip)?;
+                // 国家|区域|省份|城市|ISP
+                let fields: Vec<&str> = region.split('|').collect();
+                Some(IpInfo::new(
+                    fields.first().copied().unwrap_or_default(),
+                    fields.get(2).copied().unwrap_or_default(),
+                    fields.get(4).copied().unwrap_or_default(),
+                ))
+            }
+            IpDb::Mmdb(reader) => {
+                let record: MmdbRecord = reader.lookup(ip).ok()?;
+                let isp = record
+                    .isp
+                    .or(record.organization)
+                    .or(record.autonomous_system_organization)
+                    .unwrap_or_default();
+                Some(IpInfo::new(
+                    &record.country.map(|x| x.name()).unwrap_or_default(),
+                    &record
+                        .subdivisions
+                        .and_then(|x| x.into_iter().next())
+                        .map(|x| x.name())
+                        .unwrap_or_default(),
+                    &isp,
+                ))
+            }
+        }
+    }
+
+    /// 查询字符串形式的IP, 无效的IP或没有查到返回None
+    pub fn lookup_str(&self, ip: &str) -> Option<IpInfo> {
+        self.lookup(ip.trim().parse().ok()?)
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        buf.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// 先用IP的前两个字节定位向量索引，再在二分索引中查找
+fn xdb_search(buf: &[u8], ip: u32) -> Option<String> {
+    let (il0, il1) = ((ip >> 24) as usize, ((ip >> 16) & 0xff) as usize);
+    let offset = XDB_HEADER_LEN + (il0 * XDB_VECTOR_COLS + il1) * XDB_VECTOR_SIZE;
+    let start = read_u32(buf, offset)? as usize;
+    let end = read_u32(buf, offset + 4)? as usize;
+    if end < start {
+        return None;
+    }
+    let (mut low, mut high) = (0i64, ((end - start) / XDB_SEGMENT_SIZE) as i64);
+    while low <= high {
+        let mid = (low + high) / 2;
+        let p = start + mid as usize * XDB_SEGMENT_SIZE;
+        if ip < read_u32(buf, p)? {
+            high = mid - 1;
+        } else if ip > read_u32(buf, p + 4)? {
+            low = mid + 1;
+        } else {
+            let len = u16::from_le_bytes(buf.get(p + 8..p + 10)?.try_into().ok()?) as usize;
+            let ptr = read_u32(buf, p + 10)? as usize;
+            return Some(String::from_utf8_lossy(buf.get(ptr..ptr + len)?).to_string());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    /// 按xdb格式生成只包含给定区间的库
+    fn build_xdb(segments: &[(Ipv4Addr, Ipv4Addr, &str)]) -> Vec<u8> {
+        let index_start = XDB_HEADER_LEN + XDB_VECTOR_COLS * XDB_VECTOR_COLS * XDB_VECTOR_SIZE;
+        let mut buf = vec![0u8; index_start];
+        let mut data: Vec<u8> = vec![];
+        let mut index = vec![];
+        let data_start = index_start + segments.len() * XDB_SEGMENT_SIZE;
+        for (start, end, region) in segments {
+            index.extend(u32::from(*start).to_le_bytes());
+            index.extend(u32::from(*end).to_le_bytes());
+            index.extend((region.len() as u16).to_le_bytes());
+            index.extend(((data_start + data.len()) as u32).to_le_bytes());
+            data.extend(region.as_bytes());
+        }
+        // 所有向量索引都指向整个二分索引
+        let last = (index_start + (segments.len() - 1) * XDB_SEGMENT_SIZE) as u32;
+        for i in 0..XDB_VECTOR_COLS * XDB_VECTOR_COLS {
+            let p = XDB_HEADER_LEN + i * XDB_VECTOR_SIZE;
+            buf[p..p + 4].copy_from_slice(&(index_start as u32).to_le_bytes());
+            buf[p + 4..p + 8].copy_from_slice(&last.to_le_bytes());
+        }
+        buf.extend(index);
+        buf.extend(data);
+        buf
+    }
+
+    #[test]
+    fn ipdb_test() {
+        let db = IpDb::Xdb(build_xdb(&[
+            (
+                Ipv4Addr::new(1, 0, 0, 0),
+                Ipv4Addr::new(1, 0, 0, 255),
+                "中国|0|广东省|深圳市|电信",
+            ),
+            (
+                Ipv4Addr::new(1, 0, 1, 0),
+                Ipv4Addr::new(1, 0, 1, 255),
+                "中国|0|内蒙古|呼和浩特市|中国移动",
+            ),
+            (
+                Ipv4Addr::new(8, 8, 8, 0),
+                Ipv4Addr::new(8, 8, 8, 255),
+                "美国|0|0|0|Level3",
+            ),
+            (
+                Ipv4Addr::new(9, 0, 0, 0),
+                Ipv4Addr::new(9, 0, 0, 255),
+                "中国|0|香港|0|0",
+            ),
+        ]));
+        let info = db.lookup_str("1.0.0.8").unwrap();
+        assert_eq!((info.region_code, info.isp_code), ("guangdong", "telecom"));
+        assert_eq!(info.to_string(), "中国 广东 电信");
+        let info = db.lookup_str("1.0.1.1").unwrap();
+        assert_eq!((info.region_code, info.isp_code), ("neimenggu", "mobile"));
+        let info = db.lookup_str("8.8.8.8").unwrap();
+        assert_eq!((info.region_code, info.isp_code), ("oversea", "others"));
+        assert_eq!(info.region_name(), "美国");
+        assert_eq!(db.lookup_str("9.0.0.1").unwrap().region_code, "hongkong");
+        assert_eq!(
+            db.lookup_str("::ffff:1.0.0.1").unwrap().region_code,
+            "guangdong"
+        );
+        assert!(db.lookup_str("1.0.2.1").is_none());
+        assert!(db.lookup_str("2001:db8::1").is_none());
+        assert!(db.lookup_str("abc").is_none());
+        assert_eq!(
+            get_region_code_from_name("China", "Inner Mongolia"),
+            "neimenggu"
+        );
+        assert_eq!(get_region_code_from_name("China", "Guangxi"), "guangxi");
+        assert_eq!(get_region_code_from_name("Japan", "Tokyo"), "oversea");
+        assert_eq!(
+            get_isp_code_from_name("China", "China Unicom Beijing"),
+            "unicom"
+        );
+        assert_eq!(
+            get_isp_code_from_name("Hong Kong", "China Mobile Hong Kong"),
+            "mobile"
+        );
+        // 国外运营商
+        assert_eq!(get_isp_code_from_name("美国", "T-Mobile US"), "others");
+        assert_eq!(get_isp_code_from_name("Italy", "Telecom Italia"), "others");
+        assert_eq!(get_isp_code_from_name("台湾", "Chunghwa Telecom"), "others");
+        assert_eq!(get_isp_code_from_name("Japan", "CNC"), "others");
+        assert_eq!(get_isp_code_from_name("", "电信"), "others");
+    }
+}
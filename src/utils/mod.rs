@@ -1,21 +1,32 @@
 //! utils
+pub mod chart;
+pub mod cron;
+pub mod ipdb;
 pub mod qy_robot;
 pub mod region_isp;
 pub mod token;
 use std::process::exit;
 use std::{
+    collections::hash_map::RandomState,
+    env, fs,
+    hash::{BuildHasher, Hasher},
     io::{self, Write},
+    path::{Path, PathBuf},
     time::Duration,
 };
 
+use chrono::{DateTime, FixedOffset, NaiveDateTime, Utc};
 use colored::Colorize;
 use dialoguer::{console::Style, theme::ColorfulTheme, Confirm};
+use serde::Serialize;
 use tokio::{
     sync::oneshot::{self, Sender},
     task::JoinHandle,
     time::sleep,
 };
 
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
 use crate::{config::Config, Client};
 
 pub fn print_err<T: Colorize>(msg: T, exit_process: bool) {
@@ -63,6 +74,72 @@ pub fn max_length<T: IntoIterator<Item: Into<String>> + Clone>(data: &T, limit:
     length
 }
 
+/// 按显示宽度左对齐, 中文占两列, 用于终端表格
+pub fn pad(s: &str, width: usize) -> String {
+    format!("{}{}", s, " ".repeat(width.saturating_sub(s.width_cjk())))
+}
+
+/// 按显示宽度截断, 超出部分以`...`结尾
+pub fn truncate(s: &str, width: usize) -> String {
+    if s.width_cjk() <= width {
+        return s.to_string();
+    }
+    let mut result = String::new();
+    for c in s.chars() {
+        if result.width_cjk() + c.width_cjk().unwrap_or(0) + 3 > width {
+            break;
+        }
+        result.push(c);
+    }
+    result.push_str("...");
+    result
+}
+
+/// 七牛返回的时间点为东八区时间
+pub const QINIU_OFFSET: i32 = 8 * 3600;
+
+/// 七牛时区(东八区)的当前时间, 与接口返回的时间点比较时使用, 不依赖本机时区
+pub fn qiniu_now() -> DateTime<FixedOffset> {
+    Utc::now().with_timezone(&FixedOffset::east_opt(QINIU_OFFSET).unwrap())
+}
+
+/// 解析七牛接口返回的时间点, 计费流量为`2024-07-16 10:05:00`, 日志分析为`2024-07-16-10-05-00`
+pub fn parse_point_time(point: &str) -> Option<NaiveDateTime> {
+    for fmt in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d-%H-%M-%S", "%Y-%m-%d-%H-%M"] {
+        if let Ok(t) = NaiveDateTime::parse_from_str(point, fmt) {
+            return Some(t);
+        }
+    }
+    chrono::NaiveDate::parse_from_str(point, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+}
+
+/// 数据目录, 优先使用`$XDG_DATA_HOME`, 默认`$HOME/.local/share/qiniu-cdn-manager`
+pub fn data_dir() -> PathBuf {
+    match env::var("XDG_DATA_HOME") {
+        Ok(k) if !k.is_empty() => PathBuf::from(k),
+        _ => PathBuf::from(env::var("HOME").unwrap_or(".".to_string())).join(".local/share"),
+    }
+    .join("qiniu-cdn-manager")
+}
+
+/// 先写临时文件再重命名, 中断时不会损坏原文件
+pub fn write_json_atomic<T: Serialize>(path: &Path, value: &T) -> Result<(), anyhow::Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+    fs::write(&tmp, serde_json::to_string_pretty(value)?)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// 随机数，用于任务抖动等不需要密码学安全的场景
+pub fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
 #[derive(Debug)]
 pub struct WaitBlinker {
     pub sender: Sender<bool>,
@@ -123,3 +200,20 @@ pub async fn get_domains(
     }
     Ok(res)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pad_test() {
+        assert_eq!(pad("ab", 4), "ab  ");
+        assert_eq!(pad("广东", 6), "广东  ");
+        assert_eq!(pad("广东省", 4), "广东省");
+        // 零宽字符不会导致溢出
+        assert_eq!(pad("a\u{200b}", 2), "a\u{200b} ");
+        assert_eq!(truncate("abc", 3), "abc");
+        assert_eq!(truncate("abcdefgh", 6), "abc...");
+        assert_eq!(truncate("中文中文", 6), "中...");
+    }
+}
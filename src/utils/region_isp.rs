@@ -118,3 +118,89 @@ pub fn get_isp_name_from_code(isp_code: &str) -> &str {
     }
     "其他"
 }
+
+/// 根据IP库中的国家及省份名称获取region code, 支持中文及英文名称
+pub fn get_region_code_from_name(country: &str, province: &str) -> &'static str {
+    let province = province
+        .trim()
+        .trim_end_matches("特别行政区")
+        .trim_end_matches("壮族自治区")
+        .trim_end_matches("回族自治区")
+        .trim_end_matches("维吾尔自治区")
+        .trim_end_matches("自治区")
+        .trim_end_matches("省")
+        .trim_end_matches("市");
+    let english = match province.to_lowercase().replace([' ', '-'], "").as_str() {
+        "innermongolia" => "neimenggu".to_string(),
+        "tibet" => "xizang".to_string(),
+        "macao" => "macau".to_string(),
+        "hongkong" => "hongkong".to_string(),
+        x => x.to_string(),
+    };
+    // 港澳台只出现在国家字段
+    let country_code = match country.trim() {
+        "香港" | "Hong Kong" | "HK" => Some("hongkong"),
+        "澳门" | "Macau" | "Macao" | "MO" => Some("macau"),
+        "台湾" | "Taiwan" | "TW" => Some("taiwan"),
+        _ => None,
+    };
+    if let Some(code) = country_code {
+        return code;
+    }
+    for (i, r) in REGION_NAME_LIST.iter().enumerate().skip(3) {
+        if !province.is_empty() && (*r == province || REGION_CODE_LIST[i] == english) {
+            return REGION_CODE_LIST[i];
+        }
+    }
+    match country.trim() {
+        "" | "0" => "unknown",
+        "中国" | "China" | "CN" => "china",
+        _ => "oversea",
+    }
+}
+
+/// 国家是否为中国(包括港澳台)
+fn is_china(country: &str) -> bool {
+    matches!(
+        country.trim(),
+        "中国"
+            | "China"
+            | "CN"
+            | "香港"
+            | "Hong Kong"
+            | "HK"
+            | "澳门"
+            | "Macau"
+            | "Macao"
+            | "MO"
+            | "台湾"
+            | "Taiwan"
+            | "TW"
+    )
+}
+
+/// 根据IP库中的国家及运营商名称获取运营商代码, 国外运营商为others
+pub fn get_isp_code_from_name(country: &str, isp: &str) -> &'static str {
+    if !is_china(country) {
+        return "others";
+    }
+    let isp = isp.to_lowercase();
+    // 英文名称带上china, 避免匹配到中华电信(Chunghwa Telecom)等
+    let rules: [(&[&str], &str); 6] = [
+        (&["电信", "china telecom", "chinanet"], "telecom"),
+        (
+            &["联通", "china unicom", "china netcom", "cncgroup"],
+            "unicom",
+        ),
+        (&["移动", "china mobile", "cmnet"], "mobile"),
+        (&["鹏博士", "dr.peng", "drpeng"], "drpeng"),
+        (&["铁通", "tietong", "china railcom"], "tietong"),
+        (&["教育网", "cernet"], "cernet"),
+    ];
+    for (names, code) in rules {
+        if names.iter().any(|x| isp.contains(x)) {
+            return code;
+        }
+    }
+    "others"
+}